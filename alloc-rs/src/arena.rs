@@ -0,0 +1,327 @@
+use core::alloc::Layout;
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem::{needs_drop, size_of};
+use core::ops::{Deref, DerefMut};
+use core::ptr::NonNull;
+
+use crate::bump::{drop_n, BumpAllocator};
+
+// growable containers over a borrowed BumpAllocator, for code that has no global allocator.
+// core only, so they work on bare metal. the borrow keeps the arena from being reset or dropped
+// under them. a vec that is still the arena's newest allocation grows in place, otherwise it
+// moves to a fresh block and the old one is wasted until the arena resets, so build one
+// container at a time or reserve up front. dropping a container runs its elements' destructors
+// but gives no memory back, that only happens on reset.
+
+pub struct ArenaVec<'a, T> {
+    arena: &'a BumpAllocator,
+    ptr: NonNull<T>,
+    len: usize,
+    cap: usize,
+    _marker: PhantomData<T>
+}
+
+impl<'a, T> ArenaVec<'a, T> {
+    pub fn new_in(arena: &'a BumpAllocator) -> Self {
+        // zero sized values never need memory
+        let cap = if size_of::<T>() == 0 { usize::MAX } else { 0 };
+        Self { arena, ptr: NonNull::dangling(), len: 0, cap, _marker: PhantomData }
+    }
+
+    pub fn with_capacity_in(cap: usize, arena: &'a BumpAllocator) -> Option<Self> {
+        let mut v = Self::new_in(arena);
+        v.reserve(cap).then_some(v)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    // room for additional more elements, false (and nothing changed) when the arena is full
+    pub fn reserve(&mut self, additional: usize) -> bool {
+        let Some(needed) = self.len.checked_add(additional) else {
+            return false;
+        };
+        if needed <= self.cap {
+            return true;
+        }
+        // doubling first, then just what was asked for so the last bytes of the arena get used too
+        let doubled = needed.max(self.cap * 2).max(4);
+        self.grow_to(doubled) || (doubled > needed && self.grow_to(needed))
+    }
+
+    fn grow_to(&mut self, new_cap: usize) -> bool {
+        let Ok(layout) = Layout::array::<T>(new_cap) else {
+            return false;
+        };
+        if self.cap > 0 && self.arena.grow_in_place(self.ptr.cast(), self.cap * size_of::<T>(), layout.size()) {
+            self.cap = new_cap;
+            return true;
+        }
+        let Some(new) = self.arena.alloc(layout) else {
+            return false;
+        };
+        let new = new.cast::<T>();
+        unsafe { core::ptr::copy_nonoverlapping(self.ptr.as_ptr(), new.as_ptr(), self.len) };
+        self.ptr = new;
+        self.cap = new_cap;
+        true
+    }
+
+    // hands the value back when the arena is full
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        if self.len == self.cap && !self.reserve(1) {
+            return Err(value);
+        }
+        unsafe { self.ptr.as_ptr().add(self.len).write(value) };
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        Some(unsafe { self.ptr.as_ptr().add(self.len).read() })
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+        let tail = core::ptr::slice_from_raw_parts_mut(unsafe { self.ptr.as_ptr().add(len) }, self.len - len);
+        // shrink first, a panicking destructor then leaks the rest instead of dropping twice
+        self.len = len;
+        unsafe { core::ptr::drop_in_place(tail) };
+    }
+
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        unsafe { core::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        unsafe { core::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+
+    // all or nothing
+    pub fn extend_from_slice(&mut self, values: &[T]) -> bool
+    where
+        T: Clone
+    {
+        if !self.reserve(values.len()) {
+            return false;
+        }
+        for value in values {
+            // can't fail, the room is reserved
+            let _ = self.push(value.clone());
+        }
+        true
+    }
+
+    // turns the vec into a slice that lives as long as the arena, the elements' destructors
+    // then run on the arena's reset like alloc_iter's, so the same 'static + Send applies.
+    // None, with the elements dropped, when the arena has no room left to track them
+    pub fn into_slice(self) -> Option<&'a mut [T]>
+    where
+        T: 'static + Send
+    {
+        let mut this = core::mem::ManuallyDrop::new(self);
+        if needs_drop::<T>() && !this.arena.push_drop(this.ptr.as_ptr() as usize, this.len, drop_n::<T>) {
+            this.clear();
+            return None;
+        }
+        Some(unsafe { core::slice::from_raw_parts_mut(this.ptr.as_ptr(), this.len) })
+    }
+
+    // the elements stay in the arena and are never dropped, for values that borrow
+    pub fn leak(self) -> &'a mut [T] {
+        let this = core::mem::ManuallyDrop::new(self);
+        unsafe { core::slice::from_raw_parts_mut(this.ptr.as_ptr(), this.len) }
+    }
+}
+
+impl<T> Drop for ArenaVec<'_, T> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl<T> Deref for ArenaVec<'_, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        self.as_slice()
+    }
+}
+
+impl<T> DerefMut for ArenaVec<'_, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.as_mut_slice()
+    }
+}
+
+impl<'v, T> IntoIterator for &'v ArenaVec<'_, T> {
+    type Item = &'v T;
+    type IntoIter = core::slice::Iter<'v, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.as_slice().iter()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for ArenaVec<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+pub struct ArenaString<'a> {
+    bytes: ArenaVec<'a, u8>
+}
+
+impl<'a> ArenaString<'a> {
+    pub fn new_in(arena: &'a BumpAllocator) -> Self {
+        Self { bytes: ArenaVec::new_in(arena) }
+    }
+
+    pub fn from_str_in(s: &str, arena: &'a BumpAllocator) -> Option<Self> {
+        let mut string = Self::new_in(arena);
+        string.push_str(s).then_some(string)
+    }
+
+    // false, and nothing appended, when the arena is full
+    pub fn push_str(&mut self, s: &str) -> bool {
+        self.bytes.extend_from_slice(s.as_bytes())
+    }
+
+    pub fn push(&mut self, c: char) -> bool {
+        self.push_str(c.encode_utf8(&mut [0; 4]))
+    }
+
+    pub fn as_str(&self) -> &str {
+        // only ever appended whole strs
+        unsafe { core::str::from_utf8_unchecked(self.bytes.as_slice()) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.bytes.capacity()
+    }
+
+    pub fn reserve(&mut self, additional: usize) -> bool {
+        self.bytes.reserve(additional)
+    }
+
+    pub fn clear(&mut self) {
+        self.bytes.clear();
+    }
+
+    // bytes need no drop, so nothing is queued on the arena
+    pub fn into_str(self) -> &'a mut str {
+        let bytes = self.bytes.leak();
+        unsafe { core::str::from_utf8_unchecked_mut(bytes) }
+    }
+}
+
+impl Deref for ArenaString<'_> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+// write!() into an arena, fails once the arena is full
+impl fmt::Write for ArenaString<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.push_str(s) { Ok(()) } else { Err(fmt::Error) }
+    }
+}
+
+impl fmt::Display for ArenaString<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for ArenaString<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+// a single value that is dropped with the box, unlike alloc_value's which wait for the reset
+pub struct ArenaBox<'a, T> {
+    ptr: NonNull<T>,
+    _marker: PhantomData<(&'a BumpAllocator, T)>
+}
+
+impl<'a, T> ArenaBox<'a, T> {
+    // hands the value back when the arena is full
+    pub fn new_in(value: T, arena: &'a BumpAllocator) -> Result<Self, T> {
+        let Some(ptr) = arena.alloc(Layout::new::<T>()) else {
+            return Err(value);
+        };
+        let ptr = ptr.cast::<T>();
+        unsafe { ptr.as_ptr().write(value) };
+        Ok(Self { ptr, _marker: PhantomData })
+    }
+
+    pub fn into_inner(self) -> T {
+        let this = core::mem::ManuallyDrop::new(self);
+        unsafe { this.ptr.as_ptr().read() }
+    }
+
+    // the value stays in the arena and is never dropped
+    pub fn leak(self) -> &'a mut T {
+        let this = core::mem::ManuallyDrop::new(self);
+        unsafe { &mut *this.ptr.as_ptr() }
+    }
+}
+
+impl<T> Drop for ArenaBox<'_, T> {
+    fn drop(&mut self) {
+        unsafe { core::ptr::drop_in_place(self.ptr.as_ptr()) };
+    }
+}
+
+impl<T> Deref for ArenaBox<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T> DerefMut for ArenaBox<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for ArenaBox<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
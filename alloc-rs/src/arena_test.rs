@@ -0,0 +1,104 @@
+use core::fmt::Write;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::arena::{ArenaBox, ArenaString, ArenaVec};
+use crate::bump::BumpAllocator;
+use crate::test_helpers::CountsDrops;
+
+#[test]
+pub fn arena_collections_test() {
+    static mut HEAP: [u8; 4096] = [0u8; 4096];
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+    let drops = || DROPS.load(Ordering::Relaxed);
+
+    let heap_addr = &raw mut HEAP as *mut u8 as usize;
+    let mut arena = unsafe { BumpAllocator::from_raw_parts(heap_addr, 4096) };
+    {
+        // the newest allocation grows without moving
+        let mut ports = ArenaVec::new_in(&arena);
+        ports.push(80u16).unwrap();
+        let first = ports.as_ptr();
+        for port in 1..100u16 {
+            ports.push(port).unwrap();
+        }
+        assert_eq!(ports.as_ptr(), first);
+        assert_eq!(ports.len(), 100);
+        assert_eq!(ports[0], 80);
+        assert_eq!(ports.iter().map(|&p| p as usize).sum::<usize>(), 80 + 99 * 100 / 2);
+        assert_eq!(arena.free_bytes(), 4096 - ports.capacity() * 2);
+
+        // once something else follows it, it has to move
+        let mut name = ArenaString::from_str_in("eth", &arena).unwrap();
+        let full_cap = ports.capacity();
+        // truncating past the length leaves the vec alone
+        ports.truncate(full_cap);
+        assert_eq!((ports.len(), ports.capacity()), (100, full_cap));
+        while ports.len() < full_cap {
+            ports.push(0).unwrap();
+        }
+        ports.push(443).unwrap();
+        assert_ne!(ports.as_ptr(), first);
+        assert_eq!((ports[0], ports[ports.len() - 1]), (80, 443));
+        assert_eq!(ports.pop(), Some(443));
+
+        write!(name, "{}", 0).unwrap();
+        name.push(':');
+        assert_eq!(name.as_str(), "eth0:");
+        assert_eq!(format!("{name}"), "eth0:");
+
+        // destructors run with the container, not at the reset
+        let mut owned = ArenaVec::with_capacity_in(4, &arena).unwrap();
+        for _ in 0..6 {
+            assert!(owned.push(CountsDrops(&DROPS)).is_ok());
+        }
+        owned.truncate(4);
+        assert_eq!(drops(), 2);
+        drop(owned);
+        assert_eq!(drops(), 6);
+
+        let mut boxed = ArenaBox::new_in([0u8; 16], &arena).unwrap();
+        boxed[3] = 7;
+        assert_eq!(boxed.into_inner()[3], 7);
+        let Ok(counted) = ArenaBox::new_in(CountsDrops(&DROPS), &arena) else {
+            panic!("should box");
+        };
+        drop(counted);
+        assert_eq!(drops(), 7);
+
+        // handed over to the arena, dropped on reset
+        let mut kept = ArenaVec::new_in(&arena);
+        assert!(kept.push(CountsDrops(&DROPS)).is_ok());
+        assert_eq!(kept.into_slice().unwrap().len(), 1);
+        let label = ArenaString::from_str_in("arp", &arena).unwrap().into_str();
+        assert_eq!(label, "arp");
+        // borrowed values can only be leaked, their destructors would run after the borrow
+        let mut words = ArenaVec::new_in(&arena);
+        assert!(words.push(&label[1..]).is_ok());
+        assert_eq!(words.leak(), &["rp"]);
+
+        // a full arena refuses and gives the value back
+        let mut big = ArenaVec::new_in(&arena);
+        assert!(!big.reserve(4096));
+        assert!(big.is_empty());
+        let mut filled = 0usize;
+        while big.push(filled as u64).is_ok() {
+            filled += 1;
+        }
+        assert_eq!(big.push(1).unwrap_err(), 1);
+        assert_eq!(big.len(), filled);
+        assert!(ArenaBox::new_in([0u64; 64], &arena).is_err());
+        assert!(!name.push_str("overflow".repeat(100).as_str()));
+        assert_eq!(name.as_str(), "eth0:");
+    }
+    assert_eq!(drops(), 7);
+    arena.reset();
+    assert_eq!(drops(), 8);
+    assert_eq!(arena.free_bytes(), 4096);
+
+    // zero sized values take no memory
+    let mut units = ArenaVec::new_in(&arena);
+    for _ in 0..1000 {
+        units.push(()).unwrap();
+    }
+    assert_eq!((units.len(), arena.free_bytes()), (1000, 4096));
+}
@@ -1,6 +1,7 @@
 use core::alloc::Layout;
 use core::ptr::NonNull;
 use core::sync::atomic::{AtomicUsize,Ordering};
+use core::mem::needs_drop;
 
 // this is plain old bump allocator which contains only a start pointer and a tail pointer , allocs memory in contiguous blocks which is power of two, 
 // does not reclaim free memory, to keep it simple only foward moving pointer is implemented,
@@ -10,17 +11,43 @@ use core::sync::atomic::{AtomicUsize,Ordering};
 pub struct BumpAllocator {
     heap_start: usize,
     heap_end: usize,
-    next: AtomicUsize
+    next: AtomicUsize,
+    drop_head: AtomicUsize
+}
+
+// typed values that need Drop get a node carved from the arena itself, so the drop list needs
+// no extra memory and works on no_std. nodes form an atomic lifo stack like the slab free list,
+// reset()/drop walks it newest first, so values are destroyed in reverse allocation order.
+// values are dropped on whichever thread resets or drops the arena, and maybe long after
+// anything they borrowed is gone, the arena has no lifetime to tie them to. so the typed api
+// only takes 'static + Send values, plain data that borrows goes through alloc_slice_copy.
+#[repr(C)]
+struct DropNode {
+    next: usize,
+    ptr: usize,
+    len: usize,
+    drop_fn: unsafe fn(usize, usize)
+}
+
+pub(crate) unsafe fn drop_n<T>(ptr: usize, len: usize) {
+    unsafe { core::ptr::drop_in_place(core::ptr::slice_from_raw_parts_mut(ptr as *mut T, len)) };
 }
 
 
 impl BumpAllocator {
-    pub fn new(heap_start: usize, heap_size : usize) -> Self {
+    /// an arena over heap_size bytes at heap_start. the typed api writes values there and reset()
+    /// reads them back to drop them.
+    ///
+    /// # Safety
+    /// the region has to be valid for reads and writes and used by nothing but this allocator
+    /// for as long as it or anything allocated from it is around.
+    pub unsafe fn from_raw_parts(heap_start: usize, heap_size : usize) -> Self {
         let heap_end = heap_start + heap_size;
         Self {
             heap_start,
             heap_end,
-            next: AtomicUsize::new(heap_start)
+            next: AtomicUsize::new(heap_start),
+            drop_head: AtomicUsize::new(0)
         }
     }
     // all the allocs live the lifetime of the allocator, non_aliased for any other purpose.
@@ -44,11 +71,107 @@ impl BumpAllocator {
         }
 
     }
-    // reset is not for concurrent alloc, it is not thread safe.
-    pub fn reset(&self) { 
+    // extends the allocation at ptr from old_size to new_size bytes, which only works while it is
+    // the newest one: next has to still sit right at its end. the arena collections grow this way
+    pub fn grow_in_place(&self, ptr: NonNull<u8>, old_size: usize, new_size: usize) -> bool {
+        let start = ptr.as_ptr() as usize;
+        let new_end = match start.checked_add(new_size) {
+            Some(end) if end <= self.heap_end => end,
+            _ => return false,
+        };
+        self.next.compare_exchange(start + old_size, new_end, Ordering::SeqCst, Ordering::Relaxed).is_ok()
+    }
+
+    // reset runs the destructors of every tracked value and rewinds next to the start.
+    // it takes &mut self so no reference handed out by the typed api can outlive it.
+    pub fn reset(&mut self) { 
+        self.run_drops();
         self.next.store(self.heap_start, Ordering::Relaxed);
     }
 
+    // typed arena api, references live as long as the borrow of the arena.
+    // reset() or dropping the arena destroys whatever needs Drop, Copy data is simply forgotten.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_value<T: 'static + Send>(&self, value: T) -> Option<&mut T> {
+        let ptr = self.alloc(Layout::new::<T>())?.as_ptr() as *mut T;
+        if needs_drop::<T>() && !self.push_drop(ptr as usize, 1, drop_n::<T>) {
+            return None;
+        }
+        unsafe {
+            ptr.write(value);
+            Some(&mut *ptr)
+        }
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_slice_copy<T: Copy>(&self, src: &[T]) -> Option<&mut [T]> {
+        let layout = Layout::array::<T>(src.len()).ok()?;
+        let ptr = self.alloc(layout)?.as_ptr() as *mut T;
+        unsafe {
+            core::ptr::copy_nonoverlapping(src.as_ptr(), ptr, src.len());
+            Some(core::slice::from_raw_parts_mut(ptr, src.len()))
+        }
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_str(&self, src: &str) -> Option<&mut str> {
+        let bytes = self.alloc_slice_copy(src.as_bytes())?;
+        Some(unsafe { core::str::from_utf8_unchecked_mut(bytes) })
+    }
+
+    // the iterator has to report its exact length up front since there is no way to grow in place
+    // once something else is allocated after it. a short iterator yields a shorter slice.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_iter<T, I>(&self, iter: I) -> Option<&mut [T]>
+    where
+        T: 'static + Send,
+        I: IntoIterator<Item = T>,
+        I::IntoIter: ExactSizeIterator
+    {
+        let iter = iter.into_iter();
+        let cap = iter.len();
+        let layout = Layout::array::<T>(cap).ok()?;
+        let ptr = self.alloc(layout)?.as_ptr() as *mut T;
+        let mut len = 0;
+        for value in iter.take(cap) {
+            unsafe { ptr.add(len).write(value) };
+            len += 1;
+        }
+        if needs_drop::<T>() && !self.push_drop(ptr as usize, len, drop_n::<T>) {
+            unsafe { drop_n::<T>(ptr as usize, len) };
+            return None;
+        }
+        Some(unsafe { core::slice::from_raw_parts_mut(ptr, len) })
+    }
+
+    // drop_fn runs at the reset, on whatever thread does it. callers keep ptr's type 'static + Send
+    pub(crate) fn push_drop(&self, ptr: usize, len: usize, drop_fn: unsafe fn(usize, usize)) -> bool {
+        let node = match self.alloc(Layout::new::<DropNode>()) {
+            Some(p) => p.as_ptr() as *mut DropNode,
+            None => return false,
+        };
+        let mut head = self.drop_head.load(Ordering::Acquire);
+        loop {
+            unsafe { node.write(DropNode { next: head, ptr, len, drop_fn }) };
+            match self.drop_head.compare_exchange(head, node as usize, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return true,
+                Err(current) => {
+                    head = current;
+                    core::hint::spin_loop();
+                }
+            }
+        }
+    }
+
+    fn run_drops(&mut self) {
+        let mut head = self.drop_head.swap(0, Ordering::AcqRel);
+        while head != 0 {
+            let node = unsafe { (head as *const DropNode).read() };
+            unsafe { (node.drop_fn)(node.ptr, node.len) };
+            head = node.next;
+        }
+    }
+
     pub fn free_bytes(&self)-> usize  { 
         let current = self.next.load(Ordering::Relaxed);
         if current >= self.heap_end {
@@ -59,6 +182,12 @@ impl BumpAllocator {
     }
 }
 
+impl Drop for BumpAllocator {
+    fn drop(&mut self) {
+        self.run_drops();
+    }
+}
+
 pub fn align_up(addr: usize, align: usize) ->  usize {
     if align == 0 {
         addr
@@ -11,7 +11,7 @@ pub fn simple_alloc_and_test() {
     let heap_addr = unsafe { &raw mut TEST_HEAP as *mut _ as usize};
     let heap_size = core::mem::size_of::<[u8;1024]>();
     println!("heap addr : {heap_addr} and heap_size : {heap_size}");
-    let allocator = BumpAllocator::new(heap_addr, heap_size);
+    let mut allocator = unsafe { BumpAllocator::from_raw_parts(heap_addr, heap_size) };
     let l1 = Layout::from_size_align(16, 8).unwrap();
     println!("l1: {l1:?}");
     let ptr1 = allocator.alloc(l1).expect("expected to alloc l1");
@@ -33,7 +33,7 @@ pub fn simple_alloc_and_test() {
 pub fn alloc_alignment_test() {
     let heap_addr = unsafe { &raw mut TEST_HEAP as *mut _ as usize};
     let heap_size = 1024 as usize;
-    let allocator = BumpAllocator::new(heap_addr, heap_size);
+    let allocator = unsafe { BumpAllocator::from_raw_parts(heap_addr, heap_size) };
     let l1 = Layout::from_size_align(1, 1).unwrap();
     let ptr = allocator.alloc(l1).expect("expected to succeed");
     assert!((ptr.as_ptr() as usize) % 1 == 0, "alignment is mismatched");
@@ -44,7 +44,7 @@ use crate::global_bump::GLOBAL_BUMP_ALLOCATOR as GLOBAL;
 //#[test]
 pub fn simple_global_alloc() {
     println!("runing test");
-    GLOBAL.reset();
+    unsafe { GLOBAL.reset() };
     println!("reset");
 
     let b = Box::new(42u64);
@@ -56,4 +56,38 @@ pub fn simple_global_alloc() {
     }
     let free_bytes = GLOBAL.free_bytes();
     println!("after box alloc free bytes are {free_bytes}");
-}
\ No newline at end of file
+}
+
+#[test]
+pub fn typed_arena_test() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use crate::test_helpers::CountsDrops;
+
+    static mut ARENA_HEAP: [u8; 1024] = [0u8; 1024];
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+    let drops = || DROPS.load(Ordering::Relaxed);
+
+    let heap_addr = unsafe { &raw mut ARENA_HEAP as *mut u8 as usize};
+    let mut arena = unsafe { BumpAllocator::from_raw_parts(heap_addr, 1024) };
+
+    let v = arena.alloc_value(7u64).expect("should alloc value");
+    *v += 1;
+    assert_eq!(*v, 8);
+    let s = arena.alloc_str("arp").expect("should alloc str");
+    assert_eq!(s, "arp");
+    let copied = arena.alloc_slice_copy(&[1u16, 2, 3]).expect("should alloc slice");
+    assert_eq!(copied, &[1, 2, 3]);
+    let squares = arena.alloc_iter((0..4u32).map(|i| i * i)).expect("should alloc iter");
+    assert_eq!(squares, &[0, 1, 4, 9]);
+
+    arena.alloc_value(CountsDrops(&DROPS)).expect("should alloc droppable");
+    arena.alloc_iter((0..3).map(|_| CountsDrops(&DROPS))).expect("should alloc droppables");
+    assert_eq!(drops(), 0);
+    arena.reset();
+    assert_eq!(drops(), 4, "reset should run every destructor");
+    assert_eq!(arena.free_bytes(), 1024);
+
+    arena.alloc_value(CountsDrops(&DROPS)).expect("should alloc after reset");
+    drop(arena);
+    assert_eq!(drops(), 5, "dropping the arena should run pending destructors");
+}
@@ -0,0 +1,320 @@
+use core::alloc::{GlobalAlloc, Layout};
+
+use crate::composite::{CompositeAllocator, Tier};
+
+// the C allocation api on top of a CompositeAllocator, so real C programs can run on it:
+//     cargo build --release --features cabi
+//     LD_PRELOAD=target/release/liballoc_rs.so ls -lR /usr
+// C frees without a size, so free() asks the heap which tier a pointer came from. slab blocks
+// are recognised by the heap's page owner table and their size is the class size. bigger requests
+// carry a two word header right below the pointer, [prefix back to the start of the heap
+// allocation, requested size], which is what free, realloc and malloc_usable_size go by.
+// their mappings go back to the kernel, bump memory is never reclaimed. so the preload heap
+// keeps everything up to the largest slab class in the slabs and maps everything above it,
+// only what the mapping table can't hold ends up in the bump tier.
+// pointers the heap doesn't know (memory from before the preload, or from glibc's memalign
+// and friends which aren't exported) are ignored by free.
+// the c_* functions take the heap explicitly, the exported symbols only exist with the cabi
+// feature so the test binaries keep their libc malloc.
+
+// what malloc guarantees, alignof(max_align_t) on x86_64
+const MALLOC_ALIGN: usize = 16;
+const HEADER: usize = 2 * core::mem::size_of::<usize>();
+
+const EINVAL: i32 = 22;
+const ENOMEM: i32 = 12;
+
+unsafe extern "C" {
+    fn __errno_location() -> *mut i32;
+}
+
+fn set_errno(errno: i32) {
+    unsafe { *__errno_location() = errno };
+}
+
+// where a block's size comes from
+enum Block {
+    Slab { block_size: usize },
+    // base is the heap allocation the header points back to, mapped the length of its
+    // mapping when it has one
+    Headered { base: *mut u8, prefix: usize, size: usize, mapped: Option<usize> },
+    Foreign
+}
+
+/// # Safety
+/// heap must be used through the c_* functions only, C and Rust layouts don't mix.
+pub unsafe fn c_malloc(heap: &CompositeAllocator, size: usize) -> *mut u8 {
+    unsafe { c_alloc(heap, size, MALLOC_ALIGN, false) }
+}
+
+/// # Safety
+/// as c_malloc.
+pub unsafe fn c_calloc(heap: &CompositeAllocator, count: usize, size: usize) -> *mut u8 {
+    match count.checked_mul(size) {
+        Some(total) => unsafe { c_alloc(heap, total, MALLOC_ALIGN, true) },
+        None => {
+            set_errno(ENOMEM);
+            core::ptr::null_mut()
+        },
+    }
+}
+
+/// # Safety
+/// ptr is null or a live pointer from the c_* functions on this heap.
+pub unsafe fn c_free(heap: &CompositeAllocator, ptr: *mut u8) {
+    match unsafe { block_of(heap, ptr) } {
+        Block::Slab { block_size } => unsafe { heap.dealloc(ptr, slab_layout(block_size)) },
+        Block::Headered { base, mapped: Some(len), .. } => unsafe {
+            heap.dealloc(base, Layout::from_size_align_unchecked(len, MALLOC_ALIGN))
+        },
+        Block::Headered { mapped: None, .. } | Block::Foreign => {},
+    }
+}
+
+/// # Safety
+/// ptr is null or a live pointer from the c_* functions on this heap.
+pub unsafe fn c_realloc(heap: &CompositeAllocator, ptr: *mut u8, size: usize) -> *mut u8 {
+    if ptr.is_null() {
+        return unsafe { c_malloc(heap, size) };
+    }
+    if size == 0 {
+        unsafe { c_free(heap, ptr) };
+        return core::ptr::null_mut();
+    }
+    let block = unsafe { block_of(heap, ptr) };
+    let usable = match block {
+        Block::Slab { block_size } => block_size,
+        Block::Headered { size, .. } => size,
+        // not ours, there is no telling how much to copy
+        Block::Foreign => {
+            set_errno(ENOMEM);
+            return core::ptr::null_mut();
+        },
+    };
+    if size <= usable {
+        return ptr;
+    }
+    // a plain malloc block that stays headered keeps its prefix, the heap's realloc moves the
+    // whole allocation (mremap between mappings), header included
+    let stays_headered = Layout::from_size_align(size, MALLOC_ALIGN).is_ok_and(|layout| heap.slab_for(layout).is_none());
+    if let Block::Headered { base, prefix: HEADER, size: old_size, .. } = block && stays_headered {
+        let old = unsafe { Layout::from_size_align_unchecked(old_size + HEADER, MALLOC_ALIGN) };
+        let moved = match size.checked_add(HEADER) {
+            Some(outer) => unsafe { heap.realloc(base, old, outer) },
+            None => core::ptr::null_mut(),
+        };
+        if moved.is_null() {
+            set_errno(ENOMEM);
+            return moved;
+        }
+        let p = unsafe { moved.add(HEADER) };
+        unsafe { write_header(p, HEADER, size) };
+        return p;
+    }
+    let p = unsafe { c_malloc(heap, size) };
+    if !p.is_null() {
+        unsafe {
+            core::ptr::copy_nonoverlapping(ptr, p, usable);
+            c_free(heap, ptr);
+        }
+    }
+    p
+}
+
+/// # Safety
+/// memptr is valid for a write.
+pub unsafe fn c_posix_memalign(heap: &CompositeAllocator, memptr: *mut *mut u8, align: usize, size: usize) -> i32 {
+    if !align.is_power_of_two() || !align.is_multiple_of(core::mem::size_of::<usize>()) {
+        return EINVAL;
+    }
+    let p = unsafe { c_alloc(heap, size, align.max(MALLOC_ALIGN), false) };
+    if p.is_null() {
+        return ENOMEM;
+    }
+    unsafe { memptr.write(p) };
+    0
+}
+
+/// # Safety
+/// as c_malloc.
+pub unsafe fn c_aligned_alloc(heap: &CompositeAllocator, align: usize, size: usize) -> *mut u8 {
+    if !align.is_power_of_two() {
+        set_errno(EINVAL);
+        return core::ptr::null_mut();
+    }
+    unsafe { c_alloc(heap, size, align.max(MALLOC_ALIGN), false) }
+}
+
+/// 0 for null and for pointers the heap doesn't know.
+///
+/// # Safety
+/// ptr is null or a live pointer from the c_* functions on this heap.
+pub unsafe fn c_usable_size(heap: &CompositeAllocator, ptr: *mut u8) -> usize {
+    match unsafe { block_of(heap, ptr) } {
+        Block::Slab { block_size } => block_size,
+        Block::Headered { size, .. } => size,
+        Block::Foreign => 0,
+    }
+}
+
+// slab classes serve small requests as they are, anything bigger gets the header. the outer
+// layout is bigger with the same alignment, so it can't land in a slab class either
+unsafe fn c_alloc(heap: &CompositeAllocator, size: usize, align: usize, zeroed: bool) -> *mut u8 {
+    let alloc = |layout: Layout| unsafe { if zeroed { heap.alloc_zeroed(layout) } else { heap.alloc(layout) } };
+    let p = match Layout::from_size_align(size.max(1), align) {
+        Ok(layout) if heap.slab_for(layout).is_some() => alloc(layout),
+        Ok(layout) => {
+            let prefix = HEADER.next_multiple_of(align);
+            match size.checked_add(prefix).and_then(|outer| Layout::from_size_align(outer, align).ok()) {
+                Some(outer) => {
+                    let base = alloc(outer);
+                    if base.is_null() {
+                        base
+                    } else {
+                        let p = unsafe { base.add(prefix) };
+                        unsafe { write_header(p, prefix, layout.size()) };
+                        p
+                    }
+                },
+                None => core::ptr::null_mut(),
+            }
+        },
+        Err(_) => core::ptr::null_mut(),
+    };
+    if p.is_null() {
+        set_errno(ENOMEM);
+    }
+    p
+}
+
+// the tier lookup. a pointer that isn't a slab block is only taken for headered when its
+// header leads back into the heap, or to the start of one of its mappings
+unsafe fn block_of(heap: &CompositeAllocator, ptr: *mut u8) -> Block {
+    if ptr.is_null() || !(ptr as usize).is_multiple_of(MALLOC_ALIGN) {
+        return Block::Foreign;
+    }
+    if let Tier::Slab { block_size } = heap.tier_of(ptr) {
+        return Block::Slab { block_size };
+    }
+    let (prefix, size) = unsafe {
+        let header = (ptr as *const usize).sub(2);
+        (header.read(), header.add(1).read())
+    };
+    if prefix < HEADER || prefix > ptr as usize {
+        return Block::Foreign;
+    }
+    let base = unsafe { ptr.sub(prefix) };
+    match heap.tier_of(base) {
+        Tier::Bump | Tier::Reserve => Block::Headered { base, prefix, size, mapped: None },
+        Tier::Mmap { len } if size.checked_add(prefix).is_some_and(|outer| outer <= len) => {
+            Block::Headered { base, prefix, size, mapped: Some(len) }
+        },
+        _ => Block::Foreign,
+    }
+}
+
+unsafe fn write_header(p: *mut u8, prefix: usize, size: usize) {
+    unsafe {
+        let header = (p as *mut usize).sub(2);
+        header.write(prefix);
+        header.add(1).write(size);
+    }
+}
+
+fn slab_layout(block_size: usize) -> Layout {
+    // the class size is what slab_for picked, a layout of that size routes back to the class
+    unsafe { Layout::from_size_align_unchecked(block_size, core::mem::size_of::<usize>()) }
+}
+
+// the heap behind the exported symbols. it can't be a static array (rustc would have to build
+// a gigabyte at compile time), so it is mapped and the allocator built on first use
+#[cfg(feature = "cabi")]
+mod preload {
+    use core::cell::UnsafeCell;
+    use core::ffi::c_void;
+    use core::mem::MaybeUninit;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    // address space only, pages are touched as the heap gets to them
+    const PRELOAD_HEAP_BYTES: usize = 16 << 30;
+    const PRELOAD_MAX_CLASS: usize = 16 * 1024;
+
+    const UNINIT: usize = 0;
+    const BUILDING: usize = 1;
+    const READY: usize = 2;
+    const FAILED: usize = 3;
+
+    struct PreloadHeap {
+        state: AtomicUsize,
+        heap: UnsafeCell<MaybeUninit<CompositeAllocator>>
+    }
+
+    unsafe impl Sync for PreloadHeap {}
+
+    static PRELOAD: PreloadHeap = PreloadHeap { state: AtomicUsize::new(UNINIT), heap: UnsafeCell::new(MaybeUninit::uninit()) };
+
+    fn heap() -> Option<&'static CompositeAllocator> {
+        loop {
+            match PRELOAD.state.compare_exchange(UNINIT, BUILDING, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => {
+                    let region = crate::mmap_tier::map_region(PRELOAD_HEAP_BYTES);
+                    if region.is_null() {
+                        PRELOAD.state.store(FAILED, Ordering::Release);
+                        return None;
+                    }
+                    let heap = unsafe { CompositeAllocator::builder().heap(region as *mut [u8; PRELOAD_HEAP_BYTES]) }
+                        // a fresh anonymous mapping reads as zero
+                        .zeroed_heap(true)
+                        .slab_region_bytes(4 * 1024 * 1024)
+                        .power_of_two_classes(PRELOAD_MAX_CLASS)
+                        .mmap_threshold(Some(PRELOAD_MAX_CLASS))
+                        .build();
+                    unsafe { (*PRELOAD.heap.get()).write(heap) };
+                    PRELOAD.state.store(READY, Ordering::Release);
+                },
+                Err(READY) => return Some(unsafe { (*PRELOAD.heap.get()).assume_init_ref() }),
+                Err(FAILED) => return None,
+                Err(_) => core::hint::spin_loop(),
+            }
+        }
+    }
+
+    #[unsafe(no_mangle)]
+    pub unsafe extern "C" fn malloc(size: usize) -> *mut c_void {
+        heap().map_or(core::ptr::null_mut(), |heap| unsafe { c_malloc(heap, size) } as *mut c_void)
+    }
+
+    #[unsafe(no_mangle)]
+    pub unsafe extern "C" fn calloc(count: usize, size: usize) -> *mut c_void {
+        heap().map_or(core::ptr::null_mut(), |heap| unsafe { c_calloc(heap, count, size) } as *mut c_void)
+    }
+
+    #[unsafe(no_mangle)]
+    pub unsafe extern "C" fn free(ptr: *mut c_void) {
+        if let Some(heap) = heap() {
+            unsafe { c_free(heap, ptr as *mut u8) };
+        }
+    }
+
+    #[unsafe(no_mangle)]
+    pub unsafe extern "C" fn realloc(ptr: *mut c_void, size: usize) -> *mut c_void {
+        heap().map_or(core::ptr::null_mut(), |heap| unsafe { c_realloc(heap, ptr as *mut u8, size) } as *mut c_void)
+    }
+
+    #[unsafe(no_mangle)]
+    pub unsafe extern "C" fn posix_memalign(memptr: *mut *mut c_void, align: usize, size: usize) -> i32 {
+        heap().map_or(ENOMEM, |heap| unsafe { c_posix_memalign(heap, memptr as *mut *mut u8, align, size) })
+    }
+
+    #[unsafe(no_mangle)]
+    pub unsafe extern "C" fn aligned_alloc(align: usize, size: usize) -> *mut c_void {
+        heap().map_or(core::ptr::null_mut(), |heap| unsafe { c_aligned_alloc(heap, align, size) } as *mut c_void)
+    }
+
+    #[unsafe(no_mangle)]
+    pub unsafe extern "C" fn malloc_usable_size(ptr: *mut c_void) -> usize {
+        heap().map_or(0, |heap| unsafe { c_usable_size(heap, ptr as *mut u8) })
+    }
+}
@@ -0,0 +1,110 @@
+use crate::cabi::{c_aligned_alloc, c_calloc, c_free, c_malloc, c_posix_memalign, c_realloc, c_usable_size};
+use crate::composite::{CompositeAllocator, FallbackPolicy};
+
+#[test]
+pub fn c_abi_test() {
+    static mut HEAP: [u8; 64 * 1024] = [0u8; 64 * 1024];
+    static HEAP_C: CompositeAllocator = unsafe { CompositeAllocator::builder().heap(&raw mut HEAP) }
+        .slab_region_bytes(16 * 1024)
+        .power_of_two_classes(1024)
+        .fallback(FallbackPolicy::Fail)
+        .mmap_threshold(Some(16 * 1024))
+        .build();
+    let heap = &HEAP_C;
+    let heap_start = &raw mut HEAP as usize;
+    let in_heap = |p: *mut u8| (p as usize) >= heap_start && (p as usize) < heap_start + 64 * 1024;
+
+    unsafe {
+        // small requests are plain slab blocks, the size comes from the class
+        let small = c_malloc(heap, 10);
+        let medium = c_malloc(heap, 100);
+        assert!(in_heap(small) && small as usize % 16 == 0);
+        assert_eq!((c_usable_size(heap, small), c_usable_size(heap, medium)), (16, 128));
+        // bigger ones carry their size in a header, from the bump tier or a mapping
+        let bump = c_malloc(heap, 3000);
+        let mapped = c_malloc(heap, 40_000);
+        assert!(in_heap(bump) && !in_heap(mapped));
+        assert_eq!((c_usable_size(heap, bump), c_usable_size(heap, mapped)), (3000, 40_000));
+        assert_eq!(heap.mmap.mappings(), 1);
+
+        // realloc within the usable size stays put, growing copies or remaps
+        assert_eq!(c_realloc(heap, medium, 120), medium);
+        medium.write_bytes(7, 128);
+        let medium = c_realloc(heap, medium, 600);
+        assert_eq!((medium.read(), medium.add(127).read(), c_usable_size(heap, medium)), (7, 7, 1024));
+        bump.write_bytes(8, 3000);
+        let bump = c_realloc(heap, bump, 5000);
+        assert_eq!((bump.read(), bump.add(2999).read(), c_usable_size(heap, bump)), (8, 8, 5000));
+        mapped.write_bytes(9, 40_000);
+        let mapped = c_realloc(heap, mapped, 200_000);
+        assert_eq!((mapped.read(), mapped.add(39_999).read(), c_usable_size(heap, mapped)), (9, 9, 200_000));
+        assert_eq!(heap.mmap.mappings(), 1);
+
+        // frees without a size find their tier
+        c_free(heap, mapped);
+        assert_eq!(heap.mmap.mappings(), 0);
+        small.write_bytes(0xff, 16);
+        c_free(heap, small);
+        c_free(heap, bump);
+        c_free(heap, medium);
+        c_free(heap, core::ptr::null_mut());
+
+        // calloc clears the recycled block, and checks the multiplication
+        let zeroed = c_calloc(heap, 4, 4);
+        assert_eq!(zeroed, small);
+        assert!((0..16).all(|i| zeroed.add(i).read() == 0));
+        assert!(c_calloc(heap, usize::MAX / 2, 3).is_null());
+        assert!(c_realloc(heap, zeroed, 0).is_null());
+
+        // alignment requests
+        let mut p = core::ptr::null_mut();
+        assert_eq!(c_posix_memalign(heap, &mut p, 64, 100), 0);
+        assert_eq!(p as usize % 64, 0);
+        let mut q = core::ptr::null_mut();
+        assert_eq!(c_posix_memalign(heap, &mut q, 4096, 100), 0);
+        assert_eq!((q as usize % 4096, c_usable_size(heap, q)), (0, 100));
+        assert_eq!(c_posix_memalign(heap, &mut q, 12, 100), 22);
+        let r = c_aligned_alloc(heap, 1 << 16, 20_000);
+        assert_eq!((r as usize % (1 << 16), c_usable_size(heap, r)), (0, 20_000));
+        assert_eq!(heap.mmap.mappings(), 1);
+        c_free(heap, r);
+        assert_eq!(heap.mmap.mappings(), 0);
+        assert!(c_aligned_alloc(heap, 3, 16).is_null());
+        c_free(heap, p);
+        c_free(heap, q);
+
+        // memory the heap doesn't know is left alone
+        #[repr(align(16))]
+        struct Foreign([u8; 64]);
+        let mut foreign = Foreign([0u8; 64]);
+        let inside = foreign.0.as_mut_ptr().add(32);
+        assert_eq!(c_usable_size(heap, inside), 0);
+        c_free(heap, inside);
+        assert!(c_realloc(heap, inside, 10).is_null());
+    }
+}
+
+#[test]
+pub fn c_abi_reclaims_mid_size_test() {
+    static mut HEAP: [u8; 1024 * 1024] = [0u8; 1024 * 1024];
+    // shaped like the preload heap: slab classes up to 16 KiB, mappings above
+    static HEAP_C: CompositeAllocator = unsafe { CompositeAllocator::builder().heap(&raw mut HEAP) }
+        .slab_region_bytes(16 * 1024)
+        .power_of_two_classes(16 * 1024)
+        .mmap_threshold(Some(16 * 1024))
+        .build();
+    let heap = &HEAP_C;
+
+    // batch after batch of mid-size blocks, freed in between, reuse the same pages
+    let mut bump_free = None;
+    for round in 0..4 {
+        let blocks: Vec<*mut u8> = (0..60).map(|i| unsafe { c_malloc(heap, 1000 + i * 200) }).collect();
+        assert!(blocks.iter().all(|p| !p.is_null()), "round {round} ran out of memory");
+        assert!(blocks.iter().enumerate().all(|(i, &p)| unsafe { c_usable_size(heap, p) } >= 1000 + i * 200));
+        for p in blocks {
+            unsafe { c_free(heap, p) };
+        }
+        let free = heap.bump_allocator.free_bytes();
+        assert_eq!(*bump_free.get_or_insert(free), free, "round {round} took more of the bump tier");
+    }
+}
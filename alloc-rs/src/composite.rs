@@ -1,19 +1,88 @@
-use core::{alloc::GlobalAlloc, cell::UnsafeCell, mem::MaybeUninit, ptr::NonNull, sync::atomic::{AtomicUsize, Ordering}};
+use core::{alloc::{GlobalAlloc, Layout}, mem::MaybeUninit, ptr::NonNull, sync::atomic::AtomicU8};
 use std::rc::Rc;
 
-use crate::{bump::align_up, global_bump::GlobalBumpAllocator, slab::{Slab, StrippedLayout}};
+use crate::sync::{AtomicUsize, Ordering};
+#[cfg(all(feature = "std", target_os = "linux", not(loom)))]
+use crate::mmap_tier::{MmapTier, MMAP_PAGE_SIZE};
+use crate::oom::{OomHandler, OomHooks, OomStats};
+use crate::{bump::align_up, global_bump::{GlobalBumpAllocator, THREAD_CHUNK_BYTES}, slab::{Slab, StrippedLayout, MAX_PAGE_SIZE, SLAB_PAGE_SIZE}, tagging::{self, Tag, TagStats, TagTable}};
 
 
 pub struct CompositeAllocator { 
     pub inited: AtomicUsize,
     pub bump_allocator: GlobalBumpAllocator,
-    pub slab_allocator : UnsafeCell<MaybeUninit<Slab>>,
+    pub config: CompositeConfig,
+    // one slab per entry of the size-class table, the ones past config.class_count stay unused
+    pub slabs: [Slab; SLAB_CLASSES],
     pub slab_block_size : usize,
+    // pages the slabs released, lifo stack threaded through the first word of each page
+    pub free_pages: AtomicUsize,
+    pub free_page_count: AtomicUsize,
+    // bytes of bump tier blocks handed out and not freed yet, thread chunk blocks included.
+    // the bump itself only knows how far it got, which charges a whole chunk at once
+    pub bump_live: AtomicUsize,
+    pub tags: TagTable,
+    pub oom: OomHooks,
+    #[cfg(all(feature = "std", target_os = "linux", not(loom)))]
+    pub mmap: MmapTier,
+}
+
+// what a small request does once its size class can't get another page
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FallbackPolicy {
+    // fail the allocation
+    Fail,
+    // fall back to a plain bump allocation, never recycled
+    Bump
+}
 
+// which tier a pointer came from, see tier_of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tier {
+    // its own mapping of len bytes
+    Mmap { len: usize },
+    Slab { block_size: usize },
+    // the bump tier doesn't remember sizes, and doesn't take memory back either
+    Bump,
+    Reserve,
+    // not from this allocator, or not the start of a mapping
+    Foreign
+}
+
+// const fn builder for the heap shape, so a #[global_allocator] static can pick its own.
+// everything is checked in build(), which runs at compile time when used in a static initialiser:
+//
+//     static mut HEAP: [u8; 256 * 1024] = [0u8; 256 * 1024];
+//     #[global_allocator]
+//     static ALLOC: CompositeAllocator = unsafe { CompositeAllocator::builder().heap(&raw mut HEAP) }
+//         .zeroed_heap(true)
+//         .slab_region_bytes(64 * 1024)
+//         .size_classes(&[16, 32, 48, 64, 128])
+//         .fallback(FallbackPolicy::Bump)
+//         .accounting(true)
+//         .build();
+#[derive(Debug, Clone, Copy)]
+pub struct CompositeConfig {
+    pub heap_start: *mut u8,
+    pub heap_size: usize,
+    pub slab_region_bytes: usize,
+    pub size_classes: [usize; SLAB_CLASSES],
+    pub class_count: usize,
+    pub fallback: FallbackPolicy,
+    pub accounting: bool,
+    // requests of at least this many bytes get their own mapping (std, linux), None keeps
+    // everything in the heap
+    pub mmap_threshold: Option<usize>,
+    // the heap starts out all zero (a static like GLOBAL_HEAP), alloc_zeroed relies on it for
+    // memory nobody got before. only the default heap has it, any other has to opt in
+    pub zeroed_heap: bool,
+    // bytes at the end of the heap held back until the first out of memory, 0 for none
+    pub emergency_reserve: usize
 }
 
 const HEAP_SIZE : usize = 1024 *1024;
-const SLOTS : [AtomicUsize;10] = {
+const SLOTS : [core::sync::atomic::AtomicUsize;10] = {
+    use core::sync::atomic::AtomicUsize;
     const fn make_arr() -> [AtomicUsize; 10] {
         let mut arr: [MaybeUninit<AtomicUsize>; 10] = unsafe {
             MaybeUninit::<[MaybeUninit<AtomicUsize>; 10]>::uninit().assume_init()
@@ -30,17 +99,221 @@ const SLOTS : [AtomicUsize;10] = {
 
 static mut GLOBAL_HEAP: [u8; HEAP_SIZE] = [0u8; HEAP_SIZE];
 const SLAB_REGION_BYTES: usize = 128 * 1024;
+pub const DEFAULT_MMAP_THRESHOLD: usize = 128 * 1024;
 const SLAB_WORD_ALIGN: usize = core::mem::size_of::<usize>();
-impl  CompositeAllocator { 
-    pub const fn new_const(slab_block_size : usize) -> Self { 
-        Self{ 
+// once the initial slab region is used up the slab grows one page at a time, first from the
+// pages it released earlier and then out of the bump tier
+const SLAB_PAGE_BYTES: usize = SLAB_PAGE_SIZE;
+// completely empty pages the slab keeps around before handing them back to the page pool
+const SLAB_RESERVE_PAGES: usize = 4;
+// the page owner table at the start of the heap has one byte per SLAB_PAGE_BYTES page: 0 for
+// bump memory and pool pages, class + 1 for a page carved into that class's blocks. dealloc and
+// tier_of go by it rather than by whatever the page's first word says, which for bump memory
+// is caller data
+const NOT_SLAB: u8 = 0;
+// mark of a pool page that was never handed to a slab
+const FRESH_PAGE: usize = 0xF8E5_4A6E;
+// size classes 8, 16, .., 16384. a class is naturally aligned, so a request is served by the
+// smallest class covering both its size and its alignment
+const SLAB_MIN_CLASS: usize = SLAB_WORD_ALIGN;
+pub const SLAB_CLASSES: usize = 12;
+// classes past a quarter of a pool page get bigger pages of their own, carved straight out of
+// the bump tier. the pool only holds SLAB_PAGE_BYTES pages, so those classes keep their empty
+// pages instead of releasing them: their memory follows their peak, not every byte ever asked for
+const MAX_SLAB_CLASS: usize = MAX_PAGE_SIZE / 4;
+
+// the bump tier's per thread chunks, a 256th of the heap and at most THREAD_CHUNK_BYTES, so the
+// part a thread leaves behind when it exits stays small next to the heap
+const fn thread_chunk_bytes(heap_size: usize) -> usize {
+    let chunk = heap_size / 256;
+    if chunk == 0 {
+        0
+    } else if chunk >= THREAD_CHUNK_BYTES {
+        THREAD_CHUNK_BYTES
+    } else {
+        1 << chunk.ilog2()
+    }
+}
+
+const fn slab_page_size(block_size: usize) -> usize {
+    if block_size <= SLAB_PAGE_BYTES / 4 {
+        SLAB_PAGE_BYTES
+    } else {
+        let page = (block_size * 16).next_power_of_two();
+        if page > MAX_PAGE_SIZE { MAX_PAGE_SIZE } else { page }
+    }
+}
+
+loom_const_fn! {
+const fn make_slabs(config: &CompositeConfig) -> [Slab; SLAB_CLASSES] {
+    let mut arr: [MaybeUninit<Slab>; SLAB_CLASSES] = [const { MaybeUninit::uninit() }; SLAB_CLASSES];
+    let mut i = 0;
+    while i < SLAB_CLASSES {
+        let block_size = if i < config.class_count { config.size_classes[i] } else { SLAB_MIN_CLASS };
+        let page_size = slab_page_size(block_size);
+        let slab = Slab::new_rounded(block_size).with_page_size(page_size);
+        arr[i] = MaybeUninit::new(if page_size == SLAB_PAGE_BYTES { slab.with_reserve(SLAB_RESERVE_PAGES) } else { slab });
+        i += 1;
+    }
+    unsafe { core::mem::transmute(arr)}
+}
+}
+
+impl CompositeConfig {
+    // the defaults: the shared 1 MiB GLOBAL_HEAP, a 128 KiB slab region, classes 8..=64, Fail
+    pub const fn new() -> Self {
+        Self {
+            heap_start: &raw mut GLOBAL_HEAP as *mut u8,
+            heap_size: HEAP_SIZE,
+            slab_region_bytes: SLAB_REGION_BYTES,
+            size_classes: [0; SLAB_CLASSES],
+            class_count: 0,
+            fallback: FallbackPolicy::Fail,
+            accounting: false,
+            mmap_threshold: Some(DEFAULT_MMAP_THRESHOLD),
+            zeroed_heap: true,
+            emergency_reserve: 0
+        }.power_of_two_classes(64)
+    }
+
+    /// the heap the allocator carves everything from. nothing is assumed about its contents, a
+    /// zero initialised one says so with zeroed_heap(true).
+    ///
+    /// # Safety
+    /// heap has to be valid for reads and writes of N bytes for as long as the allocator is used,
+    /// and dedicated to it: two allocators on one heap corrupt each other.
+    pub const unsafe fn heap<const N: usize>(mut self, heap: *mut [u8; N]) -> Self {
+        self.heap_start = heap as *mut u8;
+        self.heap_size = N;
+        self.zeroed_heap = false;
+        self
+    }
+
+    // how much of the heap seeds the slab page pool, the rest is the bump tier
+    pub const fn slab_region_bytes(mut self, bytes: usize) -> Self {
+        self.slab_region_bytes = bytes;
+        self
+    }
+
+    // explicit table, ascending. power of two classes are naturally aligned, others word aligned
+    pub const fn size_classes(mut self, classes: &[usize]) -> Self {
+        assert!(!classes.is_empty() && classes.len() <= SLAB_CLASSES, "between 1 and SLAB_CLASSES size classes");
+        let mut i = 0;
+        while i < classes.len() {
+            self.size_classes[i] = classes[i];
+            i += 1;
+        }
+        self.class_count = classes.len();
+        self
+    }
+
+    // SLAB_MIN_CLASS, 2 * SLAB_MIN_CLASS, .. up to max_block rounded up to a power of two
+    pub const fn power_of_two_classes(mut self, max_block: usize) -> Self {
+        let max_block = max_block.next_power_of_two();
+        let mut class = SLAB_MIN_CLASS;
+        let mut i = 0;
+        while class <= max_block {
+            assert!(i < SLAB_CLASSES, "max_block exceeds the largest size class");
+            self.size_classes[i] = class;
+            class <<= 1;
+            i += 1;
+        }
+        self.class_count = i;
+        self
+    }
+
+    pub const fn fallback(mut self, policy: FallbackPolicy) -> Self {
+        self.fallback = policy;
+        self
+    }
+
+    // per tag accounting, every allocation then carries a header word in front of it that
+    // remembers its tag, so dealloc can uncharge the right one. off by default.
+    pub const fn accounting(mut self, enabled: bool) -> Self {
+        self.accounting = enabled;
+        self
+    }
+
+    // where the direct mmap tier starts, DEFAULT_MMAP_THRESHOLD unless changed. only has an
+    // effect with std on linux, elsewhere large requests stay in the bump tier.
+    pub const fn mmap_threshold(mut self, threshold: Option<usize>) -> Self {
+        self.mmap_threshold = threshold;
+        self
+    }
+
+    // the heap starts out all zero (a zero initialised static, a fresh mapping), so alloc_zeroed
+    // can skip the memset for memory nobody got before. goes after heap(), which clears it
+    pub const fn zeroed_heap(mut self, zeroed: bool) -> Self {
+        self.zeroed_heap = zeroed;
+        self
+    }
+
+    // carves bytes off the end of the heap for the error path, see oom.rs
+    pub const fn emergency_reserve(mut self, bytes: usize) -> Self {
+        self.emergency_reserve = bytes;
+        self
+    }
+
+    loom_const_fn! {
+    pub const fn build(self) -> CompositeAllocator {
+        self.validate();
+        CompositeAllocator {
             inited: AtomicUsize::new(0),
-            bump_allocator: GlobalBumpAllocator::new_const(),
-            slab_allocator: UnsafeCell::new(MaybeUninit::new(Slab::new_rounded(slab_block_size))),
-            slab_block_size
+            bump_allocator: GlobalBumpAllocator::new_const().with_thread_chunk(thread_chunk_bytes(self.heap_size)),
+            config: self,
+            slabs: make_slabs(&self),
+            slab_block_size: self.size_classes[self.class_count - 1],
+            free_pages: AtomicUsize::new(0),
+            free_page_count: AtomicUsize::new(0),
+            bump_live: AtomicUsize::new(0),
+            tags: TagTable::new(),
+            oom: OomHooks::new(),
+            #[cfg(all(feature = "std", target_os = "linux", not(loom)))]
+            mmap: MmapTier::new()
+        }
+    }
+    }
+
+    // bytes of the page owner table, enough for every page the heap touches
+    const fn owner_table_len(&self) -> usize {
+        self.heap_size / SLAB_PAGE_BYTES + 2
+    }
 
+    const fn validate(&self) {
+        assert!(!self.heap_start.is_null() && self.heap_size > 0, "heap must not be empty");
+        // the owner table comes first and the slab region is aligned up to a page, leave room
+        let overhead = self.owner_table_len() + SLAB_PAGE_BYTES;
+        assert!(self.slab_region_bytes + overhead <= self.heap_size, "slab region does not fit in the heap");
+        assert!(self.slab_region_bytes + overhead + self.emergency_reserve <= self.heap_size, "emergency reserve does not fit in the heap");
+        assert!(self.slab_region_bytes & (SLAB_PAGE_BYTES - 1) == 0, "slab region must be a whole number of slab pages");
+        assert!(self.class_count > 0 && self.class_count <= SLAB_CLASSES, "between 1 and SLAB_CLASSES size classes");
+        let mut i = 0;
+        while i < self.class_count {
+            let class = self.size_classes[i];
+            assert!(class >= SLAB_MIN_CLASS && class & (SLAB_WORD_ALIGN - 1) == 0, "size classes must be whole words");
+            assert!(class <= MAX_SLAB_CLASS, "size classes must fit several blocks in a slab page");
+            assert!(i == 0 || class > self.size_classes[i - 1], "size classes must be ascending");
+            i += 1;
         }
-        
+    }
+}
+
+impl Default for CompositeConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl  CompositeAllocator { 
+    // slab_block_size is the largest class routed to the slabs, rounded up to a power of two
+    loom_const_fn! {
+    pub const fn new_const(slab_block_size : usize) -> Self { 
+        CompositeConfig::new().power_of_two_classes(slab_block_size).build()
+    }
+    }
+
+    pub const fn builder() -> CompositeConfig {
+        CompositeConfig::new()
     }
 
     // pub fn visualize_internal_fragmentation(&self) -> String { 
@@ -64,33 +337,366 @@ impl  CompositeAllocator {
         if current == 2 {
             return
         }
-        if self.inited.compare_exchange(current, 2, Ordering::AcqRel, Ordering::Acquire).is_ok() {
-            let heap_addr = unsafe { &raw mut GLOBAL_HEAP as *mut u8 as usize};
-            let heap_end = heap_addr + HEAP_SIZE;
-            let slab_size = SLAB_REGION_BYTES.min(heap_end - heap_addr);
-            let slab_start = align_up(heap_addr, SLAB_WORD_ALIGN);
+        // 0 untouched, 1 being set up by the thread that won the cas, 2 ready
+        if current == 0 && self.inited.compare_exchange(0, 1, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+            let heap_addr = self.config.heap_start as usize;
+            let heap_end = self.bump_end();
+            unsafe { core::ptr::write_bytes(heap_addr as *mut u8, NOT_SLAB, self.config.owner_table_len()) };
+            let slab_start = align_up(heap_addr + self.config.owner_table_len(), SLAB_PAGE_BYTES);
+            let slab_size = self.config.slab_region_bytes.min(heap_end - slab_start);
             let slab_end = slab_start + slab_size;
          
             // init the bump
             let bump_start = align_up(slab_end, core::mem::size_of::<usize>());
             self.bump_allocator.ensure_init(bump_start, heap_end);
-            unsafe { (&mut *(*self.slab_allocator.get()).as_mut_ptr()).init_region(slab_start, slab_end - slab_start);};
+            if self.config.emergency_reserve > 0 {
+                self.oom.init_reserve(heap_end, heap_addr + self.config.heap_size);
+            }
+            self.seed_page_pool(slab_start, slab_end, true);
             self.inited.store(2, Ordering::SeqCst);
         } else { 
             while self.inited.load(Ordering::Acquire) !=2 { 
-                core::hint::spin_loop();
+                crate::sync::spin_loop();
             }
         }
     }
 
+    // the slab region seeds the page pool, every size class takes its pages from there
+    fn seed_page_pool(&self, slab_start: usize, slab_end: usize, fresh: bool) {
+        let mut page = slab_start;
+        while page + SLAB_PAGE_BYTES <= slab_end {
+            self.push_free_page(page, fresh);
+            page += SLAB_PAGE_BYTES;
+        }
+    }
+
+    /// throws away every heap block at once: the slabs drop their pages, the page pool is seeded
+    /// from the slab region again and the bump tier rewinds. mappings still go back through
+    /// dealloc and tag charges are left alone.
+    ///
+    /// # Safety
+    /// no slab or bump block handed out before the reset may be used or freed afterwards, and
+    /// nothing may allocate from the heap while it runs.
+    pub unsafe fn reset(&self) {
+        if self.inited.load(Ordering::Acquire) != 2 {
+            return;
+        }
+        for slab in &self.slabs {
+            unsafe { slab.clear() };
+        }
+        let heap_addr = self.config.heap_start as usize;
+        unsafe { core::ptr::write_bytes(heap_addr as *mut u8, NOT_SLAB, self.config.owner_table_len()) };
+        self.free_pages.store(0, Ordering::SeqCst);
+        self.free_page_count.store(0, Ordering::SeqCst);
+        unsafe { self.bump_allocator.reset() };
+        self.bump_live.store(0, Ordering::SeqCst);
+        // the slab region has been written to by now, so its pages aren't fresh any more
+        let slab_start = align_up(heap_addr + self.config.owner_table_len(), SLAB_PAGE_BYTES);
+        let slab_end = slab_start + self.config.slab_region_bytes.min(self.bump_end() - slab_start);
+        self.seed_page_pool(slab_start, slab_end, false);
+    }
+
+    // hands the slab a page, recycled ones first, otherwise a fresh page carved out of the bump tier.
+    // the bigger pages of the large classes always come from the bump tier
+    fn refill_slab(&self, slab: &Slab) -> bool {
+        if slab.page_size == SLAB_PAGE_BYTES && let Some((page, fresh)) = self.pop_free_page() {
+            return unsafe { self.add_slab_page(slab, page, fresh) };
+        }
+        let page = match Layout::from_size_align(slab.page_size, slab.page_size) {
+            Ok(layout) => layout,
+            Err(_) => return false,
+        };
+        match self.bump_allocator.try_alloc_fresh(page) {
+            Some((p, fresh)) => unsafe { self.add_slab_page(slab, p.as_ptr() as usize, fresh) },
+            None => false,
+        }
+    }
+
+    // the page is marked before its blocks can be handed out, so the first free already finds it
+    unsafe fn add_slab_page(&self, slab: &Slab, page: usize, fresh: bool) -> bool {
+        let class = unsafe { (slab as *const Slab).offset_from(self.slabs.as_ptr()) } as u8;
+        self.set_page_owner(page, slab.page_size, class + 1);
+        let added = if fresh && self.config.zeroed_heap {
+            unsafe { slab.add_fresh_page(page) }
+        } else {
+            unsafe { slab.add_page(page) }
+        };
+        if !added {
+            self.set_page_owner(page, slab.page_size, NOT_SLAB);
+        }
+        added
+    }
+
+    // a page the slab released, nothing on it is live any more
+    fn release_slab_page(&self, slab: &Slab, page: usize) {
+        self.set_page_owner(page, slab.page_size, NOT_SLAB);
+        self.push_free_page(page, false);
+    }
+
+    fn owner_entry(&self, p: usize) -> &AtomicU8 {
+        let index = p / SLAB_PAGE_BYTES - self.config.heap_start as usize / SLAB_PAGE_BYTES;
+        debug_assert!(index < self.config.owner_table_len());
+        // always core's atomic, the table is plain heap memory
+        unsafe { &*(self.config.heap_start as *const AtomicU8).add(index) }
+    }
+
+    fn set_page_owner(&self, page: usize, len: usize, owner: u8) {
+        let mut at = page;
+        while at < page + len {
+            self.owner_entry(at).store(owner, core::sync::atomic::Ordering::Release);
+            at += SLAB_PAGE_BYTES;
+        }
+    }
+
+    // the slab whose page p is on, by the owner table. None for anything outside the heap
+    pub fn slab_of(&self, p: usize) -> Option<&Slab> {
+        let heap_start = self.config.heap_start as usize;
+        if self.inited.load(Ordering::Acquire) != 2 || p < heap_start || p >= heap_start + self.config.heap_size {
+            return None;
+        }
+        match self.owner_entry(p).load(core::sync::atomic::Ordering::Acquire) {
+            NOT_SLAB => None,
+            owner => self.slabs.get(owner as usize - 1),
+        }
+    }
+
+    // the pool's pages carry the stack link in their first word and whether they are untouched
+    // in the second, both end up under the slab page header
+    fn pop_free_page(&self) -> Option<(usize, bool)> {
+        loop {
+            let head = self.free_pages.load(Ordering::Acquire);
+            if head == 0 {
+                return None;
+            }
+            let next = unsafe { (head as *const usize).read() };
+            match self.free_pages.compare_exchange(head, next, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => {
+                    self.free_page_count.fetch_sub(1, Ordering::Relaxed);
+                    let fresh = unsafe { (head as *const usize).add(1).read() } == FRESH_PAGE;
+                    return Some((head, fresh));
+                },
+                Err(_) => {
+                    crate::sync::spin_loop();
+                }
+            }
+        }
+    }
+
+    fn push_free_page(&self, page: usize, fresh: bool) {
+        unsafe { (page as *mut usize).add(1).write(if fresh { FRESH_PAGE } else { 0 }) };
+        loop {
+            let head = self.free_pages.load(Ordering::Acquire);
+            unsafe { (page as *mut usize).write(head) };
+            match self.free_pages.compare_exchange(head, page, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => {
+                    self.free_page_count.fetch_add(1, Ordering::Relaxed);
+                    return;
+                },
+                Err(_) => {
+                    crate::sync::spin_loop();
+                }
+            }
+        }
+    }
+
+    // pages sitting in the page pool, released by the slab and not yet reused
+    pub fn free_pages(&self) -> usize {
+        self.free_page_count.load(Ordering::Relaxed)
+    }
+
+    // the smallest size class covering both size and alignment, None if it has to go to the bump tier
+    pub fn slab_for(&self, layout: Layout) -> Option<&Slab> {
+        if layout.size().max(layout.align()) > self.slab_block_size {
+            return None;
+        }
+        self.slabs[..self.config.class_count].iter()
+            .find(|slab| slab.block_size >= layout.size() && slab.block_align >= layout.align())
+    }
+
+    // registers a subsystem tag with an optional hard budget in bytes
+    pub fn register_tag(&self, name: &'static str, budget: Option<usize>) -> Option<Tag> {
+        self.tags.register(name, budget)
+    }
+
+    pub fn tag_stats(&self, tag: Tag) -> Option<TagStats> {
+        self.tags.stats(tag)
+    }
+
+    // allocation charged to an explicit tag, freed through the regular dealloc.
+    // null if the tag's budget would be exceeded or the heap is out of memory.
+    // needs the accounting builder option, without it the tag is ignored.
+    pub fn alloc_tagged(&self, layout: Layout, tag: Tag) -> *mut u8 {
+        self.ensure_init();
+        self.alloc_with_tag(layout, tag, false)
+    }
+
+    fn alloc_with_tag(&self, layout: Layout, tag: Tag, zeroed: bool) -> *mut u8 {
+        if !self.config.accounting {
+            return self.alloc_or_oom(layout, zeroed);
+        }
+        let (outer, prefix) = match Self::tagged_layout(layout) {
+            Some(tagged) => tagged,
+            None => return core::ptr::null_mut(),
+        };
+        if !self.tags.charge(tag, layout.size()) {
+            return core::ptr::null_mut();
+        }
+        let base = self.alloc_or_oom(outer, zeroed);
+        if base.is_null() {
+            self.tags.uncharge(tag, layout.size());
+            return base;
+        }
+        unsafe {
+            let p = base.add(prefix);
+            (p as *mut usize).sub(1).write(tag.index());
+            p
+        }
+    }
+
+    // the header sits right below the returned pointer, the prefix keeps the pointer aligned
+    fn tagged_layout(layout: Layout) -> Option<(Layout, usize)> {
+        let prefix = layout.align().max(core::mem::size_of::<usize>());
+        let outer = Layout::from_size_align(layout.size().checked_add(prefix)?, layout.align()).ok()?;
+        Some((outer, prefix))
+    }
+
+    // the heap minus the emergency reserve
+    fn bump_end(&self) -> usize {
+        self.config.heap_start as usize + self.config.heap_size - self.config.emergency_reserve
+    }
+
+    // out of memory goes through the handler and the reserve, reserve memory is as untouched
+    // as fresh bump memory
+    fn alloc_or_oom(&self, layout: Layout, zeroed: bool) -> *mut u8 {
+        let p = self.oom.alloc_with(layout, || self.alloc_untracked(layout, zeroed));
+        if zeroed && !self.config.zeroed_heap && !p.is_null() && p as usize >= self.bump_end() {
+            unsafe { core::ptr::write_bytes(p, 0, layout.size()) };
+        }
+        p
+    }
+
+    // pointer to tier lookup for callers that don't know the layout (free() without a size).
+    // slab blocks are told apart from bump memory by the page owner table, like in dealloc
+    pub fn tier_of(&self, ptr: *mut u8) -> Tier {
+        let p = ptr as usize;
+        #[cfg(all(feature = "std", target_os = "linux", not(loom)))]
+        if let Some(len) = self.mmap.len_of(ptr) {
+            return Tier::Mmap { len };
+        }
+        let heap_start = self.config.heap_start as usize;
+        if self.inited.load(Ordering::Acquire) != 2 || p < heap_start || p >= heap_start + self.config.heap_size {
+            return Tier::Foreign;
+        }
+        if let Some(slab) = self.slab_of(p) {
+            return Tier::Slab { block_size: slab.block_size };
+        }
+        if p >= self.bump_end() { Tier::Reserve } else { Tier::Bump }
+    }
+
+    pub fn set_oom_handler(&self, handler: Option<OomHandler>) {
+        self.oom.set_handler(handler);
+    }
+
+    pub fn oom_stats(&self) -> OomStats {
+        self.oom.stats()
+    }
+
+    // zeroed only clears what may have been written before: mappings come from the kernel
+    // zeroed, fresh bump memory and never handed out slab blocks are zero in a zeroed heap
+    fn alloc_untracked(&self, layout: Layout, zeroed: bool) -> *mut u8 {
+        #[cfg(all(feature = "std", target_os = "linux", not(loom)))]
+        if self.wants_mmap(layout) {
+            let p = self.mmap.alloc(layout);
+            if !p.is_null() {
+                return p;
+            }
+        }
+        if let Some(slab) = self.slab_for(layout) {
+            loop {
+                let block = if zeroed { unsafe { slab.alloc_zeroed() } } else { unsafe { slab.alloc() } };
+                if let Some(p) = block {
+                    return p.as_ptr();
+                }
+                if !self.refill_slab(slab) {
+                    break;
+                }
+            }
+            if self.config.fallback == FallbackPolicy::Bump {
+                return self.alloc_bump(layout, zeroed);
+            }
+            core::ptr::null_mut()
+        } else {
+            self.alloc_bump(layout, zeroed)
+        }
+    }
+
+    // plain allocations take the calling thread's chunk, zeroed ones the shared next, which
+    // knows whether the memory is fresh
+    fn alloc_bump(&self, layout: Layout, zeroed: bool) -> *mut u8 {
+        let p = self.bump_block(layout, zeroed);
+        if !p.is_null() {
+            self.bump_live.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        p
+    }
+
+    fn bump_block(&self, layout: Layout, zeroed: bool) -> *mut u8 {
+        #[cfg(feature = "std")]
+        if !zeroed {
+            return self.bump_allocator.try_alloc_local(layout).map_or(core::ptr::null_mut(), |p| p.as_ptr());
+        }
+        match self.bump_allocator.try_alloc_fresh(layout) {
+            Some((p, fresh)) => {
+                if zeroed && !(fresh && self.config.zeroed_heap) {
+                    unsafe { core::ptr::write_bytes(p.as_ptr(), 0, layout.size()) };
+                }
+                p.as_ptr()
+            },
+            None => core::ptr::null_mut(),
+        }
+    }
+
+    fn dealloc_untracked(&self, ptr: *mut u8, layout: Layout) {
+        #[cfg(all(feature = "std", target_os = "linux", not(loom)))]
+        if self.wants_mmap(layout) && self.mmap.dealloc(ptr) {
+            return;
+        }
+        if let Some(slab) = self.slab_of(ptr as usize) {
+            unsafe { slab.dealloc(NonNull::new_unchecked(ptr)) };
+            while let Some(page) = slab.release_page() {
+                self.release_slab_page(slab, page);
+            }
+        } else if (self.config.heap_start as usize..self.bump_end()).contains(&(ptr as usize)) {
+            // bump memory isn't reused, it only stops counting as live
+            self.bump_live.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+    }
+
+    // large enough for the mmap tier. a full side table sends those to the heap instead, so
+    // dealloc still has to ask the table
+    #[cfg(all(feature = "std", target_os = "linux", not(loom)))]
+    fn wants_mmap(&self, layout: Layout) -> bool {
+        matches!(self.config.mmap_threshold, Some(threshold) if layout.size() >= threshold)
+    }
+
+    // the GlobalAlloc entry point, tagged with the thread's current tag and seen by the profiler
+    fn alloc_global(&self, layout: Layout, zeroed: bool) -> *mut u8 {
+        self.ensure_init();
+        let ptr = self.alloc_with_tag(layout, tagging::current_tag(), zeroed);
+        #[cfg(all(feature = "std", target_os = "linux"))]
+        if crate::profiler::HEAP_PROFILER.is_running() {
+            crate::profiler::HEAP_PROFILER.on_alloc(ptr, layout.size());
+        }
+        ptr
+    }
+
     pub fn free_blocks(&self) -> usize { 
-        let slab = unsafe { (&*(*self.slab_allocator.get()).as_ptr())};
-        let slab_free = slab.debug_count_free();
-        slab_free
+        self.slabs.iter().map(|slab| slab.debug_count_free()).sum()
     }
 }
 
 
+#[cfg(not(loom))]
 #[global_allocator]
 pub static GLOBAL_ALLOC: CompositeAllocator = CompositeAllocator::new_const(64);
 
@@ -98,34 +704,59 @@ unsafe impl Sync for CompositeAllocator {}
 
 unsafe impl GlobalAlloc for CompositeAllocator {
     unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
-        self.ensure_init();
-        let size = layout.size();
-        let align = layout.align();
-
-        if size <= self.slab_block_size && align <= SLAB_WORD_ALIGN {
-            let slab = unsafe { (&*(*self.slab_allocator.get()).as_ptr())};
-            if let Some(p) = slab.alloc() {
-                return p.as_ptr();
-            } else {
-                return core::ptr::null_mut();
-            }
-        } else {
-            match self.bump_allocator.try_alloc(layout) {
-                Some(p) => return p.as_ptr(),
-                None => return core::ptr::null_mut(),
-            }
-        }
+        self.alloc_global(layout, false)
+    }
+
+    // only recycled memory gets cleared, see alloc_untracked
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.alloc_global(layout, true)
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
         if self.inited.load(Ordering::Acquire) != 2 {
             return;
         }
+        #[cfg(all(feature = "std", target_os = "linux"))]
+        if crate::profiler::HEAP_PROFILER.is_running() {
+            crate::profiler::HEAP_PROFILER.on_dealloc(ptr);
+        }
 
-        let p = ptr as usize;
-        let slab = unsafe { &*(*self.slab_allocator.get()).as_ptr()};
-        if layout.size() <= self.slab_block_size && layout.align() <= SLAB_WORD_ALIGN &&  slab.owns(p) {
-            slab.dealloc(unsafe {NonNull::new_unchecked(ptr)});
+        if self.config.accounting {
+            if let Some((outer, prefix)) = Self::tagged_layout(layout) {
+                let tag = unsafe { (ptr as *const usize).sub(1).read() };
+                self.tags.uncharge(Tag::from_index(tag), layout.size());
+                self.dealloc_untracked(unsafe { ptr.sub(prefix) }, outer);
+            }
+            return;
         }
+        self.dealloc_untracked(ptr, layout);
     }
-}
\ No newline at end of file
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        // a mapping that stays above the threshold is resized by the kernel, everything else
+        // (and a failed mremap) takes the usual alloc, copy, dealloc route
+        #[cfg(all(feature = "std", target_os = "linux", not(loom)))]
+        if !self.config.accounting && layout.align() <= MMAP_PAGE_SIZE && self.inited.load(Ordering::Acquire) == 2 {
+            let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+            if self.wants_mmap(layout) && self.wants_mmap(new_layout) {
+                let p = self.mmap.realloc(ptr, new_size);
+                if !p.is_null() {
+                    if crate::profiler::HEAP_PROFILER.is_running() {
+                        crate::profiler::HEAP_PROFILER.on_dealloc(ptr);
+                        crate::profiler::HEAP_PROFILER.on_alloc(p, new_size);
+                    }
+                    return p;
+                }
+            }
+        }
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+        let p = unsafe { self.alloc(new_layout) };
+        if !p.is_null() {
+            unsafe {
+                core::ptr::copy_nonoverlapping(ptr, p, layout.size().min(new_size));
+                self.dealloc(ptr, layout);
+            }
+        }
+        p
+    }
+}
@@ -23,4 +23,142 @@ pub fn test_composite_allocator() {
     //println!("{}",GLOBAL_ALLOC.visualize_internal_fragmentation());
 
 
-}
\ No newline at end of file
+}
+#[test]
+pub fn test_slab_refills_from_bump() {
+    GLOBAL_ALLOC.ensure_init();
+    // more 64 byte objects than the pooled slab pages hold, the slab has to grow out of the bump tier
+    let count = (GLOBAL_ALLOC.free_pages() + 4) * (4096 / 64);
+    let mut boxes = Vec::with_capacity(count);
+    for i in 0..count {
+        boxes.push(Box::new([i as u64; 8]));
+    }
+    assert!(boxes.iter().enumerate().all(|(i, b)| b[7] == i as u64));
+    drop(boxes);
+    assert!(GLOBAL_ALLOC.free_pages() > 0, "pages emptied by the drop go back to the page pool");
+}
+
+#[test]
+pub fn test_over_aligned_allocations_use_slab() {
+    use core::alloc::Layout;
+
+    #[repr(align(64))]
+    struct CacheLine([u64; 2]);
+    #[repr(align(16))]
+    struct Wide(u64);
+
+    GLOBAL_ALLOC.ensure_init();
+    let line = Box::new(CacheLine([1, 2]));
+    let wide = Box::new(Wide(3));
+    let line_ptr = &*line as *const CacheLine as usize;
+    let wide_ptr = &*wide as *const Wide as usize;
+    assert_eq!(line_ptr % 64, 0);
+    assert_eq!(wide_ptr % 16, 0);
+    assert!(GLOBAL_ALLOC.slab_for(Layout::new::<CacheLine>()).is_some_and(|slab| slab.owns(line_ptr)));
+    assert!(GLOBAL_ALLOC.slab_for(Layout::new::<Wide>()).is_some_and(|slab| slab.owns(wide_ptr)));
+    assert_eq!(line.0[0] + line.0[1] + wide.0, 6);
+
+    // past the largest class, over-aligned or not, it is the bump tier's job
+    assert!(GLOBAL_ALLOC.slab_for(Layout::from_size_align(8, 128).unwrap()).is_none());
+}
+
+#[test]
+pub fn test_builder_heap_shape() {
+    use core::alloc::{GlobalAlloc, Layout};
+    use crate::composite::{CompositeAllocator, FallbackPolicy, Tier};
+
+    static mut SMALL_HEAP: [u8; 64 * 1024] = [0u8; 64 * 1024];
+    static SMALL: CompositeAllocator = unsafe { CompositeAllocator::builder().heap(&raw mut SMALL_HEAP) }
+        .slab_region_bytes(8 * 1024)
+        .size_classes(&[16, 24, 64])
+        .fallback(FallbackPolicy::Bump)
+        .build();
+
+    let heap_start = unsafe { &raw mut SMALL_HEAP as *mut u8 as usize };
+    let in_heap = |p: *mut u8| (p as usize) >= heap_start && (p as usize) < heap_start + 64 * 1024;
+
+    assert_eq!(SMALL.slab_block_size, 64);
+    assert_eq!(SMALL.slab_for(Layout::from_size_align(20, 8).unwrap()).unwrap().block_size, 24);
+    // 24 is not a power of two, so a 16 aligned 20 byte request skips it
+    assert_eq!(SMALL.slab_for(Layout::from_size_align(20, 16).unwrap()).unwrap().block_size, 64);
+
+    let small = Layout::from_size_align(24, 8).unwrap();
+    let p = unsafe { SMALL.alloc(small) };
+    assert!(!p.is_null() && in_heap(p));
+    let large = Layout::from_size_align(2048, 8).unwrap();
+    let q = unsafe { SMALL.alloc(large) };
+    assert!(!q.is_null() && in_heap(q));
+
+    // bump memory that starts a page with a copy of a slab page header is still bump memory
+    let page = Layout::from_size_align(4096, 4096).unwrap();
+    let fake = unsafe { SMALL.alloc(page) };
+    unsafe { (fake as *mut [usize; 8]).write(((p as usize & !4095) as *const [usize; 8]).read()) };
+    assert_eq!(SMALL.tier_of(fake), Tier::Bump);
+    assert_eq!(SMALL.tier_of(unsafe { fake.add(64) }), Tier::Bump);
+    assert_eq!(SMALL.tier_of(p), Tier::Slab { block_size: 24 });
+    let free_before = SMALL.free_blocks();
+    unsafe {
+        SMALL.dealloc(fake.add(64), small);
+        assert_eq!(SMALL.free_blocks(), free_before, "a bump pointer never reaches a slab free list");
+        SMALL.dealloc(p, small);
+        SMALL.dealloc(q, large);
+    }
+}
+
+#[test]
+pub fn test_alloc_zeroed_clears_recycled_memory() {
+    use core::alloc::{GlobalAlloc, Layout};
+    use crate::composite::{CompositeAllocator, FallbackPolicy};
+
+    static mut ZERO_HEAP: [u8; 32 * 1024] = [0u8; 32 * 1024];
+    static ZEROED: CompositeAllocator = unsafe { CompositeAllocator::builder().heap(&raw mut ZERO_HEAP) }
+        .zeroed_heap(true)
+        .slab_region_bytes(8 * 1024)
+        .size_classes(&[32])
+        .fallback(FallbackPolicy::Bump)
+        .build();
+
+    let block = Layout::from_size_align(32, 8).unwrap();
+    let buffer = Layout::from_size_align(512, 8).unwrap();
+    let is_zero = |p: *mut u8, len: usize| (0..len).all(|i| unsafe { p.add(i).read() } == 0);
+    unsafe {
+        // first use of slab and bump memory, then dirty it and hand it back
+        let p = ZEROED.alloc_zeroed(block);
+        let q = ZEROED.alloc_zeroed(buffer);
+        assert!(is_zero(p, 32) && is_zero(q, 512));
+        p.write_bytes(0xab, 32);
+        q.write_bytes(0xab, 512);
+        ZEROED.dealloc(p, block);
+
+        // the recycled slab block comes back cleared
+        let p2 = ZEROED.alloc_zeroed(block);
+        assert_eq!(p2, p);
+        assert!(is_zero(p2, 32));
+        // so does memory handed out before a reset, the slab page goes back to the pool
+        ZEROED.reset();
+        assert_eq!(ZEROED.slabs[0].page_counts(), (0, 0, 0));
+        let q2 = ZEROED.alloc_zeroed(buffer);
+        assert_eq!(q2, q);
+        assert!(is_zero(q2, 512));
+        let p3 = ZEROED.alloc_zeroed(block);
+        assert!(is_zero(p3, 32));
+        ZEROED.dealloc(p3, block);
+    }
+
+    // vec![0u8; n] goes through alloc_zeroed on the global allocator
+    let packet = vec![0u8; 1500];
+    assert!(packet.iter().all(|&b| b == 0));
+
+    // a heap given without zeroed_heap(true) may hold anything, even its fresh memory is cleared
+    static mut DIRTY_HEAP: [u8; 32 * 1024] = [0xab; 32 * 1024];
+    static DIRTY: CompositeAllocator = unsafe { CompositeAllocator::builder().heap(&raw mut DIRTY_HEAP) }
+        .slab_region_bytes(8 * 1024)
+        .size_classes(&[32])
+        .build();
+    assert!(!DIRTY.config.zeroed_heap);
+    unsafe {
+        let p = DIRTY.alloc_zeroed(block);
+        let q = DIRTY.alloc_zeroed(buffer);
+        assert!(is_zero(p, 32) && is_zero(q, 512));
+    }
+}
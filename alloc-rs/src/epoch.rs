@@ -0,0 +1,214 @@
+use core::cell::Cell;
+use core::marker::PhantomData;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+// epoch based reclamation for lock-free structures built on these allocators.
+// a thread pins a guard before it reads shared pointers and keeps it until it is done with
+// them. memory unlinked from a structure is not freed right away but deferred, together with
+// the global epoch at that moment. the epoch only advances when every pinned participant has
+// seen the current one, so once it is two ahead of a deferred block no guard can still hold a
+// pointer to it and collect() frees it.
+// everything is fixed size or intrusive so it works on no_std: participants sit in a static
+// array, the deferred list is threaded through the garbage blocks themselves, which therefore
+// have to be at least DEFER_NODE_SIZE bytes.
+
+pub const MAX_PARTICIPANTS: usize = 64;
+
+const SLOT_FREE: usize = 0;
+const SLOT_CLAIMED: usize = 1;
+// low bit of a participant's epoch word, the epoch itself sits above it
+const PINNED: usize = 1;
+
+pub type FreeFn = unsafe fn(NonNull<u8>, usize);
+
+#[repr(C)]
+struct DeferNode {
+    next: usize,
+    epoch: usize,
+    free_fn: FreeFn,
+    ctx: usize
+}
+
+pub const DEFER_NODE_SIZE: usize = core::mem::size_of::<DeferNode>();
+
+struct Participant {
+    state: AtomicUsize,
+    epoch: AtomicUsize
+}
+
+pub struct Collector {
+    epoch: AtomicUsize,
+    participants: [Participant; MAX_PARTICIPANTS],
+    garbage: AtomicUsize,
+    pending: AtomicUsize
+}
+
+impl Collector {
+    pub const fn new() -> Self {
+        Self {
+            epoch: AtomicUsize::new(0),
+            participants: [const { Participant { state: AtomicUsize::new(SLOT_FREE), epoch: AtomicUsize::new(0) } }; MAX_PARTICIPANTS],
+            garbage: AtomicUsize::new(0),
+            pending: AtomicUsize::new(0)
+        }
+    }
+
+    // one handle per thread, None once all MAX_PARTICIPANTS slots are taken
+    pub fn register(&self) -> Option<LocalHandle<'_>> {
+        let slot = self.participants.iter().position(|p| {
+            p.state.compare_exchange(SLOT_FREE, SLOT_CLAIMED, Ordering::AcqRel, Ordering::Acquire).is_ok()
+        })?;
+        Some(LocalHandle { collector: self, slot, pins: Cell::new(0), _not_send: PhantomData })
+    }
+
+    pub fn epoch(&self) -> usize {
+        self.epoch.load(Ordering::SeqCst)
+    }
+
+    // moves the epoch one step if every pinned participant is in the current one
+    pub fn try_advance(&self) -> bool {
+        let epoch = self.epoch.load(Ordering::SeqCst);
+        for p in &self.participants {
+            if p.state.load(Ordering::Acquire) != SLOT_CLAIMED {
+                continue;
+            }
+            let local = p.epoch.load(Ordering::SeqCst);
+            if local & PINNED != 0 && local >> 1 != epoch {
+                return false;
+            }
+        }
+        self.epoch.compare_exchange(epoch, epoch + 1, Ordering::SeqCst, Ordering::Relaxed).is_ok()
+    }
+
+    // frees every deferred block that no guard can reach anymore, returns how many.
+    // runs the free functions on the calling thread, pinned or not.
+    pub fn collect(&self) -> usize {
+        let mut cursor = self.garbage.swap(0, Ordering::AcqRel);
+        let epoch = self.epoch.load(Ordering::SeqCst);
+        let mut freed = 0;
+        let (mut keep_head, mut keep_tail) = (0usize, 0usize);
+        while cursor != 0 {
+            let node = unsafe { (cursor as *const DeferNode).read() };
+            if node.epoch + 2 <= epoch {
+                unsafe { (node.free_fn)(NonNull::new_unchecked(cursor as *mut u8), node.ctx) };
+                freed += 1;
+            } else {
+                unsafe { (*(cursor as *mut DeferNode)).next = keep_head };
+                if keep_head == 0 {
+                    keep_tail = cursor;
+                }
+                keep_head = cursor;
+            }
+            cursor = node.next;
+        }
+        if keep_head != 0 {
+            self.push_chain(keep_head, keep_tail);
+        }
+        self.pending.fetch_sub(freed, Ordering::Relaxed);
+        freed
+    }
+
+    // deferred blocks not freed yet
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::Relaxed)
+    }
+
+    fn push_chain(&self, head: usize, tail: usize) {
+        let mut current = self.garbage.load(Ordering::Acquire);
+        loop {
+            unsafe { (*(tail as *mut DeferNode)).next = current };
+            match self.garbage.compare_exchange_weak(current, head, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return,
+                Err(actual) => {
+                    current = actual;
+                    core::hint::spin_loop();
+                }
+            }
+        }
+    }
+}
+
+impl Default for Collector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// a thread's registration with a collector, gives its slot back on drop
+pub struct LocalHandle<'c> {
+    collector: &'c Collector,
+    slot: usize,
+    pins: Cell<usize>,
+    // the participant slot is this thread's, it must not move to another thread
+    _not_send: PhantomData<*const ()>
+}
+
+impl<'c> LocalHandle<'c> {
+    // guards nest, the participant stays pinned until the outermost one is dropped
+    pub fn pin(&self) -> Guard<'_, 'c> {
+        let pins = self.pins.get();
+        self.pins.set(pins + 1);
+        if pins == 0 {
+            let participant = &self.collector.participants[self.slot];
+            loop {
+                let epoch = self.collector.epoch.load(Ordering::SeqCst);
+                participant.epoch.store((epoch << 1) | PINNED, Ordering::SeqCst);
+                // the epoch may have moved before our pin was visible, pin to the new one then
+                if self.collector.epoch.load(Ordering::SeqCst) == epoch {
+                    break;
+                }
+            }
+        }
+        Guard { handle: self }
+    }
+
+    pub fn is_pinned(&self) -> bool {
+        self.pins.get() > 0
+    }
+}
+
+impl Drop for LocalHandle<'_> {
+    fn drop(&mut self) {
+        let participant = &self.collector.participants[self.slot];
+        participant.epoch.store(0, Ordering::SeqCst);
+        participant.state.store(SLOT_FREE, Ordering::Release);
+    }
+}
+
+pub struct Guard<'h, 'c> {
+    handle: &'h LocalHandle<'c>
+}
+
+impl Guard<'_, '_> {
+    /// hands ptr to free_fn(ptr, ctx) once no guard pinned now can still see it.
+    ///
+    /// # Safety
+    /// ptr has to be unlinked already, so no new reader can reach it, and has to point to at
+    /// least DEFER_NODE_SIZE word aligned bytes that the collector may overwrite. free_fn and
+    /// whatever ctx refers to have to stay valid until the block is collected.
+    pub unsafe fn defer_free(&self, ptr: NonNull<u8>, free_fn: FreeFn, ctx: usize) {
+        let collector = self.handle.collector;
+        // the global epoch, not the guard's: a reader may have pinned to a newer epoch and
+        // picked up the pointer before it was unlinked
+        let epoch = collector.epoch.load(Ordering::SeqCst);
+        let node = ptr.as_ptr() as *mut DeferNode;
+        unsafe { node.write(DeferNode { next: 0, epoch, free_fn, ctx }) };
+        collector.pending.fetch_add(1, Ordering::Relaxed);
+        collector.push_chain(node as usize, node as usize);
+    }
+
+    pub fn collector(&self) -> &Collector {
+        self.handle.collector
+    }
+}
+
+impl Drop for Guard<'_, '_> {
+    fn drop(&mut self) {
+        let pins = self.handle.pins.get() - 1;
+        self.handle.pins.set(pins);
+        if pins == 0 {
+            self.handle.collector.participants[self.handle.slot].epoch.store(0, Ordering::SeqCst);
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::epoch::Collector;
+use crate::slab::Slab;
+
+#[test]
+pub fn epoch_defers_until_guards_move_on() {
+    static COLLECTOR: Collector = Collector::new();
+    static FREED: AtomicUsize = AtomicUsize::new(0);
+    static mut BLOCK: [usize; 4] = [0; 4];
+
+    unsafe fn count_free(_: NonNull<u8>, ctx: usize) {
+        FREED.fetch_add(ctx, Ordering::Relaxed);
+    }
+
+    let reader = COLLECTOR.register().unwrap();
+    let writer = COLLECTOR.register().unwrap();
+    let read_guard = reader.pin();
+    {
+        let guard = writer.pin();
+        let nested = writer.pin();
+        drop(nested);
+        assert!(writer.is_pinned());
+        unsafe { guard.defer_free(NonNull::new(&raw mut BLOCK as *mut u8).unwrap(), count_free, 1) };
+    }
+    assert!(!writer.is_pinned());
+    assert_eq!(COLLECTOR.pending(), 1);
+
+    // the reader pinned in epoch 0 lets it advance once, but not twice
+    assert!(COLLECTOR.try_advance());
+    assert!(!COLLECTOR.try_advance());
+    assert_eq!(COLLECTOR.collect(), 0);
+    drop(read_guard);
+
+    assert!(COLLECTOR.try_advance());
+    assert_eq!(COLLECTOR.epoch(), 2);
+    assert_eq!(COLLECTOR.collect(), 1);
+    assert_eq!((FREED.load(Ordering::Relaxed), COLLECTOR.pending()), (1, 0));
+}
+
+#[test]
+pub fn slab_deferred_dealloc_test() {
+    #[repr(C, align(4096))]
+    struct Page([u8; 4096]);
+    static mut PAGE: Page = Page([0u8; 4096]);
+    static COLLECTOR: Collector = Collector::new();
+
+    let slab = Slab::new_rounded(32);
+    unsafe { slab.grow(&raw mut PAGE as usize, 4096) };
+    let free = slab.debug_count_free();
+    let handle = COLLECTOR.register().unwrap();
+    let block = unsafe { slab.alloc() }.unwrap();
+    {
+        let guard = handle.pin();
+        unsafe { slab.dealloc_deferred(&guard, block) };
+    }
+    assert_eq!(slab.debug_count_free(), free - 1, "still out until collected");
+    while COLLECTOR.collect() == 0 {
+        COLLECTOR.try_advance();
+    }
+    assert_eq!(slab.debug_count_free(), free);
+}
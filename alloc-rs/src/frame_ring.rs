@@ -0,0 +1,137 @@
+use core::cell::UnsafeCell;
+use core::ops::Deref;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::bump::BumpAllocator;
+
+// ring of N bump frames for pipelines with whole-frame lifetimes (capture -> decode -> transmit).
+// the region is cut into N equal frames, each one a BumpAllocator. the producer opens the next
+// frame in ring order, allocates into it, then seals it into a FrameTicket that travels down the
+// pipeline with the data. when the last stage is done it releases the ticket, which resets the
+// frame in one step (running the destructors of typed values) and makes it available again.
+// there are no per allocation frees. up to N frames are in flight, when the producer comes back
+// around to a frame that is still in flight begin() returns None, which is the backpressure.
+// one producer opens frames, releases may come from any thread.
+
+const FRAME_FREE: usize = 0;
+const FRAME_OPEN: usize = 1;
+const FRAME_SEALED: usize = 2;
+
+pub struct FrameRing<const N: usize> {
+    frames: [UnsafeCell<BumpAllocator>; N],
+    states: [AtomicUsize; N],
+    head: AtomicUsize,
+    frame_size: usize
+}
+
+unsafe impl<const N: usize> Sync for FrameRing<N> {}
+
+// the producer's handle on an open frame, derefs to the frame's BumpAllocator
+pub struct OpenFrame<'r, const N: usize> {
+    ring: &'r FrameRing<N>,
+    index: usize
+}
+
+// a sealed frame on its way through the pipeline
+#[derive(Debug, PartialEq, Eq)]
+pub struct FrameTicket {
+    // address of the ring that sealed it
+    ring: usize,
+    index: usize
+}
+
+impl<const N: usize> FrameRing<N> {
+    /// cuts heap_size bytes at heap_start into N frames.
+    ///
+    /// # Safety
+    /// the region has to be valid for reads and writes and reserved for the ring while any of
+    /// its frames is open or in flight.
+    pub unsafe fn from_raw_parts(heap_start: usize, heap_size: usize) -> Self {
+        assert!(N > 0, "a frame ring needs at least one frame");
+        let frame_size = heap_size / N;
+        Self {
+            // each frame gets its own slice of the region
+            frames: core::array::from_fn(|i| UnsafeCell::new(unsafe { BumpAllocator::from_raw_parts(heap_start + i * frame_size, frame_size) })),
+            states: [const { AtomicUsize::new(FRAME_FREE) }; N],
+            head: AtomicUsize::new(0),
+            frame_size
+        }
+    }
+
+    // opens the next frame in ring order, None while that frame is still open or in flight
+    pub fn begin(&self) -> Option<OpenFrame<'_, N>> {
+        let head = self.head.load(Ordering::Acquire);
+        let index = head % N;
+        self.states[index].compare_exchange(FRAME_FREE, FRAME_OPEN, Ordering::AcqRel, Ordering::Acquire).ok()?;
+        self.head.store(head + 1, Ordering::Release);
+        Some(OpenFrame { ring: self, index })
+    }
+
+    /// resets the frame and hands it back to the producer. false for a ticket sealed by another
+    /// ring, or by this one before it was moved.
+    ///
+    /// # Safety
+    /// nothing allocated in the frame may be used afterwards, every stage has to be done with it.
+    pub unsafe fn release(&self, ticket: FrameTicket) -> bool {
+        let index = ticket.index;
+        if ticket.ring != self as *const Self as usize || index >= N {
+            return false;
+        }
+        if self.states[index].load(Ordering::Acquire) != FRAME_SEALED {
+            return false;
+        }
+        // sealed frames are only touched by the ticket holder, which is us
+        unsafe { (*self.frames[index].get()).reset() };
+        self.states[index].store(FRAME_FREE, Ordering::Release);
+        true
+    }
+
+    // frames open or sealed but not released yet
+    pub fn in_flight(&self) -> usize {
+        self.states.iter().filter(|state| state.load(Ordering::Acquire) != FRAME_FREE).count()
+    }
+
+    pub fn frame_size(&self) -> usize {
+        self.frame_size
+    }
+}
+
+impl<const N: usize> OpenFrame<'_, N> {
+    // no more allocations, the frame now belongs to whoever holds the ticket
+    pub fn seal(self) -> FrameTicket {
+        self.ring.states[self.index].store(FRAME_SEALED, Ordering::Release);
+        let ticket = FrameTicket { ring: self.ring as *const FrameRing<N> as usize, index: self.index };
+        // the frame lives on in the ticket, don't let Drop throw it away
+        core::mem::forget(self);
+        ticket
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+impl<const N: usize> Deref for OpenFrame<'_, N> {
+    type Target = BumpAllocator;
+
+    fn deref(&self) -> &BumpAllocator {
+        // only the open frame's owner gets at the allocator, release needs a sealed frame
+        unsafe { &*self.ring.frames[self.index].get() }
+    }
+}
+
+// a frame dropped without seal(), by an early return or a panic, is thrown away and goes back
+// into the ring, otherwise the producer would stall on it the next time round
+impl<const N: usize> Drop for OpenFrame<'_, N> {
+    fn drop(&mut self) {
+        // nothing borrowed from the frame outlives the OpenFrame
+        unsafe { (*self.ring.frames[self.index].get()).reset() };
+        self.ring.states[self.index].store(FRAME_FREE, Ordering::Release);
+    }
+}
+
+impl FrameTicket {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
@@ -0,0 +1,67 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::frame_ring::FrameRing;
+use crate::test_helpers::CountsDrops;
+
+#[test]
+pub fn frame_ring_test() {
+    static mut HEAP: [u64; 96] = [0u64; 96];
+    let heap_addr = &raw mut HEAP as *mut u8 as usize;
+    let ring: FrameRing<3> = unsafe { FrameRing::from_raw_parts(heap_addr, 768) };
+    assert_eq!(ring.frame_size(), 256);
+
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+    let drops = || DROPS.load(Ordering::Relaxed);
+
+    // capture fills three frames, the fourth has to wait for the pipeline to drain
+    let mut tickets = Vec::new();
+    for i in 0..3 {
+        let frame = ring.begin().unwrap();
+        assert_eq!(frame.index(), i);
+        frame.alloc_value(CountsDrops(&DROPS)).unwrap();
+        frame.alloc_slice_copy(&[i as u8; 64]).unwrap();
+        tickets.push(frame.seal());
+    }
+    assert_eq!(ring.in_flight(), 3);
+    assert!(ring.begin().is_none());
+
+    // the oldest frame leaves the transmit stage, its packet is dropped and the frame is reused
+    let first = tickets.remove(0);
+    unsafe { assert!(ring.release(first)) };
+    assert_eq!(drops(), 1);
+    assert_eq!(ring.in_flight(), 2);
+    let frame = ring.begin().unwrap();
+    assert_eq!(frame.index(), 0);
+    assert_eq!(frame.free_bytes(), 256);
+    // a frame only holds what fits in its share of the region
+    assert!(frame.alloc_slice_copy(&[0u8; 512]).is_none());
+    let reused = frame.seal();
+
+    // another ring's frame 0 is sealed as well, its ticket doesn't release ours
+    static mut OTHER: [u64; 32] = [0u64; 32];
+    let other: FrameRing<1> = unsafe { FrameRing::from_raw_parts(&raw mut OTHER as *mut u8 as usize, 256) };
+    let foreign = other.begin().unwrap().seal();
+    unsafe {
+        assert!(!ring.release(foreign));
+        assert!(ring.release(reused));
+    }
+
+    // releases can come out of order, the producer still goes round in order
+    unsafe {
+        assert!(ring.release(tickets.pop().unwrap()));
+        assert!(ring.begin().is_none());
+        assert!(ring.release(tickets.pop().unwrap()));
+    }
+    assert_eq!(drops(), 3);
+    assert_eq!(ring.in_flight(), 0);
+    assert_eq!(ring.begin().unwrap().index(), 1);
+
+    // an abandoned frame is reset and handed back, the ring keeps going round
+    for _ in 0..2 * 3 {
+        let frame = ring.begin().expect("dropped frames go back into the ring");
+        frame.alloc_value(CountsDrops(&DROPS)).unwrap();
+    }
+    assert_eq!(drops(), 9);
+    assert_eq!(ring.in_flight(), 0);
+    assert_eq!(ring.begin().unwrap().free_bytes(), 256);
+}
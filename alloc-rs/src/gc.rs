@@ -0,0 +1,355 @@
+use core::alloc::Layout;
+use core::cell::{Cell, RefCell};
+use core::marker::PhantomData;
+use core::mem::{align_of, size_of};
+use core::ops::Deref;
+use core::ptr::NonNull;
+
+use crate::bump::BumpAllocator;
+use crate::slab::{SLAB_PAGE_SIZE, Slab};
+
+// tracing mark-sweep heap for managed runtime experiments.
+// objects live in slab blocks, one slab per size class, and the slabs take their pages from a
+// BumpAllocator over the caller's region. every block starts with a GcHeader, the headers of
+// all live objects form an intrusive list that the sweep walks.
+// collect() marks from the registered roots with an explicit gray list threaded through the
+// headers (no recursion, deep object graphs don't blow the stack), then sweeps: unmarked
+// objects are dropped and their blocks go back to the slab free lists.
+// there is no stack scanning, a Gc held across collect() is only valid if it is reachable from
+// a root, which is why collect() is unsafe. a Gc borrows the heap, so none outlives it.
+// destructors: the sweep (and dropping the heap) unlinks and flags every dead object before it
+// drops any of them, then hands the blocks back. a destructor that follows a Gc into another
+// dead object would see it half destroyed, so deref panics there, try_get answers None.
+// single threaded, the heap and Gc pointers are !Send/!Sync.
+
+const GC_CLASSES: [usize; 6] = [32, 64, 128, 256, 512, 1024];
+pub const MAX_ROOTS: usize = 64;
+
+#[repr(C)]
+struct GcHeader {
+    // next object on the all-objects list
+    next: Cell<usize>,
+    // next object on the gray list while marking
+    gray: Cell<usize>,
+    marked: Cell<bool>,
+    // set once the object is on its way out, before any destructor runs
+    swept: Cell<bool>,
+    class: usize,
+    trace_fn: unsafe fn(usize, &mut Tracer),
+    drop_fn: unsafe fn(usize)
+}
+
+const fn value_offset<T>() -> usize {
+    let align = align_of::<T>();
+    (size_of::<GcHeader>() + align - 1) & !(align - 1)
+}
+
+unsafe fn trace_value<T: Trace>(header: usize, tracer: &mut Tracer) {
+    unsafe { (*((header + value_offset::<T>()) as *const T)).trace(tracer) };
+}
+
+unsafe fn drop_value<T>(header: usize) {
+    unsafe { core::ptr::drop_in_place((header + value_offset::<T>()) as *mut T) };
+}
+
+/// # Safety
+/// trace has to hand every Gc the value holds to the tracer, anything it misses is freed while
+/// still referenced. besides Gc the value may only borrow what outlives the heap, it is dropped
+/// whenever a collect finds it dead or the heap goes away.
+pub unsafe trait Trace {
+    fn trace(&self, tracer: &mut Tracer);
+}
+
+pub struct Tracer {
+    gray: usize
+}
+
+impl Tracer {
+    pub fn mark<T: Trace>(&mut self, gc: &Gc<'_, T>) {
+        self.mark_header(gc.header.as_ptr() as usize);
+    }
+
+    fn mark_header(&mut self, header: usize) {
+        let h = unsafe { &*(header as *const GcHeader) };
+        if !h.marked.replace(true) {
+            h.gray.set(self.gray);
+            self.gray = header;
+        }
+    }
+}
+
+pub struct Gc<'heap, T> {
+    header: NonNull<GcHeader>,
+    _marker: PhantomData<(&'heap GcHeap, T)>
+}
+
+impl<T> Clone for Gc<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Gc<'_, T> {}
+
+impl<'heap, T> Gc<'heap, T> {
+    pub fn ptr_eq(a: &Gc<'heap, T>, b: &Gc<'heap, T>) -> bool {
+        a.header == b.header
+    }
+
+    // None for an object the running sweep already condemned, for destructors that follow Gc
+    pub fn try_get(&self) -> Option<&T> {
+        if unsafe { self.header.as_ref() }.swept.get() {
+            return None;
+        }
+        Some(unsafe { &*((self.header.as_ptr() as usize + value_offset::<T>()) as *const T) })
+    }
+}
+
+impl<T> Deref for Gc<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.try_get().expect("Gc dereferenced from a destructor after its object was swept")
+    }
+}
+
+impl<T: core::fmt::Debug> core::fmt::Debug for Gc<'_, T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RootId(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CycleStats {
+    pub cycle: usize,
+    pub roots: usize,
+    pub marked: usize,
+    pub swept: usize,
+    pub swept_bytes: usize,
+    pub live_objects: usize,
+    pub live_bytes: usize
+}
+
+pub struct GcHeap {
+    pages: BumpAllocator,
+    slabs: [Slab; GC_CLASSES.len()],
+    objects: Cell<usize>,
+    roots: [Cell<usize>; MAX_ROOTS],
+    live_objects: Cell<usize>,
+    live_bytes: Cell<usize>,
+    last: Cell<CycleStats>,
+    // objects need not be Send, so the heap holding them can't be either
+    _not_send: PhantomData<*const ()>
+}
+
+impl GcHeap {
+    /// a collected heap whose slab pages are cut from heap_size bytes at heap_start.
+    ///
+    /// # Safety
+    /// the region has to be valid for reads and writes and left to this heap until it is dropped,
+    /// the sweep reads and drops whatever it finds in the objects there.
+    pub unsafe fn from_raw_parts(heap_start: usize, heap_size: usize) -> Self {
+        Self {
+            pages: unsafe { BumpAllocator::from_raw_parts(heap_start, heap_size) },
+            slabs: GC_CLASSES.map(Slab::new_rounded),
+            objects: Cell::new(0),
+            roots: [const { Cell::new(0) }; MAX_ROOTS],
+            live_objects: Cell::new(0),
+            live_bytes: Cell::new(0),
+            last: Cell::new(CycleStats::default()),
+            _not_send: PhantomData
+        }
+    }
+
+    // None once the region is out of pages or the value doesn't fit the largest class.
+    // allocation never collects on its own, see collect().
+    pub fn alloc<T: Trace>(&self, value: T) -> Option<Gc<'_, T>> {
+        let size = value_offset::<T>() + size_of::<T>();
+        let class = GC_CLASSES.iter().position(|&c| c >= size && c >= align_of::<T>())?;
+        let slab = &self.slabs[class];
+        let block = match unsafe { slab.alloc() } {
+            Some(block) => block,
+            None => {
+                let page = self.pages.alloc(Layout::from_size_align(SLAB_PAGE_SIZE, SLAB_PAGE_SIZE).ok()?)?;
+                unsafe { slab.add_page(page.as_ptr() as usize) };
+                unsafe { slab.alloc()? }
+            }
+        };
+        let header = block.as_ptr() as usize;
+        unsafe {
+            (header as *mut GcHeader).write(GcHeader {
+                next: Cell::new(self.objects.get()),
+                gray: Cell::new(0),
+                marked: Cell::new(false),
+                swept: Cell::new(false),
+                class,
+                trace_fn: trace_value::<T>,
+                drop_fn: drop_value::<T>
+            });
+            ((header + value_offset::<T>()) as *mut T).write(value);
+        }
+        self.objects.set(header);
+        self.live_objects.set(self.live_objects.get() + 1);
+        self.live_bytes.set(self.live_bytes.get() + GC_CLASSES[class]);
+        Some(Gc { header: unsafe { NonNull::new_unchecked(header as *mut GcHeader) }, _marker: PhantomData })
+    }
+
+    // None once all MAX_ROOTS slots are taken
+    pub fn add_root<T: Trace>(&self, gc: Gc<'_, T>) -> Option<RootId> {
+        let slot = self.roots.iter().position(|root| root.get() == 0)?;
+        self.roots[slot].set(gc.header.as_ptr() as usize);
+        Some(RootId(slot))
+    }
+
+    pub fn remove_root(&self, root: RootId) {
+        self.roots[root.0].set(0);
+    }
+
+    /// marks everything reachable from the roots and frees the rest.
+    ///
+    /// # Safety
+    /// every Gc used after this call has to be reachable from a registered root, an unreachable
+    /// one dangles once its object is swept.
+    pub unsafe fn collect(&self) -> CycleStats {
+        let mut stats = CycleStats { cycle: self.last.get().cycle + 1, ..Default::default() };
+        let mut tracer = Tracer { gray: 0 };
+        for root in self.roots.iter().map(Cell::get).filter(|&root| root != 0) {
+            stats.roots += 1;
+            tracer.mark_header(root);
+        }
+        while tracer.gray != 0 {
+            let header = tracer.gray;
+            let h = unsafe { &*(header as *const GcHeader) };
+            tracer.gray = h.gray.replace(0);
+            unsafe { (h.trace_fn)(header, &mut tracer) };
+            stats.marked += 1;
+        }
+
+        // sweep, moving dead objects from the all-objects list to a dead list threaded through
+        // gray (empty again after marking) and flagging them
+        let mut dead = 0usize;
+        let mut prev = 0usize;
+        let mut cursor = self.objects.get();
+        while cursor != 0 {
+            let h = unsafe { &*(cursor as *const GcHeader) };
+            let next = h.next.get();
+            if h.marked.replace(false) {
+                prev = cursor;
+            } else {
+                if prev == 0 {
+                    self.objects.set(next);
+                } else {
+                    unsafe { (*(prev as *const GcHeader)).next.set(next) };
+                }
+                h.swept.set(true);
+                h.gray.set(dead);
+                dead = cursor;
+                stats.swept += 1;
+                stats.swept_bytes += GC_CLASSES[h.class];
+            }
+            cursor = next;
+        }
+        // every header stays intact until all the destructors are done
+        unsafe { Self::drop_list(dead, |h| h.gray.get()) };
+        while dead != 0 {
+            let h = unsafe { &*(dead as *const GcHeader) };
+            let (next, class) = (h.gray.get(), h.class);
+            unsafe { self.slabs[class].dealloc(NonNull::new_unchecked(dead as *mut u8)) };
+            dead = next;
+        }
+        self.live_objects.set(self.live_objects.get() - stats.swept);
+        self.live_bytes.set(self.live_bytes.get() - stats.swept_bytes);
+        stats.live_objects = self.live_objects.get();
+        stats.live_bytes = self.live_bytes.get();
+        self.last.set(stats);
+        stats
+    }
+
+    pub fn last_cycle(&self) -> CycleStats {
+        self.last.get()
+    }
+
+    pub fn live_objects(&self) -> usize {
+        self.live_objects.get()
+    }
+
+    pub fn live_bytes(&self) -> usize {
+        self.live_bytes.get()
+    }
+
+    // bytes of the region not yet carved into slab pages
+    pub fn free_bytes(&self) -> usize {
+        self.pages.free_bytes()
+    }
+
+    // drops every object on the list starting at head, the objects have to be flagged swept
+    unsafe fn drop_list(head: usize, link: impl Fn(&GcHeader) -> usize) {
+        let mut cursor = head;
+        while cursor != 0 {
+            let h = unsafe { &*(cursor as *const GcHeader) };
+            let next = link(h);
+            unsafe { (h.drop_fn)(cursor) };
+            cursor = next;
+        }
+    }
+}
+
+impl Drop for GcHeap {
+    fn drop(&mut self) {
+        let mut cursor = self.objects.get();
+        while cursor != 0 {
+            let h = unsafe { &*(cursor as *const GcHeader) };
+            h.swept.set(true);
+            cursor = h.next.get();
+        }
+        unsafe { Self::drop_list(self.objects.get(), |h| h.next.get()) };
+    }
+}
+
+unsafe impl<T: Trace> Trace for Gc<'_, T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.mark(self);
+    }
+}
+
+unsafe impl<T: Trace> Trace for Option<T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Some(value) = self {
+            value.trace(tracer);
+        }
+    }
+}
+
+unsafe impl<T: Trace, const N: usize> Trace for [T; N] {
+    fn trace(&self, tracer: &mut Tracer) {
+        for value in self {
+            value.trace(tracer);
+        }
+    }
+}
+
+unsafe impl<T: Trace + Copy> Trace for Cell<T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.get().trace(tracer);
+    }
+}
+
+// a value that is mutably borrowed while collect() runs would be missed, so this panics instead
+unsafe impl<T: Trace> Trace for RefCell<T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.borrow().trace(tracer);
+    }
+}
+
+macro_rules! trace_leaf {
+    ($($t:ty),*) => {
+        $(unsafe impl Trace for $t {
+            fn trace(&self, _: &mut Tracer) {}
+        })*
+    };
+}
+
+trace_leaf!((), bool, char, u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64, &'static str);
@@ -0,0 +1,71 @@
+use core::cell::Cell;
+
+use crate::gc::{Gc, GcHeap, Trace, Tracer};
+
+#[test]
+pub fn gc_mark_sweep_test() {
+    #[repr(C, align(4096))]
+    struct Pages([u8; 4 * 4096]);
+    static mut HEAP: Pages = Pages([0u8; 4 * 4096]);
+    static DROPS: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(0);
+    // destructors that found their next node already condemned
+    static CONDEMNED: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(0);
+
+    struct Node<'h> {
+        value: u64,
+        next: Cell<Option<Gc<'h, Node<'h>>>>
+    }
+
+    unsafe impl Trace for Node<'_> {
+        fn trace(&self, tracer: &mut Tracer) {
+            self.next.trace(tracer);
+        }
+    }
+
+    impl Drop for Node<'_> {
+        fn drop(&mut self) {
+            DROPS.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+            if self.next.get().is_some_and(|next| next.try_get().is_none()) {
+                CONDEMNED.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+            }
+        }
+    }
+
+    let heap = unsafe { GcHeap::from_raw_parts(&raw mut HEAP as *mut u8 as usize, 4 * 4096) };
+    let node = |value| heap.alloc(Node { value, next: Cell::new(None) }).unwrap();
+
+    // a -> b -> c -> a is a cycle hanging off the root, d is garbage right away
+    let (a, b, c, d) = (node(1), node(2), node(3), node(4));
+    a.next.set(Some(b));
+    b.next.set(Some(c));
+    c.next.set(Some(a));
+    let root = heap.add_root(a).unwrap();
+    heap.alloc([7u64; 64]).unwrap();
+
+    let stats = unsafe { heap.collect() };
+    assert_eq!((stats.cycle, stats.roots, stats.marked, stats.swept), (1, 1, 3, 2));
+    assert_eq!(stats.swept_bytes, 64 + 1024);
+    assert_eq!(stats.live_objects, 3);
+    assert_eq!(DROPS.load(core::sync::atomic::Ordering::Relaxed), 1);
+
+    let mut sum = 0;
+    let mut cursor = a;
+    for _ in 0..3 {
+        sum += cursor.value;
+        cursor = cursor.next.get().unwrap();
+    }
+    assert_eq!(sum, 6);
+    assert!(Gc::ptr_eq(&cursor, &a));
+
+    // swept blocks are handed out again
+    let e = node(5);
+    assert!(Gc::ptr_eq(&e, &d));
+
+    heap.remove_root(root);
+    let stats = unsafe { heap.collect() };
+    assert_eq!((stats.cycle, stats.marked, stats.swept, stats.live_objects), (2, 0, 4, 0));
+    assert_eq!(heap.last_cycle(), stats);
+    assert_eq!(DROPS.load(core::sync::atomic::Ordering::Relaxed), 5);
+    // the whole cycle died at once, every destructor saw its neighbour flagged, none read it
+    assert_eq!(CONDEMNED.load(core::sync::atomic::Ordering::Relaxed), 3);
+}
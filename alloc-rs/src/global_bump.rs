@@ -1,71 +1,455 @@
 use core::alloc::{Layout, GlobalAlloc};
-use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::sync::{fence, AtomicUsize, Ordering};
 use core::ptr::NonNull;
 
 use crate::bump::align_up;
 
+#[cfg(not(loom))]
 const HEAP_SIZE: usize = 1024 * 1024;
 
+#[cfg(not(loom))]
 static mut  GLOBAL_HEAP : [u8; HEAP_SIZE] = [0u8; HEAP_SIZE];
 
 pub struct GlobalBumpAllocator {
     start: AtomicUsize,
     end: AtomicUsize,
-    next: AtomicUsize
+    next: AtomicUsize,
+    generation: AtomicUsize,
+    // furthest next ever got, memory from here on was never handed out since ensure_init
+    high_water: AtomicUsize,
+    // region from with_region(), set up on first use. null for new_const()
+    region: *mut u8,
+    region_size: usize,
+    scope_mark: AtomicUsize,
+    // blocks at or above scope_mark not freed yet
+    scope_live: AtomicUsize,
+    // scoped() calls inside the open scope
+    scope_users: AtomicUsize,
+    // bumped by every rewind, an allocation that saw it move retries
+    rewinds: AtomicUsize,
+    // size of the per thread chunks, 0 sends everything through the shared next
+    chunk_bytes: usize,
+    // the region starts out all zero, see zeroed_region
+    zeroed_region: bool
+}
+
+// installing it as the global allocator, the region is set up on the first allocation:
+//
+//     static mut HEAP: [u8; 256 * 1024] = [0u8; 256 * 1024];
+//     #[global_allocator]
+//     static ALLOC: GlobalBumpAllocator = GlobalBumpAllocator::with_region(&raw mut HEAP).zeroed_region(true);
+//
+// allocations past the end of the region get null, alloc_error_handler takes it from there.
+// dealloc frees nothing on its own, memory only comes back through reset() or scoped().
+
+// scoped(): batch jobs that free everything they allocate get their memory back without an
+// unsafe reset. the scope marks next when it opens and rewinds to the mark when it closes, but
+// only if every block bumped since then has been freed again, which is what scope_live counts.
+// scopes on other threads join the open one, only the last to close can rewind. a scope that
+// closes with blocks still live stays open: the mark is kept, later scopes join it, and the
+// first last-close that finds everything freed rewinds the lot.
+// the mark goes through two transient states so an allocation never has to guess which side
+// of it it is on: STARTING while next is sampled, CLOSING while a close decides. allocations
+// and frees wait those out. a block bumped right before a rewind may be handed out again, so
+// an allocation that sees the rewind count move throws its block away and tries again.
+// both sides write one word and then read the other's (next against the mark), so there is a
+// seqcst fence between the write and the read on each side.
+const SCOPE_STARTING: usize = 1;
+const SCOPE_CLOSING: usize = 2;
+
+// only the region pointer isn't atomic and it is never written after construction
+unsafe impl Send for GlobalBumpAllocator {}
+unsafe impl Sync for GlobalBumpAllocator {}
+
+// per thread arenas: every thread carves a chunk (THREAD_CHUNK_BYTES unless with_thread_chunk
+// says otherwise) out of the shared region with one cas and then bumps inside its chunk with
+// plain non atomic writes, the shared next is only touched again when the chunk runs out.
+// requests bigger than a quarter chunk skip the chunk so a single large allocation doesn't
+// waste most of one, and so does everything once the region has no room for another chunk.
+// the thread local holds one chunk, tagged with the allocator it came from and the generation
+// of that allocator, so a chunk is dropped after reset() or when the thread switches allocators.
+// scopes: a chunk carved before the scope opened lies below the mark and is left alone by the
+// rewind. a chunk carved inside the scope isn't counted in scope_live itself, its blocks are,
+// one at a time, and it remembers the rewind count it was carved under. once that moves the
+// rewind may have handed the chunk's memory out again, so the chunk is dropped.
+pub const THREAD_CHUNK_BYTES: usize = 64 * 1024;
+
+#[cfg(feature = "std")]
+#[derive(Clone, Copy)]
+struct LocalChunk {
+    owner: usize,
+    generation: usize,
+    start: usize,
+    next: usize,
+    end: usize,
+    // Some(rewind count) for a chunk carved inside the open scope
+    scope: Option<usize>
+}
+
+#[cfg(feature = "std")]
+std::thread_local! {
+    // const initialised and without Drop, so touching it never allocates
+    static LOCAL_CHUNK: core::cell::Cell<LocalChunk> = const {
+        core::cell::Cell::new(LocalChunk { owner: 0, generation: 0, start: 0, next: 0, end: 0, scope: None })
+    };
 }
 
 
 impl GlobalBumpAllocator {
+    loom_const_fn! {
     pub const fn new_const() -> Self { 
         Self { 
             start: AtomicUsize::new(0),
             end: AtomicUsize::new(0),
-            next: AtomicUsize::new(0)
+            next: AtomicUsize::new(0),
+            generation: AtomicUsize::new(0),
+            high_water: AtomicUsize::new(0),
+            region: core::ptr::null_mut(),
+            region_size: 0,
+            scope_mark: AtomicUsize::new(0),
+            scope_live: AtomicUsize::new(0),
+            scope_users: AtomicUsize::new(0),
+            rewinds: AtomicUsize::new(0),
+            chunk_bytes: THREAD_CHUNK_BYTES,
+            zeroed_region: false
         }
     }
+    }
+
+    loom_const_fn! {
+    // size of the chunk every thread takes for try_alloc_local(), 0 turns the chunks off
+    pub const fn with_thread_chunk(mut self, bytes: usize) -> Self {
+        self.chunk_bytes = bytes;
+        self
+    }
+    }
+
+    loom_const_fn! {
+    // bounded to heap, which is set up on first use
+    pub const fn with_region<const N: usize>(heap: *mut [u8; N]) -> Self {
+        let mut bump = Self::new_const();
+        bump.region = heap as *mut u8;
+        bump.region_size = N;
+        bump
+    }
+    }
+
+    loom_const_fn! {
+    // the region passed to with_region() starts out all zero (a zero initialised static), so
+    // alloc_zeroed can skip the memset for memory that was never handed out
+    pub const fn zeroed_region(mut self, zeroed: bool) -> Self {
+        self.zeroed_region = zeroed;
+        self
+    }
+    }
 
     pub fn ensure_init(&self,heap_addr: usize,end: usize) { 
-        if self.start.load(Ordering::Acquire) != 0 { 
+        // start is claimed first, end is stored last and marks the allocator as ready
+        if self.end.load(Ordering::Acquire) != 0 { 
             return;
         }
 
         match self.start.compare_exchange(0, heap_addr, Ordering::AcqRel, Ordering::Acquire) {
             Ok(_) => { 
-                self.end.store(end, Ordering::Release);
+                // end is what the losers wait for, next has to be in place before it
                 self.next.store(heap_addr, Ordering::Release);
+                self.end.store(end, Ordering::Release);
             },
             Err(_) => {
                 while self.end.load(Ordering::Acquire) == 0 {
-                    core::hint::spin_loop();
+                    crate::sync::spin_loop();
                 }
             },
         }
     }
 
     pub fn try_alloc(&self, layout: Layout) -> Option<NonNull<u8>> {
-        let heap_start = self.start.load(Ordering::Acquire);
-        let heap_end = self.start.load(Ordering::Acquire);
-        self.ensure_init(heap_start, heap_end);
-        let align = layout.align();
-        let size = layout.size();
+        self.try_alloc_fresh(layout).map(|(p, _)| p)
+    }
+
+    // like try_alloc, plus whether the block was never handed out before (not even before a
+    // reset). a zeroed region stays zero there, alloc_zeroed can skip the memset.
+    // racing allocations may report a fresh block as used, never the other way round.
+    pub fn try_alloc_fresh(&self, layout: Layout) -> Option<(NonNull<u8>, bool)> {
+        let (block, fresh, _) = self.bump_shared(layout, true)?;
+        Some((unsafe {NonNull::new_unchecked(block as *mut u8)}, fresh))
+    }
+
+    // the block, whether it is fresh, and Some(rewind count) when it lies inside the open
+    // scope. count is false for thread chunks, their blocks are counted one by one instead
+    fn bump_shared(&self, layout: Layout, count: bool) -> Option<(usize, bool, Option<usize>)> {
+        let end = self.initialised_end()?;
         loop {
-            let current = self.next.load(Ordering::Relaxed);
-            let aligned = align_up(current, align);
-            let new_next = aligned.saturating_add(size);
-            match self.next.compare_exchange(current, new_next, Ordering::AcqRel, Ordering::Acquire) {
-                Ok(_) => return Some(unsafe {NonNull::new_unchecked(aligned as *mut u8)}),
-                Err(_) =>  {
-                    core::hint::spin_loop();
-                    continue;
+            let rewinds = self.rewinds.load(Ordering::SeqCst);
+            let (aligned, new_next) = self.bump(layout, end)?;
+            if let Some(in_scope) = self.track(aligned, rewinds, count) {
+                let fresh = self.high_water.fetch_max(new_next, Ordering::AcqRel) <= aligned;
+                return Some((aligned, fresh, in_scope.then_some(rewinds)));
+            }
+            // a rewind raced us, the block may already belong to someone else
+        }
+    }
+
+    // None, with next left where it was, when the block doesn't fit before end
+    fn bump(&self, layout: Layout, end: usize) -> Option<(usize, usize)> {
+        let mut current = self.next.load(Ordering::Relaxed);
+        loop {
+            let aligned = align_up(current, layout.align());
+            let new_next = aligned.checked_add(layout.size())?;
+            if new_next > end {
+                return None;
+            }
+            match self.next.compare_exchange(current, new_next, Ordering::SeqCst, Ordering::Acquire) {
+                Ok(_) => return Some((aligned, new_next)),
+                Err(actual) =>  {
+                    // retry from the value that beat us, a fresh relaxed load may still be stale
+                    current = actual;
+                    crate::sync::spin_loop();
                 },
             }
-            
         }
     }
 
-    pub fn reset(&self) {
+    // whether the block lies inside the open scope, counting it there if count is set. None if
+    // a rewind happened since rewinds was read
+    fn track(&self, block: usize, rewinds: usize, count: bool) -> Option<bool> {
+        // the bump before the mark read, pairs with the fences in open_scope and close_scope
+        fence(Ordering::SeqCst);
+        let mark = self.stable_mark();
+        let in_scope = mark != 0 && block >= mark;
+        if in_scope && count {
+            self.scope_live.fetch_add(1, Ordering::SeqCst);
+            fence(Ordering::SeqCst);
+            // a close that read scope_live before the add is waited out, and if it rewound
+            // the rewind count says so
+            self.stable_mark();
+            if self.rewinds.load(Ordering::SeqCst) != rewinds {
+                self.uncount();
+                return None;
+            }
+            return Some(true);
+        }
+        (self.rewinds.load(Ordering::SeqCst) == rewinds).then_some(in_scope)
+    }
+
+    // the scope mark once it is neither starting nor closing, 0 without a scope
+    fn stable_mark(&self) -> usize {
+        loop {
+            match self.scope_mark.load(Ordering::SeqCst) {
+                SCOPE_STARTING | SCOPE_CLOSING => crate::sync::spin_loop(),
+                mark => return mark,
+            }
+        }
+    }
+
+    // saturating, a reset may have zeroed the count under a block that is being let go
+    fn uncount(&self) {
+        let _ = self.scope_live.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+    }
+
+    // end of the region, setting up a with_region() one on first use. None before any init
+    fn initialised_end(&self) -> Option<usize> {
+        let end = self.end.load(Ordering::Acquire);
+        if end != 0 {
+            return Some(end);
+        }
+        if self.region.is_null() {
+            return None;
+        }
+        let start = self.region as usize;
+        self.ensure_init(start, start + self.region_size);
+        Some(self.end.load(Ordering::Acquire))
+    }
+
+    // dealloc's half of the scope bookkeeping, the memory itself isn't reused. ptr has to be
+    // a block from try_alloc or try_alloc_local, anything else would throw the count off
+    pub(crate) fn release(&self, ptr: *mut u8) {
+        let mark = self.stable_mark();
+        if mark != 0 && ptr as usize >= mark {
+            self.uncount();
+        }
+    }
+
+    // runs job inside a scope and rewinds next to where it was when the scope opened if
+    // everything bumped since has been freed again, e.g. a batch job whose Vecs and Boxes are
+    // all gone by the time it returns. the bool says whether it rewound. with scopes on other
+    // threads the rewind waits for all of them, whoever closes last with nothing live does it.
+    // a job that panics never closes, its scope stays open until a reset
+    pub fn scoped<R>(&self, job: impl FnOnce() -> R) -> (R, bool) {
+        let Some(mark) = self.open_scope() else {
+            return (job(), false);
+        };
+        let result = job();
+        (result, self.close_scope(mark))
+    }
+
+    fn open_scope(&self) -> Option<usize> {
+        self.initialised_end()?;
+        loop {
+            match self.scope_mark.compare_exchange(0, SCOPE_STARTING, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => {
+                    self.scope_users.fetch_add(1, Ordering::SeqCst);
+                    fence(Ordering::SeqCst);
+                    let mark = self.next.load(Ordering::SeqCst);
+                    self.scope_mark.store(mark, Ordering::SeqCst);
+                    return Some(mark);
+                },
+                Err(SCOPE_STARTING | SCOPE_CLOSING) => crate::sync::spin_loop(),
+                Err(mark) => {
+                    // joins the open scope, unless it was closed before the join counted
+                    self.scope_users.fetch_add(1, Ordering::SeqCst);
+                    fence(Ordering::SeqCst);
+                    if self.stable_mark() == mark {
+                        return Some(mark);
+                    }
+                    self.leave_scope();
+                },
+            }
+        }
+    }
+
+    fn close_scope(&self, mark: usize) -> bool {
+        if self.leave_scope() != Some(1) {
+            return false;
+        }
+        // a reset dropped it, or someone else is closing it
+        if self.scope_mark.compare_exchange(mark, SCOPE_CLOSING, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+            return false;
+        }
+        fence(Ordering::SeqCst);
+        let next = self.next.load(Ordering::SeqCst);
+        let rewound = self.scope_users.load(Ordering::SeqCst) == 0
+            && self.scope_live.load(Ordering::SeqCst) == 0
+            && self.next.compare_exchange(next, mark, Ordering::SeqCst, Ordering::SeqCst).is_ok();
+        if rewound {
+            self.rewinds.fetch_add(1, Ordering::SeqCst);
+            self.scope_mark.store(0, Ordering::SeqCst);
+        } else {
+            self.scope_mark.store(mark, Ordering::SeqCst);
+        }
+        rewound
+    }
+
+    // the user count before leaving, None if a reset already zeroed it
+    fn leave_scope(&self) -> Option<usize> {
+        self.scope_users.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1)).ok()
+    }
+
+    /// rewinds the allocator to the start of its region, thread chunks and open scopes included.
+    ///
+    /// # Safety
+    /// every block handed out before the reset is handed out again afterwards, so none of them may
+    /// be used or freed once it runs. a composite allocator's bump tier is reset through the
+    /// composite, which also drops the slab pages carved from it.
+    pub unsafe fn reset(&self) {
+        let start = self.start.load(Ordering::Acquire);
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        // everything is gone, open scopes included. like the rest of reset this assumes nothing
+        // is allocating meanwhile, a racing allocation may leave a stale count behind
+        self.scope_mark.store(0, Ordering::SeqCst);
+        self.scope_live.store(0, Ordering::SeqCst);
+        self.scope_users.store(0, Ordering::SeqCst);
+        // a swap rather than a plain store: loom lets a racing cas read past a plain store
+        self.next.swap(start, Ordering::SeqCst);
+    }
+
+    // fast path through the calling thread's chunk, falls back to try_alloc for big requests,
+    // when no chunk fits any more and when the thread local is already gone during thread teardown.
+    #[cfg(feature = "std")]
+    pub fn try_alloc_local(&self, layout: Layout) -> Option<NonNull<u8>> {
+        if self.chunk_bytes == 0 || layout.size() > self.chunk_bytes / 4 {
+            return self.try_alloc(layout);
+        }
+        let owner = self as *const Self as usize;
+        let generation = self.generation.load(Ordering::Acquire);
+        LOCAL_CHUNK.try_with(|cell| {
+            let mut chunk = cell.get();
+            if chunk.owner == owner && chunk.generation == generation && let Some(p) = self.bump_local(&mut chunk, layout) {
+                cell.set(chunk);
+                return Some(p);
+            }
+            // chunk exhausted or stale, the rest of the old one is simply abandoned
+            loop {
+                let Some(fresh) = self.carve_chunk(owner, generation, layout.align()) else {
+                    return self.try_alloc(layout);
+                };
+                chunk = fresh;
+                // only misses when a rewind took the new chunk straight away
+                if let Some(p) = self.bump_local(&mut chunk, layout) {
+                    cell.set(chunk);
+                    return Some(p);
+                }
+            }
+        }).unwrap_or_else(|_| self.try_alloc(layout))
+    }
+
+    #[cfg(feature = "std")]
+    fn carve_chunk(&self, owner: usize, generation: usize, align: usize) -> Option<LocalChunk> {
+        let layout = Layout::from_size_align(self.chunk_bytes, align.max(core::mem::size_of::<usize>())).ok()?;
+        let (start, _, scope) = self.bump_shared(layout, false)?;
+        Some(LocalChunk { owner, generation, start, next: start, end: start + self.chunk_bytes, scope })
+    }
+
+    // a chunk inside the scope counts every block, and is done for once a rewind went past it
+    #[cfg(feature = "std")]
+    fn bump_local(&self, chunk: &mut LocalChunk, layout: Layout) -> Option<NonNull<u8>> {
+        let Some(carved_under) = chunk.scope else {
+            return Self::bump_chunk(chunk, layout);
+        };
+        let rewinds = self.rewinds.load(Ordering::SeqCst);
+        if rewinds != carved_under {
+            return None;
+        }
+        let mut bumped = *chunk;
+        let p = Self::bump_chunk(&mut bumped, layout)?;
+        self.track(p.as_ptr() as usize, rewinds, true)?;
+        *chunk = bumped;
+        Some(p)
+    }
+
+    /// rewinds only the calling thread's chunk, other threads and the shared region are untouched.
+    ///
+    /// # Safety
+    /// nothing this thread allocated through try_alloc_local() since its chunk was handed out
+    /// may still be in use.
+    #[cfg(feature = "std")]
+    pub unsafe fn reset_thread_arena(&self) {
+        let owner = self as *const Self as usize;
+        let _ = LOCAL_CHUNK.try_with(|cell| {
+            let mut chunk = cell.get();
+            if chunk.owner == owner {
+                chunk.next = chunk.start;
+                cell.set(chunk);
+            }
+        });
+    }
+
+    // bytes left in the calling thread's chunk, 0 if it has none for this allocator
+    #[cfg(feature = "std")]
+    pub fn thread_free_bytes(&self) -> usize {
+        let owner = self as *const Self as usize;
+        let generation = self.generation.load(Ordering::Acquire);
+        LOCAL_CHUNK.try_with(|cell| {
+            let chunk = cell.get();
+            if chunk.owner == owner && chunk.generation == generation { chunk.end - chunk.next } else { 0 }
+        }).unwrap_or(0)
+    }
+
+    #[cfg(feature = "std")]
+    fn bump_chunk(chunk: &mut LocalChunk, layout: Layout) -> Option<NonNull<u8>> {
+        let aligned = align_up(chunk.next, layout.align());
+        let new_next = aligned.checked_add(layout.size())?;
+        if new_next > chunk.end {
+            return None;
+        }
+        chunk.next = new_next;
+        NonNull::new(aligned as *mut u8)
+    }
+
+    pub fn used_bytes(&self) -> usize {
         let start = self.start.load(Ordering::Acquire);
-        self.next.store(start, Ordering::SeqCst);
+        let next = self.next.load(Ordering::Acquire);
+        next.saturating_sub(start)
     }
 
     pub fn free_bytes(&self) -> usize { 
@@ -81,19 +465,36 @@ impl GlobalBumpAllocator {
 }
 
 
-//#[global_allocator]
-pub static GLOBAL_BUMP_ALLOCATOR : GlobalBumpAllocator = GlobalBumpAllocator::new_const();
+#[cfg(not(loom))]
+pub static GLOBAL_BUMP_ALLOCATOR : GlobalBumpAllocator = GlobalBumpAllocator::with_region(&raw mut GLOBAL_HEAP).zeroed_region(true);
 
-// unsafe impl GlobalAlloc for GlobalBumpAllocator {
-//     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-//         match self.try_alloc(layout) {
-//             Some(ptr) => return ptr.as_ptr(),
-//             None=> core::ptr::null_mut()
-//         }
-//     }
+unsafe impl GlobalAlloc for GlobalBumpAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        #[cfg(feature = "std")]
+        let block = self.try_alloc_local(layout);
+        #[cfg(not(feature = "std"))]
+        let block = self.try_alloc(layout);
+        match block {
+            Some(ptr) => ptr.as_ptr(),
+            None => core::ptr::null_mut()
+        }
+    }
 
-//     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-//         //
-//     }
-// }
+    // memory that was never handed out needs no memset in a zeroed_region() heap.
+    // goes through the shared next, which knows what is fresh, rather than the thread chunk
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        match self.try_alloc_fresh(layout) {
+            Some((ptr, fresh)) => {
+                if !(fresh && self.zeroed_region && !self.region.is_null()) {
+                    unsafe { core::ptr::write_bytes(ptr.as_ptr(), 0, layout.size()) };
+                }
+                ptr.as_ptr()
+            },
+            None => core::ptr::null_mut()
+        }
+    }
 
+    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
+        self.release(ptr);
+    }
+}
@@ -0,0 +1,145 @@
+use core::alloc::Layout;
+
+use crate::global_bump::{GlobalBumpAllocator, THREAD_CHUNK_BYTES};
+
+#[test]
+pub fn thread_arena_test() {
+    static mut REGION: [u8; 4 * THREAD_CHUNK_BYTES] = [0u8; 4 * THREAD_CHUNK_BYTES];
+    static BUMP: GlobalBumpAllocator = GlobalBumpAllocator::new_const();
+    let start = unsafe { &raw mut REGION as *mut u8 as usize };
+    BUMP.ensure_init(start, start + 4 * THREAD_CHUNK_BYTES);
+
+    let l = Layout::from_size_align(16, 8).unwrap();
+    let a = BUMP.try_alloc_local(l).expect("should alloc a").as_ptr() as usize;
+    let b = BUMP.try_alloc_local(l).expect("should alloc b").as_ptr() as usize;
+    assert_eq!(b - a, 16, "same thread bumps inside its chunk");
+    assert_eq!(BUMP.free_bytes(), 3 * THREAD_CHUNK_BYTES, "only one chunk taken from the shared region");
+    assert_eq!(BUMP.thread_free_bytes(), THREAD_CHUNK_BYTES - 32);
+
+    let other = std::thread::spawn(|| BUMP.try_alloc_local(Layout::from_size_align(16, 8).unwrap()).unwrap().as_ptr() as usize)
+        .join()
+        .unwrap();
+    assert!(other < a || other >= a + THREAD_CHUNK_BYTES, "other threads get their own chunk");
+
+    unsafe { BUMP.reset_thread_arena() };
+    let again = BUMP.try_alloc_local(l).expect("should alloc after thread reset").as_ptr() as usize;
+    assert_eq!(again, a, "thread reset rewinds only this thread's chunk");
+    assert_eq!(BUMP.free_bytes(), 2 * THREAD_CHUNK_BYTES);
+
+    let big = BUMP.try_alloc_local(Layout::from_size_align(THREAD_CHUNK_BYTES / 2, 8).unwrap()).unwrap().as_ptr() as usize;
+    assert!(big >= start + 2 * THREAD_CHUNK_BYTES, "big requests go straight to the shared region");
+
+    unsafe { BUMP.reset() };
+    assert_eq!(BUMP.thread_free_bytes(), 0, "global reset drops every thread chunk");
+    let fresh = BUMP.try_alloc_local(l).unwrap().as_ptr() as usize;
+    assert_eq!(fresh, start);
+
+    // the global allocator bumps through the chunks, a chunk carved inside a scope counts its
+    // blocks one by one, so the scope rewinds once they are all freed
+    use core::alloc::GlobalAlloc;
+    let mark = BUMP.used_bytes();
+    let worker = || std::thread::spawn(move || {
+        let blocks: Vec<usize> = (0..3).map(|_| unsafe { BUMP.alloc(l) } as usize).collect();
+        assert_eq!(BUMP.thread_free_bytes(), THREAD_CHUNK_BYTES - 48);
+        blocks
+    }).join().unwrap();
+    let (blocks, rewound) = BUMP.scoped(|| {
+        let blocks = worker();
+        for &p in &blocks[1..] {
+            unsafe { BUMP.dealloc(p as *mut u8, l) };
+        }
+        blocks
+    });
+    assert!(!rewound, "one block is still live");
+    assert_eq!(BUMP.used_bytes(), mark + THREAD_CHUNK_BYTES);
+    unsafe { BUMP.dealloc(blocks[0] as *mut u8, l) };
+    assert!(BUMP.scoped(|| ()).1);
+    assert_eq!(BUMP.used_bytes(), mark);
+}
+
+#[test]
+pub fn bounded_global_bump_test() {
+    use core::alloc::GlobalAlloc;
+
+    static mut REGION: [u8; 4096] = [0u8; 4096];
+    static BUMP: GlobalBumpAllocator = GlobalBumpAllocator::with_region(&raw mut REGION).zeroed_region(true);
+    let start = &raw mut REGION as *mut u8 as usize;
+
+    // set up on first use, then refuses cleanly at the end of the region
+    let l = Layout::from_size_align(1000, 8).unwrap();
+    let blocks: Vec<usize> = (0..4).map(|_| unsafe { BUMP.alloc(l) } as usize).collect();
+    assert_eq!(blocks[0], start);
+    assert!(unsafe { BUMP.alloc(l) }.is_null());
+    assert!(unsafe { BUMP.alloc(Layout::from_size_align(usize::MAX / 4, 8).unwrap()) }.is_null());
+    assert_eq!(BUMP.used_bytes(), 4000, "a refused request leaves next alone");
+    assert_eq!(BUMP.free_bytes(), 96);
+    let z = unsafe { BUMP.alloc_zeroed(Layout::from_size_align(64, 8).unwrap()) };
+    assert!(unsafe { core::slice::from_raw_parts(z, 64) }.iter().all(|&b| b == 0));
+    unsafe { BUMP.reset() };
+    assert_eq!(BUMP.free_bytes(), 4096);
+
+    // a batch job that frees everything gets its memory back
+    let first = unsafe { BUMP.alloc(Layout::new::<u64>()) } as usize;
+    let (sum, rewound) = BUMP.scoped(|| {
+        let l = Layout::from_size_align(512, 8).unwrap();
+        let a = unsafe { BUMP.alloc(l) };
+        let b = unsafe { BUMP.alloc(l) };
+        unsafe { a.write_bytes(0xAB, 512) };
+        // freeing memory from before the scope doesn't pay for anything inside it
+        unsafe { BUMP.dealloc(first as *mut u8, Layout::new::<u64>()) };
+        unsafe { BUMP.dealloc(a, l) };
+        unsafe { BUMP.dealloc(b, l) };
+        3
+    });
+    assert_eq!((sum, rewound), (3, true));
+    assert_eq!(BUMP.used_bytes(), 8);
+
+    // recycled memory is cleared for alloc_zeroed
+    let z = unsafe { BUMP.alloc_zeroed(Layout::from_size_align(512, 8).unwrap()) };
+    assert!(unsafe { core::slice::from_raw_parts(z, 512) }.iter().all(|&b| b == 0));
+    unsafe { BUMP.dealloc(z, Layout::from_size_align(512, 8).unwrap()) };
+
+    // a job that keeps a block leaves the scope open, the next scope joins it and rewinds
+    // both once the block is gone
+    let mark = BUMP.used_bytes();
+    let (kept, rewound) = BUMP.scoped(|| unsafe { BUMP.alloc(Layout::new::<[u8; 100]>()) });
+    assert!(!rewound);
+    let (_, rewound) = BUMP.scoped(|| {
+        let p = unsafe { BUMP.alloc(Layout::new::<[u8; 200]>()) };
+        unsafe { BUMP.dealloc(p, Layout::new::<[u8; 200]>()) };
+    });
+    assert!(!rewound);
+    unsafe { BUMP.dealloc(kept, Layout::new::<[u8; 100]>()) };
+    let (_, rewound) = BUMP.scoped(|| ());
+    assert!(rewound);
+    assert_eq!(BUMP.used_bytes(), mark);
+
+    // scopes on several threads rewind once the last of them is done
+    let (_, rewound) = BUMP.scoped(|| {
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    let (_, inner) = BUMP.scoped(|| {
+                        let p = unsafe { BUMP.alloc(Layout::new::<u64>()) };
+                        assert!(!p.is_null());
+                        unsafe { BUMP.dealloc(p, Layout::new::<u64>()) };
+                    });
+                    inner
+                });
+            }
+        });
+    });
+    assert!(rewound);
+    assert_eq!(BUMP.used_bytes(), mark);
+
+    // an allocator without a region has nothing to give
+    static EMPTY: GlobalBumpAllocator = GlobalBumpAllocator::new_const();
+    assert!(unsafe { EMPTY.alloc(Layout::new::<u64>()) }.is_null());
+    assert!(!EMPTY.scoped(|| ()).1);
+
+    // a region that isn't known to be zero gets cleared even where it is fresh
+    static mut DIRTY: [u8; 256] = [0xAA; 256];
+    static DIRTY_BUMP: GlobalBumpAllocator = GlobalBumpAllocator::with_region(&raw mut DIRTY);
+    let z = unsafe { DIRTY_BUMP.alloc_zeroed(Layout::from_size_align(64, 8).unwrap()) };
+    assert!(unsafe { core::slice::from_raw_parts(z, 64) }.iter().all(|&b| b == 0));
+}
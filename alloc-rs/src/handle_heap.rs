@@ -0,0 +1,389 @@
+use core::cell::Cell;
+use core::marker::PhantomData;
+use core::mem::{align_of, needs_drop, size_of};
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::bump::align_up;
+
+// handle based heap that can compact itself.
+// callers keep Handle<T> (an index into the handle table) instead of pointers, the object is
+// only reachable through a borrow guard and pinned while that guard lives. compact() slides
+// every unpinned live object down over the holes that frees left behind and patches the
+// handle table, pinned objects stay where they are and the next object starts after them.
+// same region convention as BumpAllocator, a start address and a size the caller owns:
+// objects bump up from the start, the handle table grows down from the end, the heap is out
+// of memory when the two meet. alloc() compacts once before giving up.
+// the heap is single threaded (Cell based, !Sync), moving objects under a concurrent reader
+// would need a much heavier protocol. objects still alive when the heap drops are dropped
+// then, possibly on another thread and after anything they borrowed, so they are 'static + Send.
+//
+// object layout, every word aligned:
+//   [zero padding words]* [ObjectHeader] [object bytes, rounded up to a word]
+// the first header word is slot + 1, or DEAD once freed, so a zero word is always padding.
+
+const WORD: usize = size_of::<usize>();
+const DEAD: usize = usize::MAX;
+// pins value while a mutable guard is out
+const PINNED_MUT: usize = usize::MAX;
+const NO_SLOT: usize = usize::MAX;
+
+static HEAP_IDS: AtomicUsize = AtomicUsize::new(1);
+
+#[repr(C)]
+struct ObjectHeader {
+    slot: usize,
+    size: usize,
+    align: usize,
+    drop_fn: Option<unsafe fn(usize)>
+}
+
+const HEADER: usize = size_of::<ObjectHeader>();
+
+// handle table entry. addr is the object address, 0 for a free slot whose pins field then
+// links to the next free slot. generation is bumped on free so stale handles miss.
+#[repr(C)]
+struct Slot {
+    addr: usize,
+    pins: usize,
+    generation: usize
+}
+
+unsafe fn drop_one<T>(ptr: usize) {
+    unsafe { core::ptr::drop_in_place(ptr as *mut T) };
+}
+
+pub struct Handle<T> {
+    heap: u32,
+    index: u32,
+    generation: usize,
+    _marker: PhantomData<*const T>
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.heap, self.index, self.generation) == (other.heap, other.index, other.generation)
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> core::fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Handle({}#{})", self.index, self.generation)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CompactStats {
+    pub moved: usize,
+    pub pinned: usize,
+    pub reclaimed_bytes: usize
+}
+
+pub struct HandleHeap {
+    id: u32,
+    heap_start: usize,
+    heap_end: usize,
+    next: Cell<usize>,
+    slots: Cell<usize>,
+    free_slot: Cell<usize>,
+    live: Cell<usize>
+}
+
+impl HandleHeap {
+    /// a heap over heap_size bytes at heap_start, objects and the slot table are written there
+    /// and moved around by compact().
+    ///
+    /// # Safety
+    /// the region has to be valid for reads and writes and belong to this heap alone until the
+    /// heap is dropped.
+    pub unsafe fn from_raw_parts(heap_start: usize, heap_size: usize) -> Self {
+        let start = align_up(heap_start, WORD);
+        let end = heap_start.saturating_add(heap_size) & !(WORD - 1);
+        Self {
+            id: HEAP_IDS.fetch_add(1, Ordering::Relaxed) as u32,
+            heap_start: start,
+            heap_end: end.max(start),
+            next: Cell::new(start),
+            slots: Cell::new(0),
+            free_slot: Cell::new(NO_SLOT),
+            live: Cell::new(0)
+        }
+    }
+
+    pub fn alloc<T: 'static + Send>(&self, value: T) -> Option<Handle<T>> {
+        let slot = match self.take_slot() {
+            Some(slot) => slot,
+            None => {
+                self.compact();
+                self.take_slot()?
+            }
+        };
+        let size = align_up(size_of::<T>(), WORD);
+        let align = align_of::<T>().max(WORD);
+        let header = match self.place(size, align) {
+            Some(header) => header,
+            None => {
+                self.compact();
+                match self.place(size, align) {
+                    Some(header) => header,
+                    None => {
+                        self.release_slot(slot);
+                        return None;
+                    }
+                }
+            }
+        };
+        let drop_fn = if needs_drop::<T>() { Some(drop_one::<T> as unsafe fn(usize)) } else { None };
+        let obj = header + HEADER;
+        unsafe {
+            (header as *mut ObjectHeader).write(ObjectHeader { slot: slot + 1, size, align, drop_fn });
+            (obj as *mut T).write(value);
+            let s = &mut *self.slot_ptr(slot);
+            s.addr = obj;
+            s.pins = 0;
+        }
+        self.live.set(self.live.get() + 1);
+        Some(Handle { heap: self.id, index: slot as u32, generation: unsafe { (*self.slot_ptr(slot)).generation }, _marker: PhantomData })
+    }
+
+    // drops the object, false if the handle is stale or the object is borrowed right now
+    pub fn free<T>(&self, handle: Handle<T>) -> bool {
+        let slot = match self.resolve(&handle) {
+            Some(slot) => slot,
+            None => return false,
+        };
+        let s = unsafe { &mut *self.slot_ptr(slot) };
+        if s.pins != 0 {
+            return false;
+        }
+        let header = unsafe { &mut *((s.addr - HEADER) as *mut ObjectHeader) };
+        if let Some(drop_fn) = header.drop_fn {
+            unsafe { drop_fn(s.addr) };
+        }
+        header.slot = DEAD;
+        self.release_slot(slot);
+        self.live.set(self.live.get() - 1);
+        true
+    }
+
+    // shared borrow, pins the object until the guard is dropped. None for a stale handle or
+    // while a mutable guard is out.
+    pub fn get<T>(&self, handle: &Handle<T>) -> Option<Pinned<'_, T>> {
+        let slot = self.resolve(handle)?;
+        let s = unsafe { &mut *self.slot_ptr(slot) };
+        if s.pins == PINNED_MUT {
+            return None;
+        }
+        s.pins += 1;
+        Some(Pinned { heap: self, slot, ptr: s.addr as *const T })
+    }
+
+    // exclusive borrow, None for a stale handle or while any other guard is out
+    pub fn get_mut<T>(&self, handle: &Handle<T>) -> Option<PinnedMut<'_, T>> {
+        let slot = self.resolve(handle)?;
+        let s = unsafe { &mut *self.slot_ptr(slot) };
+        if s.pins != 0 {
+            return None;
+        }
+        s.pins = PINNED_MUT;
+        Some(PinnedMut { heap: self, slot, ptr: s.addr as *mut T })
+    }
+
+    // slides live unpinned objects down over the holes, in address order.
+    pub fn compact(&self) -> CompactStats {
+        let mut stats = CompactStats::default();
+        let end = self.next.get();
+        let mut cursor = self.heap_start;
+        let mut at = self.heap_start;
+        while at < end {
+            let word = unsafe { (at as *const usize).read() };
+            if word == 0 {
+                at += WORD;
+                continue;
+            }
+            let header = unsafe { (at as *const ObjectHeader).read() };
+            let block_end = at + HEADER + header.size;
+            if header.slot == DEAD {
+                at = block_end;
+                continue;
+            }
+            let slot = unsafe { &mut *self.slot_ptr(header.slot - 1) };
+            if slot.pins != 0 {
+                stats.pinned += 1;
+                self.pad(cursor, at);
+                cursor = block_end;
+            } else {
+                let target = align_up(cursor + HEADER, header.align) - HEADER;
+                if target < at {
+                    self.pad(cursor, target);
+                    unsafe { core::ptr::copy(at as *const u8, target as *mut u8, HEADER + header.size) };
+                    slot.addr = target + HEADER;
+                    stats.moved += 1;
+                }
+                cursor = target + HEADER + header.size;
+            }
+            at = block_end;
+        }
+        stats.reclaimed_bytes = end - cursor;
+        self.next.set(cursor);
+        stats
+    }
+
+    pub fn live_objects(&self) -> usize {
+        self.live.get()
+    }
+
+    // bytes between the object area and the handle table, holes left by frees are not counted
+    pub fn free_bytes(&self) -> usize {
+        self.table_bottom() - self.next.get()
+    }
+
+    pub fn used_bytes(&self) -> usize {
+        self.next.get() - self.heap_start
+    }
+
+    fn resolve<T>(&self, handle: &Handle<T>) -> Option<usize> {
+        let slot = handle.index as usize;
+        if handle.heap != self.id || slot >= self.slots.get() {
+            return None;
+        }
+        let s = unsafe { &*self.slot_ptr(slot) };
+        if s.addr == 0 || s.generation != handle.generation {
+            return None;
+        }
+        Some(slot)
+    }
+
+    // reserves room for a header plus size bytes at align, zero filling the padding in front
+    fn place(&self, size: usize, align: usize) -> Option<usize> {
+        let start = self.next.get();
+        let header = align_up(start + HEADER, align) - HEADER;
+        let end = header.checked_add(HEADER + size)?;
+        if end > self.table_bottom() {
+            return None;
+        }
+        self.pad(start, header);
+        self.next.set(end);
+        Some(header)
+    }
+
+    fn pad(&self, from: usize, to: usize) {
+        let mut at = from;
+        while at < to {
+            unsafe { (at as *mut usize).write(0) };
+            at += WORD;
+        }
+    }
+
+    fn take_slot(&self) -> Option<usize> {
+        let free = self.free_slot.get();
+        if free != NO_SLOT {
+            self.free_slot.set(unsafe { (*self.slot_ptr(free)).pins });
+            return Some(free);
+        }
+        let slot = self.slots.get();
+        if self.table_bottom() - size_of::<Slot>() < self.next.get() {
+            return None;
+        }
+        unsafe { self.slot_ptr(slot).write(Slot { addr: 0, pins: 0, generation: 0 }) };
+        self.slots.set(slot + 1);
+        Some(slot)
+    }
+
+    fn release_slot(&self, slot: usize) {
+        let s = unsafe { &mut *self.slot_ptr(slot) };
+        s.addr = 0;
+        s.generation = s.generation.wrapping_add(1);
+        s.pins = self.free_slot.get();
+        self.free_slot.set(slot);
+    }
+
+    #[inline]
+    fn table_bottom(&self) -> usize {
+        self.heap_end - self.slots.get() * size_of::<Slot>()
+    }
+
+    #[inline]
+    fn slot_ptr(&self, slot: usize) -> *mut Slot {
+        (self.heap_end - (slot + 1) * size_of::<Slot>()) as *mut Slot
+    }
+
+    fn unpin(&self, slot: usize) {
+        let s = unsafe { &mut *self.slot_ptr(slot) };
+        s.pins = if s.pins == PINNED_MUT { 0 } else { s.pins - 1 };
+    }
+}
+
+impl Drop for HandleHeap {
+    fn drop(&mut self) {
+        let end = self.next.get();
+        let mut at = self.heap_start;
+        while at < end {
+            if unsafe { (at as *const usize).read() } == 0 {
+                at += WORD;
+                continue;
+            }
+            let header = unsafe { (at as *const ObjectHeader).read() };
+            if header.slot != DEAD && let Some(drop_fn) = header.drop_fn {
+                unsafe { drop_fn(at + HEADER) };
+            }
+            at += HEADER + header.size;
+        }
+    }
+}
+
+pub struct Pinned<'a, T> {
+    heap: &'a HandleHeap,
+    slot: usize,
+    ptr: *const T
+}
+
+impl<T> Deref for Pinned<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.ptr }
+    }
+}
+
+impl<T> Drop for Pinned<'_, T> {
+    fn drop(&mut self) {
+        self.heap.unpin(self.slot);
+    }
+}
+
+pub struct PinnedMut<'a, T> {
+    heap: &'a HandleHeap,
+    slot: usize,
+    ptr: *mut T
+}
+
+impl<T> Deref for PinnedMut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.ptr }
+    }
+}
+
+impl<T> DerefMut for PinnedMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.ptr }
+    }
+}
+
+impl<T> Drop for PinnedMut<'_, T> {
+    fn drop(&mut self) {
+        self.heap.unpin(self.slot);
+    }
+}
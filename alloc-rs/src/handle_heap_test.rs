@@ -0,0 +1,60 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::handle_heap::HandleHeap;
+use crate::test_helpers::CountsDrops;
+
+#[test]
+pub fn handle_heap_compacts_around_pins() {
+    static mut HEAP: [u64; 128] = [0u64; 128];
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+    let drops = || DROPS.load(Ordering::Relaxed);
+
+    let heap = unsafe { HandleHeap::from_raw_parts(&raw mut HEAP as *mut u8 as usize, 1024) };
+    let a = heap.alloc(1u64).unwrap();
+    let b = heap.alloc([2u64; 4]).unwrap();
+    let c = heap.alloc((CountsDrops(&DROPS), 3u64)).unwrap();
+    let d = heap.alloc(4u64).unwrap();
+
+    let c_before = heap.get(&c).map(|p| &*p as *const (CountsDrops, u64) as usize).unwrap();
+    assert!(heap.free(a));
+    assert!(!heap.free(a), "stale handle");
+    {
+        // b is pinned, so only the objects after it can move
+        let pinned_b = heap.get(&b).unwrap();
+        assert!(!heap.free(b), "borrowed objects can't be freed");
+        assert!(heap.get_mut(&b).is_none());
+        let stats = heap.compact();
+        assert_eq!((stats.moved, stats.pinned, stats.reclaimed_bytes), (0, 1, 0));
+        assert_eq!(*pinned_b, [2; 4]);
+    }
+    assert!(heap.free(b));
+    let used = heap.used_bytes();
+    let stats = heap.compact();
+    assert_eq!(stats.moved, 2);
+    assert!(stats.reclaimed_bytes > 0 && heap.used_bytes() == used - stats.reclaimed_bytes);
+    let c_after = heap.get(&c).map(|p| &*p as *const (CountsDrops, u64) as usize).unwrap();
+    assert!(c_after < c_before);
+    assert_eq!(heap.get(&c).unwrap().1, 3);
+    *heap.get_mut(&d).unwrap() += 10;
+    assert_eq!(*heap.get(&d).unwrap(), 14);
+    assert_eq!(drops(), 0, "moving never drops");
+
+    // a fresh handle in a recycled slot doesn't answer to the old one
+    let e = heap.alloc(5u64).unwrap();
+    assert!(heap.get(&a).is_none() && *heap.get(&e).unwrap() == 5);
+
+    // fill up, then free everything but the last object, alloc compacts by itself
+    let mut filler = Vec::new();
+    while let Some(h) = heap.alloc([0u64; 8]) {
+        filler.push(h);
+    }
+    let last = filler.pop().unwrap();
+    for h in filler {
+        assert!(heap.free(h));
+    }
+    assert!(heap.alloc([7u64; 8]).is_some());
+    assert!(heap.get(&last).is_some());
+
+    drop(heap);
+    assert_eq!(drops(), 1);
+}
@@ -1,15 +1,97 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 
+#[macro_use]
+mod sync;
+
+#[cfg(all(test, not(loom)))]
+mod test_helpers;
+
 pub mod bump;
+#[cfg(not(loom))]
 pub mod bump_test;
 
+pub mod arena;
+#[cfg(all(test, not(loom)))]
+pub mod arena_test;
+
 pub mod global_bump;
+#[cfg(all(test, not(loom)))]
+pub mod global_bump_test;
 
 
 pub mod slab;
+#[cfg(not(loom))]
 pub mod slab_test;
 
+pub mod object_cache;
+#[cfg(all(test, not(loom)))]
+pub mod object_cache_test;
+
 
 
 pub mod composite;
-pub mod composite_test;
\ No newline at end of file
+#[cfg(not(loom))]
+pub mod composite_test;
+
+pub mod oom;
+#[cfg(all(test, not(loom)))]
+pub mod oom_test;
+
+#[cfg(all(feature = "std", target_os = "linux", not(loom)))]
+pub mod cabi;
+#[cfg(all(test, not(loom), target_os = "linux"))]
+pub mod cabi_test;
+
+#[cfg(all(feature = "std", target_os = "linux", not(loom)))]
+pub mod mmap_tier;
+#[cfg(all(test, not(loom), target_os = "linux"))]
+pub mod mmap_tier_test;
+
+#[cfg(all(feature = "std", target_os = "linux", not(loom)))]
+pub mod persistent;
+#[cfg(all(test, not(loom), target_os = "linux"))]
+pub mod persistent_test;
+
+#[cfg(all(feature = "std", target_os = "linux", not(loom)))]
+pub mod shm;
+#[cfg(all(test, not(loom), target_os = "linux"))]
+pub mod shm_test;
+
+#[cfg(feature = "std")]
+pub mod snapshot;
+
+pub mod tagging;
+#[cfg(all(test, not(loom)))]
+pub mod tagging_test;
+
+#[cfg(all(feature = "std", target_os = "linux"))]
+pub mod profiler;
+#[cfg(all(test, not(loom), target_os = "linux"))]
+pub mod profiler_test;
+
+pub mod handle_heap;
+#[cfg(all(test, not(loom)))]
+pub mod handle_heap_test;
+
+pub mod gc;
+#[cfg(all(test, not(loom)))]
+pub mod gc_test;
+
+pub mod epoch;
+#[cfg(all(test, not(loom)))]
+pub mod epoch_test;
+
+#[cfg(all(test, loom))]
+mod loom_test;
+
+pub mod stack;
+#[cfg(all(test, not(loom)))]
+pub mod stack_test;
+
+pub mod frame_ring;
+#[cfg(all(test, not(loom)))]
+pub mod frame_ring_test;
+
+pub mod paging;
+#[cfg(all(test, not(loom)))]
+pub mod paging_test;
@@ -0,0 +1,167 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::NonNull;
+
+use loom::sync::Arc;
+use loom::thread;
+
+use crate::composite::{CompositeAllocator, Tier};
+use crate::global_bump::GlobalBumpAllocator;
+use crate::slab::Slab;
+
+// run with RUSTFLAGS="--cfg loom" cargo test --release loom_test
+// the allocators do a lot of atomic steps per call, bounding preemptions keeps the models
+// small enough to finish while still covering the interesting interleavings.
+
+// page aligned zeroed memory from the system allocator, the model threads' stacks are too
+// small to hold a region
+struct Region {
+    start: usize,
+    layout: Layout
+}
+
+impl Region {
+    fn new(size: usize) -> Self {
+        let layout = Layout::from_size_align(size, 4096).unwrap();
+        Self { start: unsafe { std::alloc::alloc_zeroed(layout) } as usize, layout }
+    }
+}
+
+impl Drop for Region {
+    fn drop(&mut self) {
+        unsafe { std::alloc::dealloc(self.start as *mut u8, self.layout) };
+    }
+}
+
+fn model(f: impl Fn() + Sync + Send + 'static) {
+    let mut builder = loom::model::Builder::new();
+    builder.preemption_bound = Some(3);
+    builder.check(f);
+}
+
+#[test]
+pub fn loom_global_bump_concurrent_init() {
+    model(|| {
+        let region = Region::new(4096);
+        let bump = Arc::new(GlobalBumpAllocator::new_const());
+        let start = region.start;
+        let threads: Vec<_> = (0..2).map(|_| {
+            let bump = bump.clone();
+            thread::spawn(move || {
+                bump.ensure_init(start, start + 4096);
+                // whoever returns from ensure_init has to see a fully set up allocator
+                assert_eq!(bump.used_bytes(), 0);
+                assert_eq!(bump.free_bytes(), 4096);
+            })
+        }).collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+    });
+}
+
+#[test]
+pub fn loom_global_bump_reset_races_alloc() {
+    model(|| {
+        let region = Region::new(4096);
+        let bump = Arc::new(GlobalBumpAllocator::new_const());
+        let start = region.start;
+        bump.ensure_init(start, start + 4096);
+        let layout = Layout::from_size_align(16, 8).unwrap();
+        bump.try_alloc(layout).unwrap();
+
+        let allocator = {
+            let bump = bump.clone();
+            thread::spawn(move || bump.try_alloc(layout).unwrap().as_ptr() as usize)
+        };
+        unsafe { bump.reset() };
+        let p = allocator.join().unwrap();
+        // the allocation lands either before the reset (second block) or after it (first block)
+        assert!(p == start || p == start + 16);
+        let used = bump.used_bytes();
+        assert!(used == 0 || used == 16, "p {} used {used}", p - start);
+    });
+}
+
+#[test]
+pub fn loom_global_bump_scope_rewind_races_alloc() {
+    model(|| {
+        let region = Region::new(4096);
+        let bump = Arc::new(GlobalBumpAllocator::new_const());
+        let start = region.start;
+        bump.ensure_init(start, start + 4096);
+        let layout = Layout::from_size_align(16, 8).unwrap();
+
+        let allocator = {
+            let bump = bump.clone();
+            thread::spawn(move || bump.try_alloc(layout).unwrap().as_ptr() as usize)
+        };
+        bump.scoped(|| {
+            let p = bump.try_alloc(layout).unwrap().as_ptr();
+            bump.release(p);
+        });
+        let kept = allocator.join().unwrap();
+        // the other thread's block is still live, a rewind must not hand it out again
+        let after = bump.try_alloc(layout).unwrap().as_ptr() as usize;
+        assert_ne!(after, kept);
+    });
+}
+
+#[test]
+pub fn loom_slab_concurrent_pop_push() {
+    model(|| {
+        let page = Region::new(4096);
+        let slab = Arc::new(Slab::new_rounded(512));
+        unsafe { slab.grow(page.start, 4096) };
+        let capacity = slab.debug_count_free();
+
+        let threads: Vec<_> = (0..2usize).map(|id| {
+            let slab = slab.clone();
+            thread::spawn(move || unsafe {
+                let block = slab.alloc().unwrap().as_ptr() as *mut usize;
+                block.write_volatile(id + 1);
+                thread::yield_now();
+                // nobody else got handed the same block
+                assert_eq!(block.read_volatile(), id + 1);
+                slab.dealloc(NonNull::new_unchecked(block as *mut u8));
+            })
+        }).collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(slab.debug_count_free(), capacity);
+        assert_eq!(slab.live_count(), 0);
+        drop(page);
+    });
+}
+
+#[test]
+pub fn loom_composite_concurrent_init() {
+    model(|| {
+        let heap = Region::new(4 * 4096);
+        let alloc: &'static CompositeAllocator = Box::leak(Box::new(unsafe { CompositeAllocator::builder().heap(heap.start as *mut [u8; 4 * 4096]) }
+            .slab_region_bytes(4096)
+            .size_classes(&[64])
+            .build()));
+
+        let threads: Vec<_> = (0..2usize).map(|id| {
+            thread::spawn(move || unsafe {
+                let layout = Layout::from_size_align(64, 8).unwrap();
+                // the first alloc runs ensure_init, the loser has to wait for it to finish
+                let block = alloc.alloc(layout) as *mut usize;
+                assert!(!block.is_null());
+                assert_eq!(alloc.tier_of(block as *mut u8), Tier::Slab { block_size: 64 });
+                block.write_volatile(id + 1);
+                thread::yield_now();
+                assert_eq!(block.read_volatile(), id + 1);
+                alloc.dealloc(block as *mut u8, layout);
+            })
+        }).collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(alloc.slabs[0].live_count(), 0);
+
+        unsafe { drop(Box::from_raw(alloc as *const CompositeAllocator as *mut CompositeAllocator)) };
+        drop(heap);
+    });
+}
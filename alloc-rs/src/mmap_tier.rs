@@ -0,0 +1,182 @@
+use core::alloc::Layout;
+use core::ffi::c_void;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+// direct mmap tier for large allocations (std, linux). every allocation at or above the
+// composite's mmap threshold gets an anonymous mapping of its own: munmap hands it straight back
+// to the kernel on dealloc and mremap grows or shrinks it in place or by moving pages, no copy.
+// dealloc only has the pointer, so the live mappings are kept in a fixed side table (the
+// allocator can't allocate its own bookkeeping). when the table is full the allocation goes to
+// the regular tiers instead. mappings are page aligned, bigger alignments over map and trim.
+
+pub const MMAP_PAGE_SIZE: usize = 4096;
+pub const MAX_MAPPINGS: usize = 256;
+
+const PROT_READ: i32 = 1;
+const PROT_WRITE: i32 = 2;
+const MAP_PRIVATE: i32 = 2;
+const MAP_ANONYMOUS: i32 = 0x20;
+const MREMAP_MAYMOVE: i32 = 1;
+const MAP_FAILED: *mut c_void = !0usize as *mut c_void;
+
+unsafe extern "C" {
+    fn mmap(addr: *mut c_void, len: usize, prot: i32, flags: i32, fd: i32, offset: i64) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> i32;
+    fn mremap(old: *mut c_void, old_len: usize, new_len: usize, flags: i32, ...) -> *mut c_void;
+}
+
+struct Mapping {
+    // 0 for a free slot
+    addr: AtomicUsize,
+    len: AtomicUsize
+}
+
+pub struct MmapTier {
+    table: [Mapping; MAX_MAPPINGS],
+    mapped_bytes: AtomicUsize,
+    mappings: AtomicUsize
+}
+
+impl MmapTier {
+    pub const fn new() -> Self {
+        Self {
+            table: [const { Mapping { addr: AtomicUsize::new(0), len: AtomicUsize::new(0) } }; MAX_MAPPINGS],
+            mapped_bytes: AtomicUsize::new(0),
+            mappings: AtomicUsize::new(0)
+        }
+    }
+
+    // null if the kernel says no or the side table is full
+    pub fn alloc(&self, layout: Layout) -> *mut u8 {
+        let len = match layout.size().checked_add(MMAP_PAGE_SIZE - 1) {
+            Some(len) => len & !(MMAP_PAGE_SIZE - 1),
+            None => return core::ptr::null_mut(),
+        };
+        let extra = if layout.align() > MMAP_PAGE_SIZE { layout.align() } else { 0 };
+        let total = match len.checked_add(extra) {
+            Some(total) => total,
+            None => return core::ptr::null_mut(),
+        };
+        let base = unsafe { mmap(core::ptr::null_mut(), total, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0) };
+        if base == MAP_FAILED {
+            return core::ptr::null_mut();
+        }
+        let base = base as usize;
+        let addr = crate::bump::align_up(base, layout.align().max(MMAP_PAGE_SIZE));
+        // trim the over mapping on both sides, both are whole pages
+        unsafe {
+            if addr > base {
+                munmap(base as *mut c_void, addr - base);
+            }
+            if base + total > addr + len {
+                munmap((addr + len) as *mut c_void, base + total - addr - len);
+            }
+        }
+        if !self.insert(addr, len) {
+            unsafe { munmap(addr as *mut c_void, len) };
+            return core::ptr::null_mut();
+        }
+        addr as *mut u8
+    }
+
+    // false if ptr isn't one of ours, nothing unmapped then
+    pub fn dealloc(&self, ptr: *mut u8) -> bool {
+        let Some(slot) = self.find(ptr as usize) else {
+            return false;
+        };
+        let len = slot.len.load(Ordering::Acquire);
+        slot.addr.store(0, Ordering::Release);
+        self.mapped_bytes.fetch_sub(len, Ordering::Relaxed);
+        self.mappings.fetch_sub(1, Ordering::Relaxed);
+        unsafe { munmap(ptr as *mut c_void, len) };
+        true
+    }
+
+    // resizes a mapping of ours, the contents move with it. null (and the old mapping intact)
+    // if ptr isn't ours or mremap fails. only for page aligned mappings, a move may not keep
+    // a bigger alignment.
+    pub fn realloc(&self, ptr: *mut u8, new_size: usize) -> *mut u8 {
+        let Some(slot) = self.find(ptr as usize) else {
+            return core::ptr::null_mut();
+        };
+        let new_len = match new_size.checked_add(MMAP_PAGE_SIZE - 1) {
+            Some(len) => len & !(MMAP_PAGE_SIZE - 1),
+            None => return core::ptr::null_mut(),
+        };
+        let old_len = slot.len.load(Ordering::Acquire);
+        if new_len == old_len {
+            return ptr;
+        }
+        let moved = unsafe { mremap(ptr as *mut c_void, old_len, new_len, MREMAP_MAYMOVE) };
+        if moved == MAP_FAILED {
+            return core::ptr::null_mut();
+        }
+        // only the owner of ptr touches its slot, nobody else looks up the new address yet
+        slot.len.store(new_len, Ordering::Release);
+        slot.addr.store(moved as usize, Ordering::Release);
+        if new_len > old_len {
+            self.mapped_bytes.fetch_add(new_len - old_len, Ordering::Relaxed);
+        } else {
+            self.mapped_bytes.fetch_sub(old_len - new_len, Ordering::Relaxed);
+        }
+        moved as *mut u8
+    }
+
+    pub fn owns(&self, ptr: *mut u8) -> bool {
+        self.find(ptr as usize).is_some()
+    }
+
+    // length of the mapping ptr starts, None if it isn't ours
+    pub fn len_of(&self, ptr: *mut u8) -> Option<usize> {
+        self.find(ptr as usize).map(|slot| slot.len.load(Ordering::Acquire))
+    }
+
+    // bytes currently mapped, rounded up to pages
+    pub fn mapped_bytes(&self) -> usize {
+        self.mapped_bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn mappings(&self) -> usize {
+        self.mappings.load(Ordering::Relaxed)
+    }
+
+    fn insert(&self, addr: usize, len: usize) -> bool {
+        for slot in &self.table {
+            if slot.addr.load(Ordering::Relaxed) != 0 {
+                continue;
+            }
+            // a racing insert can take the slot between the check and the cas, then move on.
+            // len is written before addr is published, a slot nobody owns is never looked at
+            if slot.addr.compare_exchange(0, 1, Ordering::AcqRel, Ordering::Relaxed).is_ok() {
+                slot.len.store(len, Ordering::Release);
+                slot.addr.store(addr, Ordering::Release);
+                self.mapped_bytes.fetch_add(len, Ordering::Relaxed);
+                self.mappings.fetch_add(1, Ordering::Relaxed);
+                return true;
+            }
+        }
+        false
+    }
+
+    fn find(&self, addr: usize) -> Option<&Mapping> {
+        if addr == 0 || addr & (MMAP_PAGE_SIZE - 1) != 0 {
+            return None;
+        }
+        self.table.iter().find(|slot| slot.addr.load(Ordering::Acquire) == addr)
+    }
+}
+
+// a plain anonymous mapping outside any table, for heaps that are set up at runtime.
+// MAP_NORESERVE: a big heap only costs the pages that get touched
+#[cfg(feature = "cabi")]
+pub(crate) fn map_region(len: usize) -> *mut u8 {
+    const MAP_NORESERVE: i32 = 0x4000;
+    let p = unsafe { mmap(core::ptr::null_mut(), len, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS | MAP_NORESERVE, -1, 0) };
+    if p == MAP_FAILED { core::ptr::null_mut() } else { p as *mut u8 }
+}
+
+impl Default for MmapTier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,53 @@
+use core::alloc::{GlobalAlloc, Layout};
+
+use crate::composite::{CompositeAllocator, DEFAULT_MMAP_THRESHOLD};
+
+#[test]
+pub fn mmap_tier_test() {
+    static mut HEAP: [u8; 64 * 1024] = [0u8; 64 * 1024];
+    static ALLOC: CompositeAllocator = unsafe { CompositeAllocator::builder().heap(&raw mut HEAP) }
+        .slab_region_bytes(8 * 1024)
+        .size_classes(&[16, 64])
+        .build();
+    let heap_start = &raw mut HEAP as usize;
+    let in_heap = |p: *mut u8| (p as usize) >= heap_start && (p as usize) < heap_start + 64 * 1024;
+
+    // way past what the 64 KiB heap could hold
+    let big = Layout::from_size_align(DEFAULT_MMAP_THRESHOLD * 2, 8).unwrap();
+    let p = unsafe { ALLOC.alloc(big) };
+    assert!(!p.is_null() && !in_heap(p));
+    assert!(ALLOC.mmap.owns(p));
+    assert_eq!((ALLOC.mmap.mappings(), ALLOC.mmap.mapped_bytes()), (1, big.size()));
+    unsafe {
+        p.write_bytes(0x5a, big.size());
+        // grows through mremap, the contents come along
+        let grown = ALLOC.realloc(p, big, big.size() * 4);
+        assert!(!grown.is_null());
+        assert_eq!(ALLOC.mmap.mapped_bytes(), big.size() * 4);
+        assert_eq!((grown.read(), grown.add(big.size() - 1).read()), (0x5a, 0x5a));
+        grown.add(big.size() * 4 - 1).write(1);
+        // shrinking below the threshold moves it into the heap
+        let small = ALLOC.realloc(grown, Layout::from_size_align(big.size() * 4, 8).unwrap(), 32);
+        assert!(in_heap(small));
+        assert_eq!(small.read(), 0x5a);
+        assert_eq!(ALLOC.mmap.mappings(), 0);
+        ALLOC.dealloc(small, Layout::from_size_align(32, 8).unwrap());
+    }
+
+    // over aligned mappings keep their alignment
+    let aligned = Layout::from_size_align(DEFAULT_MMAP_THRESHOLD, 1 << 16).unwrap();
+    let q = unsafe { ALLOC.alloc(aligned) };
+    assert_eq!(q as usize % (1 << 16), 0);
+    assert_eq!(ALLOC.mmap.mapped_bytes(), DEFAULT_MMAP_THRESHOLD);
+    unsafe { ALLOC.dealloc(q, aligned) };
+    assert_eq!((ALLOC.mmap.mappings(), ALLOC.mmap.mapped_bytes()), (0, 0));
+
+    // heap pointers are left to the other tiers
+    let r = unsafe { ALLOC.alloc(Layout::from_size_align(64, 8).unwrap()) };
+    assert!(in_heap(r) && !ALLOC.mmap.owns(r));
+    unsafe { ALLOC.dealloc(r, Layout::from_size_align(64, 8).unwrap()) };
+
+    // the global allocator now takes buffers bigger than its whole 1 MiB heap
+    let buffer = vec![7u8; 4 << 20];
+    assert_eq!(buffer.iter().map(|&b| b as usize).sum::<usize>(), 7 * (4 << 20));
+}
@@ -0,0 +1,250 @@
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::slab::Slab;
+
+// bonwick style object cache on top of the slab.
+// objects are handed back to the cache still constructed, the destructor only runs when the
+// cache shrinks, so a recycled object skips both the ctor and the dtor.
+// every block carries one header word in front of the object, the constructed free list is
+// threaded through that word so the object bytes stay untouched while cached. objects are word
+// aligned, with_align() pads the header out to the object's alignment and aligns the blocks.
+// the slab free list on the other hand overwrites the first word of the block, which is the
+// header again, so raw blocks never clobber object state either.
+
+const MIN_HEADER: usize = core::mem::size_of::<usize>();
+
+pub type ObjectHook = unsafe fn(NonNull<u8>);
+
+// registry of all registered caches, intrusive lifo list through next_cache
+static CACHES: AtomicUsize = AtomicUsize::new(0);
+
+pub struct ObjectCache {
+    name: &'static str,
+    object_size: usize,
+    // bytes in front of the object, the free list word plus padding up to its alignment
+    header: usize,
+    ctor: Option<ObjectHook>,
+    dtor: Option<ObjectHook>,
+    slab: Slab,
+    capacity: usize,
+    constructed: AtomicUsize,
+    active: AtomicUsize,
+    cached: AtomicUsize,
+    allocs: AtomicUsize,
+    frees: AtomicUsize,
+    ctor_calls: AtomicUsize,
+    dtor_calls: AtomicUsize,
+    registered: AtomicBool,
+    next_cache: AtomicUsize
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub name: &'static str,
+    pub object_size: usize,
+    pub block_size: usize,
+    pub capacity: usize,
+    pub active: usize,
+    pub cached: usize,
+    pub allocs: usize,
+    pub frees: usize,
+    pub ctor_calls: usize,
+    pub dtor_calls: usize
+}
+
+unsafe impl Sync for ObjectCache {}
+
+impl ObjectCache {
+    loom_const_fn! {
+    pub const fn new(name: &'static str, object_size: usize, ctor: Option<ObjectHook>, dtor: Option<ObjectHook>) -> Self {
+        Self {
+            name,
+            object_size,
+            header: MIN_HEADER,
+            ctor,
+            dtor,
+            slab: Slab::new_rounded(object_size + MIN_HEADER),
+            capacity: 0,
+            constructed: AtomicUsize::new(0),
+            active: AtomicUsize::new(0),
+            cached: AtomicUsize::new(0),
+            allocs: AtomicUsize::new(0),
+            frees: AtomicUsize::new(0),
+            ctor_calls: AtomicUsize::new(0),
+            dtor_calls: AtomicUsize::new(0),
+            registered: AtomicBool::new(false),
+            next_cache: AtomicUsize::new(0)
+        }
+    }
+    }
+
+    loom_const_fn! {
+    // objects aligned to align (align_of::<T>() for a cache of T), before init_region()
+    pub const fn with_align(mut self, align: usize) -> Self {
+        self.header = if align > MIN_HEADER { align } else { MIN_HEADER };
+        self.slab = Slab::new_rounded(self.object_size + self.header).with_align(align);
+        self
+    }
+    }
+
+    /// # Safety
+    /// same contract as Slab::init_region, the region belongs to the cache for its whole lifetime.
+    pub unsafe fn init_region(&mut self, start: usize, size: usize) {
+        unsafe { self.slab.init_region(start, size) };
+        self.capacity = self.slab.debug_count_free();
+    }
+
+    /// # Safety
+    /// init_region() must have run. hands out a constructed object, recycled ones come back in
+    /// whatever state they were freed in.
+    pub unsafe fn alloc(&self) -> Option<NonNull<u8>> {
+        let block = match self.pop_constructed() {
+            Some(block) => {
+                self.cached.fetch_sub(1, Ordering::Relaxed);
+                block
+            },
+            None => {
+                let block = unsafe { self.slab.alloc()? }.as_ptr() as usize;
+                if let Some(ctor) = self.ctor {
+                    unsafe { ctor(self.object_of(block)) };
+                    self.ctor_calls.fetch_add(1, Ordering::Relaxed);
+                }
+                block
+            }
+        };
+        self.allocs.fetch_add(1, Ordering::Relaxed);
+        self.active.fetch_add(1, Ordering::Relaxed);
+        Some(self.object_of(block))
+    }
+
+    /// # Safety
+    /// obj must come from alloc() on this cache and must be back in its constructed state,
+    /// the dtor is deferred to shrink().
+    pub unsafe fn dealloc(&self, obj: NonNull<u8>) {
+        let block = obj.as_ptr() as usize - self.header;
+        debug_assert!(self.slab.owns(block));
+        self.push_constructed(block);
+        self.frees.fetch_add(1, Ordering::Relaxed);
+        self.active.fetch_sub(1, Ordering::Relaxed);
+        self.cached.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // destroys every cached object and gives its block back to the slab, returns how many were destroyed.
+    pub fn shrink(&self) -> usize {
+        let mut destroyed = 0;
+        while let Some(block) = self.pop_constructed() {
+            self.cached.fetch_sub(1, Ordering::Relaxed);
+            if let Some(dtor) = self.dtor {
+                unsafe { dtor(self.object_of(block)) };
+                self.dtor_calls.fetch_add(1, Ordering::Relaxed);
+            }
+            unsafe { self.slab.dealloc(NonNull::new_unchecked(block as *mut u8)) };
+            destroyed += 1;
+        }
+        destroyed
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            name: self.name,
+            object_size: self.object_size,
+            block_size: self.slab.block_size(),
+            capacity: self.capacity,
+            active: self.active.load(Ordering::Relaxed),
+            cached: self.cached.load(Ordering::Relaxed),
+            allocs: self.allocs.load(Ordering::Relaxed),
+            frees: self.frees.load(Ordering::Relaxed),
+            ctor_calls: self.ctor_calls.load(Ordering::Relaxed),
+            dtor_calls: self.dtor_calls.load(Ordering::Relaxed)
+        }
+    }
+
+    // adds the cache to the global registry, registering twice is a no-op.
+    pub fn register(&'static self) {
+        if self.registered.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let me = self as *const Self as usize;
+        loop {
+            let head = CACHES.load(Ordering::Acquire);
+            self.next_cache.store(head, Ordering::Relaxed);
+            match CACHES.compare_exchange(head, me, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return,
+                Err(_) => {
+                    core::hint::spin_loop();
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn object_of(&self, block: usize) -> NonNull<u8> {
+        unsafe { NonNull::new_unchecked((block + self.header) as *mut u8) }
+    }
+
+    fn pop_constructed(&self) -> Option<usize> {
+        loop {
+            let head = self.constructed.load(Ordering::Acquire);
+            if head == 0 {
+                return None;
+            }
+            let next = unsafe { (head as *const usize).read() };
+            match self.constructed.compare_exchange(head, next, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return Some(head),
+                Err(_) => {
+                    core::hint::spin_loop();
+                }
+            }
+        }
+    }
+
+    fn push_constructed(&self, block: usize) {
+        loop {
+            let head = self.constructed.load(Ordering::Acquire);
+            unsafe { (block as *mut usize).write(head) };
+            match self.constructed.compare_exchange(head, block, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return,
+                Err(_) => {
+                    core::hint::spin_loop();
+                }
+            }
+        }
+    }
+}
+
+impl Drop for ObjectCache {
+    fn drop(&mut self) {
+        self.shrink();
+    }
+}
+
+// walks every registered cache, newest first
+pub fn caches() -> impl Iterator<Item = &'static ObjectCache> {
+    let mut cursor = CACHES.load(Ordering::Acquire);
+    core::iter::from_fn(move || {
+        if cursor == 0 {
+            return None;
+        }
+        let cache = unsafe { &*(cursor as *const ObjectCache) };
+        cursor = cache.next_cache.load(Ordering::Acquire);
+        Some(cache)
+    })
+}
+
+// one line per registered cache, laid out like /proc/slabinfo
+#[cfg(feature = "std")]
+pub fn slabinfo() -> String {
+    use core::fmt::Write;
+    let mut out = String::from("# name            <active_objs> <cached_objs> <num_objs> <objsize> <blocksize> <ctor_calls> <dtor_calls>\n");
+    for cache in caches() {
+        let s = cache.stats();
+        let _ = writeln!(out, "{:<17} {:>13} {:>13} {:>10} {:>9} {:>11} {:>12} {:>12}",
+            s.name, s.active, s.cached, s.capacity, s.object_size, s.block_size, s.ctor_calls, s.dtor_calls);
+    }
+    out
+}
@@ -0,0 +1,87 @@
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::object_cache::{self, ObjectCache};
+
+#[test]
+pub fn object_cache_recycles_constructed_objects() {
+    #[repr(C, align(4096))]
+    struct Page([u8; 4096]);
+    static mut HEAP: Page = Page([0u8; 4096]);
+    static CTORS: AtomicUsize = AtomicUsize::new(0);
+    static DTORS: AtomicUsize = AtomicUsize::new(0);
+
+    unsafe fn ctor(obj: NonNull<u8>) {
+        CTORS.fetch_add(1, Ordering::Relaxed);
+        unsafe { (obj.as_ptr() as *mut u64).write(0xC0FFEE) };
+    }
+    unsafe fn dtor(obj: NonNull<u8>) {
+        DTORS.fetch_add(1, Ordering::Relaxed);
+        unsafe { (obj.as_ptr() as *mut u64).write(0) };
+    }
+
+    let heap_addr = unsafe { &raw mut HEAP as *mut u8 as usize };
+    let cache: &'static mut ObjectCache = Box::leak(Box::new(ObjectCache::new("conn", 24, Some(ctor), Some(dtor))));
+    unsafe { cache.init_region(heap_addr, 4096) };
+    cache.register();
+    cache.register();
+
+    let a = unsafe { cache.alloc().expect("should alloc a") };
+    let b = unsafe { cache.alloc().expect("should alloc b") };
+    assert_eq!(unsafe { (a.as_ptr() as *const u64).read() }, 0xC0FFEE);
+    assert_eq!(CTORS.load(Ordering::Relaxed), 2);
+
+    unsafe { (b.as_ptr() as *mut u64).write(42) };
+    unsafe { cache.dealloc(b) };
+    let c = unsafe { cache.alloc().expect("should alloc c") };
+    assert_eq!(c, b, "freed object should be recycled first");
+    assert_eq!(unsafe { (c.as_ptr() as *const u64).read() }, 42, "recycled object keeps its state");
+    assert_eq!(CTORS.load(Ordering::Relaxed), 2, "recycling must not run the ctor");
+
+    unsafe { cache.dealloc(a) };
+    unsafe { cache.dealloc(c) };
+    let stats = cache.stats();
+    assert_eq!((stats.active, stats.cached, stats.allocs, stats.frees), (0, 2, 3, 3));
+    assert_eq!(stats.block_size, 32);
+    assert!(stats.capacity > 0 && stats.capacity < 4096 / 32);
+
+    assert_eq!(cache.shrink(), 2);
+    assert_eq!(DTORS.load(Ordering::Relaxed), 2);
+    assert_eq!(cache.stats().cached, 0);
+
+    assert_eq!(object_cache::caches().filter(|c| c.name() == "conn").count(), 1);
+    assert!(object_cache::slabinfo().lines().any(|l| l.starts_with("conn ")));
+}
+
+#[test]
+pub fn object_cache_keeps_object_alignment() {
+    #[repr(C, align(4096))]
+    struct Page([u8; 4096]);
+    static mut HEAP: Page = Page([0u8; 4096]);
+    #[repr(align(16))]
+    struct Vec4([f32; 4]);
+
+    let heap_addr = &raw mut HEAP as *mut u8 as usize;
+    let mut cache = ObjectCache::new("vec4", size_of::<Vec4>(), None, None).with_align(align_of::<Vec4>());
+    unsafe { cache.init_region(heap_addr, 4096) };
+    assert_eq!(cache.stats().block_size, 32);
+
+    // every object of the page, not just the first
+    let capacity = cache.stats().capacity;
+    for _ in 0..capacity {
+        let obj = unsafe { cache.alloc().expect("should alloc") };
+        assert_eq!(obj.as_ptr() as usize % align_of::<Vec4>(), 0);
+        unsafe { (obj.as_ptr() as *mut Vec4).write(Vec4([1.0; 4])) };
+    }
+    assert!(unsafe { cache.alloc() }.is_none());
+
+    // a size that isn't a power of two is aligned too
+    let mut odd = ObjectCache::new("vec8", 2 * size_of::<Vec4>(), None, None).with_align(align_of::<Vec4>());
+    static mut ODD_HEAP: Page = Page([0u8; 4096]);
+    unsafe { odd.init_region(&raw mut ODD_HEAP as *mut u8 as usize, 4096) };
+    assert_eq!(odd.stats().block_size, 48);
+    for _ in 0..odd.stats().capacity {
+        let obj = unsafe { odd.alloc().unwrap() };
+        assert_eq!(obj.as_ptr() as usize % align_of::<Vec4>(), 0);
+    }
+}
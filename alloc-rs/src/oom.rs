@@ -0,0 +1,146 @@
+use core::alloc::Layout;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+// what the composite does once the heap can't serve a request, instead of handing back null
+// right away. first the registered handler gets a go: it can drop whatever it caches (rawnet's
+// arp cache, pooled rx buffers) and ask for a retry, up to OOM_RETRIES times per request.
+// after that the emergency reserve, a slice of the heap set aside at init, is released for
+// good so the error path (reporting, logging) can still allocate. the reserve is a plain bump,
+// its blocks are never recycled.
+// the handler runs one at a time, an allocation failing while it runs (the handler's own
+// included) skips it instead of recursing.
+
+pub const OOM_RETRIES: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OomAction {
+    // something was freed, try the allocation again
+    Retry,
+    // nothing left to give back
+    Fail
+}
+
+pub type OomHandler = fn(Layout) -> OomAction;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OomStats {
+    // requests that ran out of heap
+    pub events: usize,
+    pub handler_calls: usize,
+    // requests the handler's retry rescued
+    pub recovered: usize,
+    pub reserve_released: bool,
+    pub reserve_free: usize
+}
+
+pub struct OomHooks {
+    // the OomHandler as an address, 0 for none
+    handler: AtomicUsize,
+    busy: AtomicBool,
+    reserve_next: AtomicUsize,
+    reserve_end: AtomicUsize,
+    released: AtomicBool,
+    events: AtomicUsize,
+    handler_calls: AtomicUsize,
+    recovered: AtomicUsize
+}
+
+impl OomHooks {
+    pub const fn new() -> Self {
+        Self {
+            handler: AtomicUsize::new(0),
+            busy: AtomicBool::new(false),
+            reserve_next: AtomicUsize::new(0),
+            reserve_end: AtomicUsize::new(0),
+            released: AtomicBool::new(false),
+            events: AtomicUsize::new(0),
+            handler_calls: AtomicUsize::new(0),
+            recovered: AtomicUsize::new(0)
+        }
+    }
+
+    pub fn set_handler(&self, handler: Option<OomHandler>) {
+        self.handler.store(handler.map_or(0, |f| f as usize), Ordering::Release);
+    }
+
+    // the region stays untouched until the reserve is released
+    pub fn init_reserve(&self, start: usize, end: usize) {
+        self.reserve_next.store(start, Ordering::Relaxed);
+        self.reserve_end.store(end, Ordering::Release);
+    }
+
+    // runs alloc, and on null the handler and the reserve as described above
+    pub fn alloc_with(&self, layout: Layout, mut alloc: impl FnMut() -> *mut u8) -> *mut u8 {
+        let p = alloc();
+        if !p.is_null() {
+            return p;
+        }
+        self.events.fetch_add(1, Ordering::Relaxed);
+        for _ in 0..OOM_RETRIES {
+            if self.call_handler(layout) != OomAction::Retry {
+                break;
+            }
+            let p = alloc();
+            if !p.is_null() {
+                self.recovered.fetch_add(1, Ordering::Relaxed);
+                return p;
+            }
+        }
+        if self.reserve_end.load(Ordering::Acquire) != 0 {
+            self.released.store(true, Ordering::Release);
+        }
+        self.alloc_reserve(layout)
+    }
+
+    pub fn reserve_released(&self) -> bool {
+        self.released.load(Ordering::Acquire)
+    }
+
+    pub fn stats(&self) -> OomStats {
+        OomStats {
+            events: self.events.load(Ordering::Relaxed),
+            handler_calls: self.handler_calls.load(Ordering::Relaxed),
+            recovered: self.recovered.load(Ordering::Relaxed),
+            reserve_released: self.reserve_released(),
+            reserve_free: self.reserve_end.load(Ordering::Acquire).saturating_sub(self.reserve_next.load(Ordering::Acquire))
+        }
+    }
+
+    fn call_handler(&self, layout: Layout) -> OomAction {
+        let raw = self.handler.load(Ordering::Acquire);
+        if raw == 0 || self.busy.compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire).is_err() {
+            return OomAction::Fail;
+        }
+        // only ever stored from an OomHandler in set_handler
+        let handler: OomHandler = unsafe { core::mem::transmute::<usize, OomHandler>(raw) };
+        self.handler_calls.fetch_add(1, Ordering::Relaxed);
+        let action = handler(layout);
+        self.busy.store(false, Ordering::Release);
+        action
+    }
+
+    fn alloc_reserve(&self, layout: Layout) -> *mut u8 {
+        let end = self.reserve_end.load(Ordering::Acquire);
+        if end == 0 {
+            return core::ptr::null_mut();
+        }
+        let mut current = self.reserve_next.load(Ordering::Acquire);
+        loop {
+            let aligned = crate::bump::align_up(current, layout.align());
+            let new_next = match aligned.checked_add(layout.size()) {
+                Some(new_next) if new_next <= end => new_next,
+                _ => return core::ptr::null_mut(),
+            };
+            match self.reserve_next.compare_exchange(current, new_next, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return aligned as *mut u8,
+                Err(actual) => current = actual,
+            }
+        }
+    }
+}
+
+impl Default for OomHooks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
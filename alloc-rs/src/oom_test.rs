@@ -0,0 +1,75 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::composite::{CompositeAllocator, FallbackPolicy};
+use crate::oom::{OomAction, OOM_RETRIES};
+
+#[test]
+pub fn oom_handler_and_reserve_test() {
+    static mut HEAP: [u8; 32 * 1024] = [0u8; 32 * 1024];
+    static ALLOC: CompositeAllocator = unsafe { CompositeAllocator::builder().heap(&raw mut HEAP) }
+        .slab_region_bytes(8 * 1024)
+        .size_classes(&[64])
+        .fallback(FallbackPolicy::Fail)
+        .emergency_reserve(4096)
+        .build();
+    // blocks a subsystem keeps cached, the handler gives them back
+    static CACHE: [AtomicUsize; 1024] = [const { AtomicUsize::new(0) }; 1024];
+    static HANDLER_CALLS: AtomicUsize = AtomicUsize::new(0);
+    let block = Layout::from_size_align(64, 8).unwrap();
+    let heap_end = &raw mut HEAP as usize + 32 * 1024;
+
+    fn drop_cache(layout: Layout) -> OomAction {
+        HANDLER_CALLS.fetch_add(1, Ordering::Relaxed);
+        let mut freed = 0;
+        for slot in CACHE.iter() {
+            let p = slot.swap(0, Ordering::Relaxed);
+            if p != 0 {
+                unsafe { ALLOC.dealloc(p as *mut u8, layout) };
+                freed += 1;
+            }
+        }
+        if freed > 0 { OomAction::Retry } else { OomAction::Fail }
+    }
+    fn never_helps(_: Layout) -> OomAction {
+        HANDLER_CALLS.fetch_add(1, Ordering::Relaxed);
+        OomAction::Retry
+    }
+
+    // fill the heap with cached blocks. the allocation that runs out calls the handler, which
+    // empties the cache, and the retry succeeds without touching the reserve
+    ALLOC.set_oom_handler(Some(drop_cache));
+    let mut cached = 0;
+    let p = loop {
+        let p = unsafe { ALLOC.alloc(block) };
+        assert!(!p.is_null() && (p as usize) < heap_end - 4096);
+        if HANDLER_CALLS.load(Ordering::Relaxed) == 1 {
+            break p;
+        }
+        CACHE[cached].store(p as usize, Ordering::Relaxed);
+        cached += 1;
+    };
+    assert!(cached > 64);
+    let stats = ALLOC.oom_stats();
+    assert_eq!((stats.events, stats.handler_calls, stats.recovered), (1, 1, 1));
+    assert!(!stats.reserve_released);
+    assert!(CACHE.iter().all(|slot| slot.load(Ordering::Relaxed) == 0));
+
+    // a handler asking for retries without freeing anything is cut off after OOM_RETRIES,
+    // then the reserve is released for the error path
+    ALLOC.set_oom_handler(Some(never_helps));
+    let big = Layout::from_size_align(2048, 8).unwrap();
+    let report = unsafe { ALLOC.alloc(big) };
+    assert_eq!(ALLOC.oom_stats().events, 2);
+    assert_eq!(HANDLER_CALLS.load(Ordering::Relaxed), 1 + OOM_RETRIES);
+    assert!((report as usize) >= heap_end - 4096 && (report as usize) + 2048 <= heap_end);
+    let stats = ALLOC.oom_stats();
+    assert!(stats.reserve_released);
+    assert_eq!(stats.reserve_free, 2048);
+    // the reserve is all that is left, once it runs out it's null again
+    ALLOC.set_oom_handler(None);
+    assert!(unsafe { ALLOC.alloc(Layout::from_size_align(4096, 8).unwrap()) }.is_null());
+    assert!(!unsafe { ALLOC.alloc(big) }.is_null());
+    assert_eq!(ALLOC.oom_stats().reserve_free, 0);
+    unsafe { ALLOC.dealloc(p, block) };
+}
@@ -0,0 +1,411 @@
+use core::cell::Cell;
+
+// software model of x86_64 4-level paging. the tables are real 512 entry u64 tables in memory,
+// laid out and walked the way the MMU does it (PML4 -> PDPT -> PD -> PT, 9 bits of the virtual
+// address per level, 4 KiB pages), they just get walked by translate()/access() instead of the
+// CPU. physical addresses are host addresses: a "physical frame" is a 4 KiB aligned piece of
+// the region handed to the frame allocator, so whatever a translation returns can be
+// dereferenced directly. that is what lets a CompositeAllocator heap sit behind a mapping.
+// access() goes through a small direct mapped TLB that, like the real one, is not kept
+// coherent with the tables: after changing a mapping the caller flushes the page (invlpg) or
+// everything (a cr3 reload), until then stale translations keep hitting.
+// single threaded, the allocator and TLB state are Cells.
+
+pub const PAGE_SIZE: usize = 4096;
+const ENTRIES: usize = 512;
+const ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+const TLB_ENTRIES: usize = 32;
+
+// page table entry flags, the bit positions are the hardware ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageFlags(u64);
+
+impl PageFlags {
+    pub const NONE: PageFlags = PageFlags(0);
+    pub const PRESENT: PageFlags = PageFlags(1);
+    pub const WRITABLE: PageFlags = PageFlags(1 << 1);
+    pub const USER: PageFlags = PageFlags(1 << 2);
+    pub const NO_EXECUTE: PageFlags = PageFlags(1 << 63);
+    const ALL: u64 = 1 | 1 << 1 | 1 << 2 | 1 << 63;
+
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    pub const fn contains(self, other: PageFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn union(self, other: PageFlags) -> PageFlags {
+        PageFlags(self.0 | other.0)
+    }
+}
+
+impl core::ops::BitOr for PageFlags {
+    type Output = PageFlags;
+
+    fn bitor(self, other: PageFlags) -> PageFlags {
+        self.union(other)
+    }
+}
+
+// source of the frames the page tables live in
+pub trait FrameAllocator {
+    // a zeroed, page aligned 4 KiB frame
+    fn alloc_frame(&self) -> Option<usize>;
+
+    /// # Safety
+    /// frame came from alloc_frame on this allocator and is not used afterwards.
+    unsafe fn free_frame(&self, frame: usize);
+}
+
+// frames bumped off a region, freed frames go on a lifo list threaded through their first word
+pub struct PhysFrames {
+    next: Cell<usize>,
+    end: usize,
+    free_list: Cell<usize>,
+    free_count: Cell<usize>
+}
+
+impl PhysFrames {
+    /// hands out the whole pages of heap_size bytes at heap_start, page tables and the free list
+    /// links get written into them.
+    ///
+    /// # Safety
+    /// the region has to be valid for reads and writes and given over to the frame allocator
+    /// until every frame from it is freed.
+    pub unsafe fn from_raw_parts(heap_start: usize, heap_size: usize) -> Self {
+        let start = crate::bump::align_up(heap_start, PAGE_SIZE);
+        let end = (heap_start + heap_size) & !(PAGE_SIZE - 1);
+        Self { next: Cell::new(start), end: end.max(start), free_list: Cell::new(0), free_count: Cell::new(0) }
+    }
+
+    // frames left, recycled and never handed out
+    pub fn free_frames(&self) -> usize {
+        self.free_count.get() + (self.end - self.next.get()) / PAGE_SIZE
+    }
+}
+
+impl FrameAllocator for PhysFrames {
+    fn alloc_frame(&self) -> Option<usize> {
+        let frame = match self.free_list.get() {
+            0 => {
+                let frame = self.next.get();
+                if frame == self.end {
+                    return None;
+                }
+                self.next.set(frame + PAGE_SIZE);
+                frame
+            },
+            frame => {
+                self.free_list.set(unsafe { (frame as *const usize).read() });
+                self.free_count.set(self.free_count.get() - 1);
+                frame
+            },
+        };
+        unsafe { core::ptr::write_bytes(frame as *mut u8, 0, PAGE_SIZE) };
+        Some(frame)
+    }
+
+    unsafe fn free_frame(&self, frame: usize) {
+        unsafe { (frame as *mut usize).write(self.free_list.get()) };
+        self.free_list.set(frame);
+        self.free_count.set(self.free_count.get() + 1);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapError {
+    // virtual or physical address not on a page boundary
+    Unaligned,
+    // bits 48..64 of the virtual address aren't copies of bit 47
+    NonCanonical,
+    AlreadyMapped,
+    NotMapped,
+    // no frame left for a page table
+    OutOfFrames
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultReason {
+    NotPresent,
+    // write to a page that isn't writable at every level
+    WriteProtected,
+    // user mode access to a page that isn't user at every level
+    Supervisor,
+    // instruction fetch from a page marked NX at some level
+    NoExecute
+}
+
+// what the model hands back instead of raising #PF
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageFault {
+    pub addr: usize,
+    pub access: Access,
+    pub user: bool,
+    pub reason: FaultReason
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TlbStats {
+    pub hits: usize,
+    pub misses: usize,
+    pub flushes: usize
+}
+
+#[derive(Clone, Copy)]
+struct TlbEntry {
+    // virtual page number + 1, 0 for an empty entry
+    tag: usize,
+    frame: usize,
+    // flags combined over all four levels, what the permission check needs
+    flags: PageFlags
+}
+
+const EMPTY_TLB_ENTRY: TlbEntry = TlbEntry { tag: 0, frame: 0, flags: PageFlags::NONE };
+
+pub struct AddressSpace<'f, F: FrameAllocator> {
+    frames: &'f F,
+    // physical address of the PML4, what cr3 would hold
+    root: usize,
+    tlb: [Cell<TlbEntry>; TLB_ENTRIES],
+    tlb_stats: Cell<TlbStats>,
+    table_frames: Cell<usize>
+}
+
+impl<'f, F: FrameAllocator> AddressSpace<'f, F> {
+    // None if there is no frame for the PML4
+    pub fn new(frames: &'f F) -> Option<Self> {
+        let root = frames.alloc_frame()?;
+        Some(Self {
+            frames,
+            root,
+            tlb: [const { Cell::new(EMPTY_TLB_ENTRY) }; TLB_ENTRIES],
+            tlb_stats: Cell::new(TlbStats::default()),
+            table_frames: Cell::new(1)
+        })
+    }
+
+    pub fn root(&self) -> usize {
+        self.root
+    }
+
+    // frames currently used for page tables, the PML4 included
+    pub fn table_frames(&self) -> usize {
+        self.table_frames.get()
+    }
+
+    // missing intermediate tables are allocated as present | writable | user, the leaf flags
+    // (and any later protect) are what restrict the page. PRESENT is implied.
+    pub fn map(&self, virt: usize, phys: usize, flags: PageFlags) -> Result<(), MapError> {
+        check_virt(virt)?;
+        if phys & (PAGE_SIZE - 1) != 0 || phys as u64 & !ADDR_MASK != 0 {
+            return Err(MapError::Unaligned);
+        }
+        let mut table = self.root;
+        for level in (1..4).rev() {
+            let entry = entry_ptr(table, virt, level);
+            let value = unsafe { entry.read() };
+            table = if value & PageFlags::PRESENT.0 != 0 {
+                (value & ADDR_MASK) as usize
+            } else {
+                let frame = self.frames.alloc_frame().ok_or(MapError::OutOfFrames)?;
+                self.table_frames.set(self.table_frames.get() + 1);
+                let parent = (PageFlags::PRESENT | PageFlags::WRITABLE | PageFlags::USER).0;
+                unsafe { entry.write(frame as u64 | parent) };
+                frame
+            };
+        }
+        let leaf = entry_ptr(table, virt, 0);
+        if unsafe { leaf.read() } & PageFlags::PRESENT.0 != 0 {
+            return Err(MapError::AlreadyMapped);
+        }
+        unsafe { leaf.write(phys as u64 | (flags.0 & PageFlags::ALL) | PageFlags::PRESENT.0) };
+        Ok(())
+    }
+
+    // clears the leaf and frees page tables left empty by it, returns the frame that was mapped.
+    // the TLB still holds the old translation until the page is flushed.
+    pub fn unmap(&self, virt: usize) -> Result<usize, MapError> {
+        check_virt(virt)?;
+        let mut path = [0usize; 4];
+        let mut table = self.root;
+        for level in (0..4).rev() {
+            path[level] = table;
+            let value = unsafe { entry_ptr(table, virt, level).read() };
+            if value & PageFlags::PRESENT.0 == 0 {
+                return Err(MapError::NotMapped);
+            }
+            table = (value & ADDR_MASK) as usize;
+        }
+        unsafe { entry_ptr(path[0], virt, 0).write(0) };
+        // walk back up, the PML4 stays even when empty
+        for level in 0..3 {
+            let empty = (0..ENTRIES).all(|i| unsafe { (path[level] as *const u64).add(i).read() } == 0);
+            if !empty {
+                break;
+            }
+            unsafe {
+                entry_ptr(path[level + 1], virt, level + 1).write(0);
+                self.frames.free_frame(path[level]);
+            }
+            self.table_frames.set(self.table_frames.get() - 1);
+        }
+        Ok(table)
+    }
+
+    // replaces the leaf flags of a mapped page, PRESENT stays. needs a flush like unmap
+    pub fn protect(&self, virt: usize, flags: PageFlags) -> Result<(), MapError> {
+        check_virt(virt)?;
+        let leaf = self.leaf(virt).ok_or(MapError::NotMapped)?;
+        let value = unsafe { leaf.read() };
+        unsafe { leaf.write((value & ADDR_MASK) | (flags.0 & PageFlags::ALL) | PageFlags::PRESENT.0) };
+        Ok(())
+    }
+
+    // plain table walk, no TLB and no permission checks
+    pub fn translate(&self, virt: usize) -> Option<usize> {
+        self.walk(virt).map(|(frame, _)| frame + (virt & (PAGE_SIZE - 1)))
+    }
+
+    // leaf frame and the flags in effect for the page (writable and user only if every level
+    // allows it, NX if any level sets it)
+    pub fn translate_page(&self, virt: usize) -> Option<(usize, PageFlags)> {
+        self.walk(virt)
+    }
+
+    // what the MMU does for a load, store or fetch: TLB lookup, table walk on a miss, then the
+    // permission check. only successful walks are cached.
+    pub fn access(&self, virt: usize, access: Access, user: bool) -> Result<usize, PageFault> {
+        let fault = |reason| PageFault { addr: virt, access, user, reason };
+        let vpn = virt / PAGE_SIZE;
+        let slot = &self.tlb[vpn % TLB_ENTRIES];
+        let mut stats = self.tlb_stats.get();
+        let cached = slot.get();
+        let hit = cached.tag == vpn + 1;
+        if hit {
+            stats.hits += 1;
+        } else {
+            stats.misses += 1;
+        }
+        self.tlb_stats.set(stats);
+        let (frame, flags) = if hit {
+            (cached.frame, cached.flags)
+        } else {
+            let (frame, flags) = self.walk(virt).ok_or(fault(FaultReason::NotPresent))?;
+            slot.set(TlbEntry { tag: vpn + 1, frame, flags });
+            (frame, flags)
+        };
+        if access == Access::Write && !flags.contains(PageFlags::WRITABLE) {
+            return Err(fault(FaultReason::WriteProtected));
+        }
+        if user && !flags.contains(PageFlags::USER) {
+            return Err(fault(FaultReason::Supervisor));
+        }
+        if access == Access::Execute && flags.contains(PageFlags::NO_EXECUTE) {
+            return Err(fault(FaultReason::NoExecute));
+        }
+        Ok(frame + (virt & (PAGE_SIZE - 1)))
+    }
+
+    // invlpg
+    pub fn flush_page(&self, virt: usize) {
+        let vpn = virt / PAGE_SIZE;
+        let slot = &self.tlb[vpn % TLB_ENTRIES];
+        if slot.get().tag == vpn + 1 {
+            slot.set(EMPTY_TLB_ENTRY);
+        }
+        self.count_flush();
+    }
+
+    // cr3 reload
+    pub fn flush_all(&self) {
+        for slot in &self.tlb {
+            slot.set(EMPTY_TLB_ENTRY);
+        }
+        self.count_flush();
+    }
+
+    pub fn tlb_stats(&self) -> TlbStats {
+        self.tlb_stats.get()
+    }
+
+    fn count_flush(&self) {
+        let mut stats = self.tlb_stats.get();
+        stats.flushes += 1;
+        self.tlb_stats.set(stats);
+    }
+
+    fn walk(&self, virt: usize) -> Option<(usize, PageFlags)> {
+        check_virt(virt & !(PAGE_SIZE - 1)).ok()?;
+        let mut table = self.root;
+        let mut allowed = PageFlags::WRITABLE.0 | PageFlags::USER.0;
+        let mut nx = 0;
+        for level in (0..4).rev() {
+            let value = unsafe { entry_ptr(table, virt, level).read() };
+            if value & PageFlags::PRESENT.0 == 0 {
+                return None;
+            }
+            allowed &= value;
+            nx |= value & PageFlags::NO_EXECUTE.0;
+            table = (value & ADDR_MASK) as usize;
+        }
+        Some((table, PageFlags(PageFlags::PRESENT.0 | allowed | nx)))
+    }
+
+    fn leaf(&self, virt: usize) -> Option<*mut u64> {
+        let mut table = self.root;
+        for level in (1..4).rev() {
+            let value = unsafe { entry_ptr(table, virt, level).read() };
+            if value & PageFlags::PRESENT.0 == 0 {
+                return None;
+            }
+            table = (value & ADDR_MASK) as usize;
+        }
+        let leaf = entry_ptr(table, virt, 0);
+        (unsafe { leaf.read() } & PageFlags::PRESENT.0 != 0).then_some(leaf)
+    }
+
+    // frees the tables below a PML4/PDPT/PD entry, leaf frames belong to whoever mapped them
+    unsafe fn free_tables(&self, table: usize, level: usize) {
+        if level > 0 {
+            for i in 0..ENTRIES {
+                let value = unsafe { (table as *const u64).add(i).read() };
+                if value & PageFlags::PRESENT.0 != 0 {
+                    unsafe { self.free_tables((value & ADDR_MASK) as usize, level - 1) };
+                }
+            }
+        }
+        unsafe { self.frames.free_frame(table) };
+    }
+}
+
+impl<F: FrameAllocator> Drop for AddressSpace<'_, F> {
+    fn drop(&mut self) {
+        unsafe { self.free_tables(self.root, 3) };
+    }
+}
+
+fn check_virt(virt: usize) -> Result<(), MapError> {
+    if virt & (PAGE_SIZE - 1) != 0 {
+        return Err(MapError::Unaligned);
+    }
+    // sign extension of bit 47
+    let high = virt >> 47;
+    if high != 0 && high != (usize::MAX >> 47) {
+        return Err(MapError::NonCanonical);
+    }
+    Ok(())
+}
+
+fn entry_ptr(table: usize, virt: usize, level: usize) -> *mut u64 {
+    let index = (virt >> (12 + 9 * level)) & (ENTRIES - 1);
+    (table as *mut u64).wrapping_add(index)
+}
@@ -0,0 +1,108 @@
+use core::alloc::{GlobalAlloc, Layout};
+
+use crate::composite::CompositeAllocator;
+use crate::paging::{Access, AddressSpace, FaultReason, FrameAllocator, MapError, PageFlags, PhysFrames, PAGE_SIZE};
+
+#[repr(C, align(4096))]
+struct Phys([u8; 32 * 4096]);
+
+#[test]
+pub fn page_table_test() {
+    static mut PHYS: Phys = Phys([0u8; 32 * 4096]);
+    let phys_addr = &raw mut PHYS as usize;
+    let frames = unsafe { PhysFrames::from_raw_parts(phys_addr, 32 * PAGE_SIZE) };
+    let space = AddressSpace::new(&frames).unwrap();
+    let data = frames.alloc_frame().unwrap();
+
+    let kernel = 0xffff_8000_0020_0000usize;
+    let user = 0x40_0000usize;
+    assert_eq!(space.map(kernel + 8, data, PageFlags::WRITABLE), Err(MapError::Unaligned));
+    assert_eq!(space.map(0x0000_8000_0000_0000, data, PageFlags::WRITABLE), Err(MapError::NonCanonical));
+    space.map(kernel, data, PageFlags::WRITABLE | PageFlags::NO_EXECUTE).unwrap();
+    space.map(user, data, PageFlags::USER).unwrap();
+    assert_eq!(space.map(user, data, PageFlags::USER), Err(MapError::AlreadyMapped));
+    // pml4 plus pdpt, pd and pt on both halves
+    assert_eq!(space.table_frames(), 7);
+
+    assert_eq!(space.translate(kernel + 0x123), Some(data + 0x123));
+    assert_eq!(space.translate(user + 0x10), Some(data + 0x10));
+    assert_eq!(space.translate(user + PAGE_SIZE), None);
+
+    // permission checks, writable and user have to hold at every level
+    assert_eq!(space.access(kernel + 8, Access::Write, false), Ok(data + 8));
+    assert_eq!(space.access(kernel, Access::Read, true).unwrap_err().reason, FaultReason::Supervisor);
+    assert_eq!(space.access(kernel, Access::Execute, false).unwrap_err().reason, FaultReason::NoExecute);
+    assert_eq!(space.access(user, Access::Write, true).unwrap_err().reason, FaultReason::WriteProtected);
+    assert_eq!(space.access(user, Access::Execute, true), Ok(data));
+    assert_eq!(space.access(user + PAGE_SIZE, Access::Read, true).unwrap_err().reason, FaultReason::NotPresent);
+
+    // the tlb isn't coherent with the tables: protect only shows up after a flush
+    space.protect(user, PageFlags::USER | PageFlags::WRITABLE).unwrap();
+    assert!(space.access(user, Access::Write, true).is_err());
+    space.flush_page(user);
+    assert_eq!(space.access(user, Access::Write, true), Ok(data));
+
+    // same for unmap, the stale entry keeps translating until invlpg
+    let before = space.tlb_stats();
+    assert_eq!(space.unmap(user), Ok(data));
+    assert_eq!(space.access(user, Access::Read, true), Ok(data));
+    space.flush_page(user);
+    assert_eq!(space.access(user, Access::Read, true).unwrap_err().reason, FaultReason::NotPresent);
+    let after = space.tlb_stats();
+    assert_eq!((after.hits - before.hits, after.misses - before.misses, after.flushes - before.flushes), (1, 1, 1));
+    assert_eq!(space.unmap(user), Err(MapError::NotMapped));
+    // the user half's pdpt, pd and pt went back to the frame allocator
+    assert_eq!(space.table_frames(), 4);
+
+    let free = frames.free_frames();
+    drop(space);
+    assert_eq!(frames.free_frames(), free + 4);
+}
+
+#[test]
+pub fn heap_in_mapped_memory_test() {
+    static mut PHYS: Phys = Phys([0u8; 32 * 4096]);
+    let frames = unsafe { PhysFrames::from_raw_parts(&raw mut PHYS as usize, 32 * PAGE_SIZE) };
+    let space = AddressSpace::new(&frames).unwrap();
+
+    // 16 heap pages behind a kernel virtual range. the data frames are taken before map()
+    // starts pulling page tables, so they come off the allocator back to back and the heap
+    // allocator gets the one contiguous heap it wants.
+    const HEAP_VIRT: usize = 0xffff_c000_0000_0000;
+    const HEAP_PAGES: usize = 16;
+    let mut heap_frames = [0usize; HEAP_PAGES];
+    for frame in heap_frames.iter_mut() {
+        *frame = frames.alloc_frame().unwrap();
+    }
+    for (page, frame) in heap_frames.iter().enumerate() {
+        space.map(HEAP_VIRT + page * PAGE_SIZE, *frame, PageFlags::WRITABLE | PageFlags::NO_EXECUTE).unwrap();
+    }
+    let heap_phys = space.translate(HEAP_VIRT).unwrap();
+    for page in 0..HEAP_PAGES {
+        assert_eq!(space.translate(HEAP_VIRT + page * PAGE_SIZE), Some(heap_phys + page * PAGE_SIZE));
+    }
+
+    let heap = unsafe { CompositeAllocator::builder().heap(heap_phys as *mut [u8; HEAP_PAGES * PAGE_SIZE]) }
+        .slab_region_bytes(8 * 1024)
+        .size_classes(&[16, 64])
+        .build();
+    // every block the heap hands out is reachable through the mapping with kernel rw rights
+    let to_virt = |p: *mut u8| HEAP_VIRT + (p as usize - heap_phys);
+    let layouts = [Layout::from_size_align(16, 8).unwrap(), Layout::from_size_align(64, 8).unwrap(), Layout::from_size_align(3000, 64).unwrap()];
+    let mut blocks = [core::ptr::null_mut(); 3];
+    for (block, layout) in blocks.iter_mut().zip(layouts) {
+        *block = unsafe { heap.alloc(layout) };
+        assert!(!block.is_null());
+        let virt = to_virt(*block);
+        assert!(virt >= HEAP_VIRT && virt + layout.size() <= HEAP_VIRT + HEAP_PAGES * PAGE_SIZE);
+        let phys = space.access(virt, Access::Write, false).unwrap();
+        assert_eq!(phys, *block as usize);
+        unsafe { (phys as *mut u8).write(0xa5) };
+        assert_eq!(space.access(virt, Access::Execute, false).unwrap_err().reason, FaultReason::NoExecute);
+        assert_eq!(space.access(virt, Access::Read, true).unwrap_err().reason, FaultReason::Supervisor);
+    }
+    for (block, layout) in blocks.iter().zip(layouts) {
+        assert_eq!(unsafe { block.read() }, 0xa5);
+        unsafe { heap.dealloc(*block, layout) };
+    }
+}
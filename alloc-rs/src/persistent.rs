@@ -0,0 +1,464 @@
+use core::alloc::Layout;
+use core::ffi::c_void;
+use core::marker::PhantomData;
+use core::sync::atomic::{compiler_fence, Ordering};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::fd::AsRawFd;
+use std::path::Path;
+
+// a heap that lives in a memory mapped file and survives restarts (std, linux).
+// everything the allocator knows sits in the file itself: a header at offset 0 with the bump
+// next, one free list head per size class and the root object, then the blocks. the file maps
+// at a different address every run, so nothing in it is a pointer, links and PPtr are offsets
+// from the start of the file. objects stored in it must be Copy and point at each other with
+// PPtr only, never with references or raw pointers.
+// every block has a 16 byte header (payload size, live/free) and blocks are laid out back to
+// back from the bump, so the heap can be walked. each operation marks the header dirty while
+// it runs: if the process dies half way the next open finds it dirty and rebuilds the free
+// lists from a walk. a block is only past the bump next once its header is written, so a
+// half done bump allocation is simply not there. the free lists are only a cache of the block
+// headers, and the file may be stale or damaged, so open rebuilds them from a walk every time
+// and alloc still checks a head before it follows it. msync only runs on flush and drop, a process
+// crash keeps everything in the page cache but a machine crash can lose what wasn't flushed.
+// one process at a time, open takes an exclusive flock on the file.
+
+pub const PERSISTENT_MAGIC: u64 = 0x5045_5253_4845_4150;
+pub const PERSISTENT_VERSION: u64 = 1;
+pub const PERSISTENT_MIN_BLOCK: usize = 16;
+pub const PERSISTENT_CLASSES: usize = 17;
+// 1 MiB, bigger objects don't fit any class
+pub const PERSISTENT_MAX_BLOCK: usize = PERSISTENT_MIN_BLOCK << (PERSISTENT_CLASSES - 1);
+const BLOCK_HEADER: usize = 16;
+const PAGE_SIZE: usize = 4096;
+
+const STATE_CLEAN: u64 = 0;
+const STATE_DIRTY: u64 = 1;
+const BLOCK_LIVE: u64 = 0x4c49_5645;
+const BLOCK_FREE: u64 = 0x4652_4545;
+
+const PROT_READ: i32 = 1;
+const PROT_WRITE: i32 = 2;
+const MAP_SHARED: i32 = 1;
+const MAP_FAILED: *mut c_void = !0usize as *mut c_void;
+const MS_SYNC: i32 = 4;
+const LOCK_EX: i32 = 2;
+const LOCK_NB: i32 = 4;
+
+unsafe extern "C" {
+    fn mmap(addr: *mut c_void, len: usize, prot: i32, flags: i32, fd: i32, offset: i64) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> i32;
+    fn msync(addr: *mut c_void, len: usize, flags: i32) -> i32;
+    fn flock(fd: i32, op: i32) -> i32;
+}
+
+// the first bytes of the file
+#[repr(C)]
+pub(crate) struct Header {
+    pub(crate) magic: u64,
+    pub(crate) version: u64,
+    pub(crate) size: u64,
+    pub(crate) next: u64,
+    pub(crate) root: u64,
+    pub(crate) root_size: u64,
+    pub(crate) state: u64,
+    pub(crate) free: [u64; PERSISTENT_CLASSES]
+}
+
+const DATA_START: usize = core::mem::size_of::<Header>().next_multiple_of(BLOCK_HEADER);
+
+#[repr(C)]
+struct BlockHeader {
+    size: u64,
+    state: u64
+}
+
+// an offset into a PersistentHeap, stays valid across runs. 0 is null
+#[repr(transparent)]
+pub struct PPtr<T> {
+    offset: u64,
+    _marker: PhantomData<*mut T>
+}
+
+impl<T> PPtr<T> {
+    pub const fn null() -> Self {
+        Self { offset: 0, _marker: PhantomData }
+    }
+
+    pub const fn is_null(&self) -> bool {
+        self.offset == 0
+    }
+
+    pub const fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    const fn from_offset(offset: u64) -> Self {
+        Self { offset, _marker: PhantomData }
+    }
+}
+
+impl<T> Clone for PPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for PPtr<T> {}
+
+impl<T> PartialEq for PPtr<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.offset == other.offset
+    }
+}
+
+impl<T> Eq for PPtr<T> {}
+
+impl<T> core::fmt::Debug for PPtr<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "PPtr({:#x})", self.offset)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PersistentStats {
+    pub size: usize,
+    // bytes the bump has handed out, headers included
+    pub used: usize,
+    pub live_blocks: usize,
+    pub live_bytes: usize,
+    pub free_blocks: usize
+}
+
+pub struct PersistentHeap {
+    base: *mut u8,
+    len: usize,
+    // set when open found the file dirty and rebuilt the free lists
+    recovered: bool,
+    // keeps the fd, and with it the lock, for as long as the mapping
+    _file: File
+}
+
+impl PersistentHeap {
+    // creates (or truncates) the file and formats an empty heap of size bytes, rounded up to pages
+    pub fn create(path: impl AsRef<Path>, size: usize) -> io::Result<Self> {
+        let len = size.next_multiple_of(PAGE_SIZE);
+        if len < DATA_START + BLOCK_HEADER + PERSISTENT_MIN_BLOCK {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "persistent heap too small"));
+        }
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        lock(&file)?;
+        file.set_len(0)?;
+        file.set_len(len as u64)?;
+        let heap = Self::map(file, len)?;
+        let header = heap.header();
+        unsafe {
+            (*header).version = PERSISTENT_VERSION;
+            (*header).size = len as u64;
+            (*header).next = DATA_START as u64;
+            (*header).root = 0;
+            (*header).root_size = 0;
+            (*header).state = STATE_CLEAN;
+            (*header).free = [0; PERSISTENT_CLASSES];
+            // magic last, a file without it was never fully formatted
+            compiler_fence(Ordering::SeqCst);
+            (*header).magic = PERSISTENT_MAGIC;
+        }
+        heap.flush()?;
+        Ok(heap)
+    }
+
+    // maps an existing heap, recovering it if the last user died mid operation
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        lock(&file)?;
+        let len = file.metadata()?.len() as usize;
+        if len < DATA_START {
+            return Err(invalid("file too small for a persistent heap"));
+        }
+        let mut heap = Self::map(file, len)?;
+        let header = heap.header();
+        let (magic, version, size, next) = unsafe { ((*header).magic, (*header).version, (*header).size, (*header).next) };
+        if magic != PERSISTENT_MAGIC || version != PERSISTENT_VERSION {
+            return Err(invalid("not a persistent heap"));
+        }
+        if size != len as u64 || next < DATA_START as u64 || next > size {
+            return Err(invalid("persistent heap header doesn't match the file"));
+        }
+        let dirty = unsafe { core::ptr::read_volatile(&(*header).state) } != STATE_CLEAN;
+        heap.begin();
+        heap.recover()?;
+        heap.recovered = dirty;
+        Ok(heap)
+    }
+
+    pub fn open_or_create(path: impl AsRef<Path>, size: usize) -> io::Result<Self> {
+        match Self::open(path.as_ref()) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Self::create(path, size),
+            result => result,
+        }
+    }
+
+    // offset of a 16 byte aligned block of at least layout.size() bytes, None when the file is
+    // full or the layout is bigger or more aligned than any class
+    pub fn alloc(&self, layout: Layout) -> Option<u64> {
+        if layout.align() > BLOCK_HEADER {
+            return None;
+        }
+        let class = class_of(layout.size())?;
+        let block_size = PERSISTENT_MIN_BLOCK << class;
+        let header = self.header();
+        self.begin();
+        let mut head = unsafe { (*header).free[class] };
+        if head != 0 && !self.free_block(head, block_size) {
+            // not a free block of this class, drop the list rather than follow it
+            unsafe { (*header).free[class] = 0 };
+            head = 0;
+        }
+        let block = if head != 0 {
+            unsafe {
+                (*header).free[class] = *self.at::<u64>(head + BLOCK_HEADER as u64);
+                (*self.at::<BlockHeader>(head)).state = BLOCK_LIVE;
+            }
+            head
+        } else {
+            let next = unsafe { (*header).next };
+            let end = next + (BLOCK_HEADER + block_size) as u64;
+            if end > self.len as u64 {
+                self.end();
+                return None;
+            }
+            unsafe {
+                self.at::<BlockHeader>(next).write(BlockHeader { size: block_size as u64, state: BLOCK_LIVE });
+                compiler_fence(Ordering::SeqCst);
+                (*header).next = end;
+            }
+            next
+        };
+        self.end();
+        Some(block + BLOCK_HEADER as u64)
+    }
+
+    /// # Safety
+    /// offset must have come from alloc on this heap (in this run or an earlier one) and nothing
+    /// may use the block afterwards. false, and nothing freed, for offsets that aren't a live block
+    pub unsafe fn free_offset(&self, offset: u64) -> bool {
+        let Some(block) = self.live_block(offset) else {
+            return false;
+        };
+        let header = self.header();
+        unsafe {
+            let class = class_of((*self.at::<BlockHeader>(block)).size as usize).unwrap();
+            self.begin();
+            (*self.at::<BlockHeader>(block)).state = BLOCK_FREE;
+            *self.at::<u64>(offset) = (*header).free[class];
+            (*header).free[class] = block;
+            self.end();
+        }
+        true
+    }
+
+    pub fn alloc_value<T: Copy>(&self, value: T) -> Option<PPtr<T>> {
+        let offset = self.alloc(Layout::new::<T>())?;
+        unsafe { self.at::<T>(offset).write(value) };
+        Some(PPtr::from_offset(offset))
+    }
+
+    /// # Safety
+    /// same as free_offset, no reference from get or get_mut may outlive the call
+    pub unsafe fn free<T>(&self, p: PPtr<T>) -> bool {
+        unsafe { self.free_offset(p.offset) }
+    }
+
+    // where p lives in this run's mapping, null for a null PPtr
+    pub fn ptr<T>(&self, p: PPtr<T>) -> *mut T {
+        if p.is_null() {
+            return core::ptr::null_mut();
+        }
+        debug_assert!((p.offset as usize) + core::mem::size_of::<T>() <= self.len);
+        self.at::<T>(p.offset)
+    }
+
+    /// # Safety
+    /// p must point at a live T of this heap and nothing may write it while the reference lives
+    pub unsafe fn get<T>(&self, p: PPtr<T>) -> &T {
+        unsafe { &*self.ptr(p) }
+    }
+
+    /// # Safety
+    /// p must point at a live T of this heap and the reference must be the only one to it
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn get_mut<T>(&self, p: PPtr<T>) -> &mut T {
+        unsafe { &mut *self.ptr(p) }
+    }
+
+    // the object to start from after a reopen. its size is kept next to it so root::<T>()
+    // with the wrong T gets None rather than a misread
+    pub fn set_root<T>(&self, p: PPtr<T>) {
+        let header = self.header();
+        self.begin();
+        unsafe {
+            (*header).root_size = core::mem::size_of::<T>() as u64;
+            (*header).root = p.offset;
+        }
+        self.end();
+    }
+
+    pub fn root<T>(&self) -> Option<PPtr<T>> {
+        let header = self.header();
+        let (root, root_size) = unsafe { ((*header).root, (*header).root_size) };
+        if root_size != core::mem::size_of::<T>() as u64 {
+            return None;
+        }
+        self.live_block(root)?;
+        Some(PPtr::from_offset(root))
+    }
+
+    // writes the mapping back to the file
+    pub fn flush(&self) -> io::Result<()> {
+        if unsafe { msync(self.base as *mut c_void, self.len, MS_SYNC) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn recovered(&self) -> bool {
+        self.recovered
+    }
+
+    pub fn size(&self) -> usize {
+        self.len
+    }
+
+    // walks every block
+    pub fn stats(&self) -> PersistentStats {
+        let next = unsafe { (*self.header()).next };
+        let mut stats = PersistentStats { size: self.len, used: next as usize - DATA_START, ..Default::default() };
+        self.walk(|_, size, live| {
+            if live {
+                stats.live_blocks += 1;
+                stats.live_bytes += size;
+            } else {
+                stats.free_blocks += 1;
+            }
+        });
+        stats
+    }
+
+    fn map(file: File, len: usize) -> io::Result<Self> {
+        let base = unsafe { mmap(core::ptr::null_mut(), len, PROT_READ | PROT_WRITE, MAP_SHARED, file.as_raw_fd(), 0) };
+        if base == MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { base: base as *mut u8, len, recovered: false, _file: file })
+    }
+
+    // rebuilds the free lists from the blocks' own headers, they are the truth
+    fn recover(&self) -> io::Result<()> {
+        let header = self.header();
+        let mut free = [0u64; PERSISTENT_CLASSES];
+        if !self.walk(|block, size, live| {
+            if !live {
+                let class = class_of(size).unwrap();
+                unsafe { *self.at::<u64>(block + BLOCK_HEADER as u64) = free[class] };
+                free[class] = block;
+            }
+        }) {
+            return Err(invalid("persistent heap has a corrupt block"));
+        }
+        unsafe {
+            (*header).free = free;
+            if (*header).root != 0 && self.live_block((*header).root).is_none() {
+                (*header).root = 0;
+            }
+        }
+        self.end();
+        Ok(())
+    }
+
+    // f(block offset, payload size, live) for each block up to the bump next, false if a
+    // header doesn't make sense
+    fn walk(&self, mut f: impl FnMut(u64, usize, bool)) -> bool {
+        let next = unsafe { (*self.header()).next };
+        let mut block = DATA_START as u64;
+        while block < next {
+            let BlockHeader { size, state } = unsafe { self.at::<BlockHeader>(block).read() };
+            let valid = class_of(size as usize).is_some_and(|class| PERSISTENT_MIN_BLOCK << class == size as usize);
+            if !valid || block + BLOCK_HEADER as u64 + size > next || (state != BLOCK_LIVE && state != BLOCK_FREE) {
+                return false;
+            }
+            f(block, size as usize, state == BLOCK_LIVE);
+            block += BLOCK_HEADER as u64 + size;
+        }
+        true
+    }
+
+    // whether block is the header offset of a free block of block_size bytes inside the heap
+    fn free_block(&self, block: u64, block_size: usize) -> bool {
+        let next = unsafe { (*self.header()).next };
+        let end = block.checked_add((BLOCK_HEADER + block_size) as u64);
+        if block < DATA_START as u64 || !block.is_multiple_of(BLOCK_HEADER as u64) || end.is_none_or(|end| end > next) {
+            return false;
+        }
+        let BlockHeader { size, state } = unsafe { self.at::<BlockHeader>(block).read() };
+        state == BLOCK_FREE && size == block_size as u64
+    }
+
+    // the block header offset for a payload offset, if its header says live. cheap rather than
+    // exact: an offset into the middle of a payload only passes if the payload mimics a header
+    fn live_block(&self, offset: u64) -> Option<u64> {
+        let next = unsafe { (*self.header()).next };
+        if offset < (DATA_START + BLOCK_HEADER) as u64 || offset >= next || !offset.is_multiple_of(BLOCK_HEADER as u64) {
+            return None;
+        }
+        let block = offset - BLOCK_HEADER as u64;
+        let BlockHeader { size, state } = unsafe { self.at::<BlockHeader>(block).read() };
+        let sized = class_of(size as usize).is_some_and(|class| PERSISTENT_MIN_BLOCK << class == size as usize);
+        (state == BLOCK_LIVE && sized && offset + size <= next).then_some(block)
+    }
+
+    fn header(&self) -> *mut Header {
+        self.base as *mut Header
+    }
+
+    fn at<T>(&self, offset: u64) -> *mut T {
+        unsafe { self.base.add(offset as usize) as *mut T }
+    }
+
+    // the state word has to reach memory before and after the operation, not be folded away
+    fn begin(&self) {
+        unsafe { core::ptr::write_volatile(&raw mut (*self.header()).state, STATE_DIRTY) };
+        compiler_fence(Ordering::SeqCst);
+    }
+
+    fn end(&self) {
+        compiler_fence(Ordering::SeqCst);
+        unsafe { core::ptr::write_volatile(&raw mut (*self.header()).state, STATE_CLEAN) };
+    }
+}
+
+impl Drop for PersistentHeap {
+    fn drop(&mut self) {
+        let _ = self.flush();
+        unsafe { munmap(self.base as *mut c_void, self.len) };
+    }
+}
+
+fn class_of(size: usize) -> Option<usize> {
+    if size > PERSISTENT_MAX_BLOCK {
+        return None;
+    }
+    let size = size.max(PERSISTENT_MIN_BLOCK).next_power_of_two();
+    Some((size / PERSISTENT_MIN_BLOCK).trailing_zeros() as usize)
+}
+
+fn lock(file: &File) -> io::Result<()> {
+    if unsafe { flock(file.as_raw_fd(), LOCK_EX | LOCK_NB) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,128 @@
+use core::alloc::Layout;
+use std::fs::OpenOptions;
+use std::io::{Seek, SeekFrom, Write};
+
+use crate::persistent::{Header, PPtr, PersistentHeap, PERSISTENT_MAX_BLOCK};
+
+#[test]
+pub fn persistent_heap_reopen_test() {
+    // what rawnet would keep across restarts
+    #[derive(Clone, Copy)]
+    struct ArpEntry {
+        ip: u32,
+        mac: [u8; 6],
+        next: PPtr<ArpEntry>
+    }
+
+    #[derive(Clone, Copy)]
+    struct State {
+        mtu: u32,
+        entries: PPtr<ArpEntry>,
+        count: u64,
+        hostname: PPtr<[u8; 32]>
+    }
+
+    let path = std::env::temp_dir().join(format!("alloc-rs-persistent-{}.heap", std::process::id()));
+    {
+        let heap = PersistentHeap::create(&path, 60 * 1024).unwrap();
+        assert_eq!(heap.size(), 60 * 1024);
+        assert!(heap.root::<State>().is_none());
+        let mut entries = PPtr::null();
+        for i in 0..3u32 {
+            entries = heap.alloc_value(ArpEntry { ip: 0x0a00_0001 + i, mac: [2, 0, 0, 0, 0, i as u8], next: entries }).unwrap();
+        }
+        let mut name = [0u8; 32];
+        name[..5].copy_from_slice(b"rawnt");
+        let hostname = heap.alloc_value(name).unwrap();
+        let state = heap.alloc_value(State { mtu: 1500, entries, count: 3, hostname }).unwrap();
+        heap.set_root(state);
+        // a second user of the file is turned away while this one has it
+        assert!(PersistentHeap::open(&path).is_err());
+        assert!(heap.alloc(Layout::from_size_align(PERSISTENT_MAX_BLOCK + 1, 8).unwrap()).is_none());
+        assert!(heap.alloc(Layout::from_size_align(64, 32).unwrap()).is_none());
+    }
+
+    // everything is back, linked by offsets, in a mapping at some other address
+    let freed = {
+        let heap = PersistentHeap::open(&path).unwrap();
+        assert!(!heap.recovered());
+        assert!(heap.root::<u64>().is_none());
+        let root = heap.root::<State>().unwrap();
+        let state = unsafe { heap.get_mut(root) };
+        assert_eq!((state.mtu, state.count), (1500, 3));
+        assert_eq!(&unsafe { heap.get(state.hostname) }[..5], b"rawnt");
+        let mut ips = Vec::new();
+        let mut e = state.entries;
+        while !e.is_null() {
+            let entry = unsafe { heap.get(e) };
+            assert_eq!(entry.mac[5] as u32, entry.ip - 0x0a00_0001);
+            ips.push(entry.ip);
+            e = entry.next;
+        }
+        assert_eq!(ips, [0x0a00_0003, 0x0a00_0002, 0x0a00_0001]);
+
+        // unlink and free the newest entry
+        let gone = state.entries;
+        state.entries = unsafe { heap.get(gone) }.next;
+        state.count = 2;
+        assert!(unsafe { heap.free(gone) });
+        assert!(!unsafe { heap.free(gone) });
+        assert_eq!(heap.stats().free_blocks, 1);
+        gone
+    };
+
+    // the free list came back too
+    {
+        let heap = PersistentHeap::open(&path).unwrap();
+        let stats = heap.stats();
+        assert_eq!((stats.live_blocks, stats.free_blocks), (4, 1));
+        let again = heap.alloc_value(ArpEntry { ip: 0x0a00_0009, mac: [0; 6], next: PPtr::null() }).unwrap();
+        assert_eq!(again, freed);
+        assert!(unsafe { heap.free(again) });
+    }
+
+    // a crash half way through a free: the header is left dirty and the list head never written
+    {
+        let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start(core::mem::offset_of!(Header, state) as u64)).unwrap();
+        file.write_all(&1u64.to_ne_bytes()).unwrap();
+        file.seek(SeekFrom::Start(core::mem::offset_of!(Header, free) as u64)).unwrap();
+        file.write_all(&[0u8; 8]).unwrap();
+    }
+    {
+        let heap = PersistentHeap::open(&path).unwrap();
+        assert!(heap.recovered());
+        let again = heap.alloc_value(ArpEntry { ip: 0x0a00_0009, mac: [0; 6], next: PPtr::null() }).unwrap();
+        assert_eq!(again, freed);
+        let state = unsafe { heap.get(heap.root::<State>().unwrap()) };
+        assert_eq!(state.count, 2);
+        assert_eq!(unsafe { heap.get(state.entries) }.ip, 0x0a00_0002);
+    }
+    assert!(!PersistentHeap::open(&path).unwrap().recovered());
+
+    // a clean file whose free list points off into nowhere, open only trusts the block headers
+    {
+        let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start(core::mem::offset_of!(Header, free) as u64 + 8)).unwrap();
+        file.write_all(&0x7fff_0000_0010u64.to_ne_bytes()).unwrap();
+    }
+    {
+        let heap = PersistentHeap::open(&path).unwrap();
+        assert!(!heap.recovered());
+        let entry = heap.alloc_value(ArpEntry { ip: 0x0a00_000a, mac: [0; 6], next: PPtr::null() }).unwrap();
+        assert!((entry.offset() as usize) < heap.size());
+        assert!(unsafe { heap.free(entry) });
+    }
+
+    // fills up cleanly
+    {
+        let heap = PersistentHeap::create(&path, 8 * 1024).unwrap();
+        let mut n = 0;
+        while heap.alloc(Layout::new::<[u8; 1000]>()).is_some() {
+            n += 1;
+        }
+        assert_eq!(n, 7);
+        assert!(heap.alloc(Layout::new::<u64>()).is_some());
+    }
+    std::fs::remove_file(&path).unwrap();
+}
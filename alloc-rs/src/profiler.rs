@@ -0,0 +1,525 @@
+use core::cell::{Cell, UnsafeCell};
+use core::ffi::c_void;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU16, AtomicUsize, Ordering};
+
+// sampling heap profiler for the global allocator.
+// instead of tracking every allocation it samples the byte stream as a poisson process:
+// each thread counts down an exponentially distributed number of bytes (mean = the sampling
+// interval) and the allocation that crosses zero gets its stack recorded. a sampled allocation
+// of size s stands for s / (1 - e^(-s/mean)) bytes, so totals stay unbiased.
+// stacks are walked with the system unwinder into fixed arrays and kept in static tables, the
+// sampling path never allocates. symbolisation is left to the tools: pprof output carries the
+// mappings of /proc/self/maps, folded output takes a resolver and falls back to hex addresses.
+// every free of the global allocator comes through on_dealloc while profiling, nearly all of
+// them for blocks that were never sampled. live_filter counts the live samples per home slot of
+// the live table and is read without the lock, a free whose slot counts none is turned away
+// before it touches the lock.
+
+pub const MAX_FRAMES: usize = 32;
+const STACK_SLOTS: usize = 1024;
+const LIVE_SLOTS: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileKind {
+    // sampled allocations that have not been freed yet
+    Live,
+    // every sampled allocation since start
+    Allocations
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileSample {
+    // return addresses, innermost frame first
+    pub frames: Vec<usize>,
+    pub objects: u64,
+    pub bytes: u64
+}
+
+#[derive(Clone, Copy)]
+struct StackEntry {
+    hash: u64,
+    depth: usize,
+    frames: [usize; MAX_FRAMES],
+    alloc_objects: f64,
+    alloc_bytes: f64,
+    live_objects: f64,
+    live_bytes: f64
+}
+
+#[derive(Clone, Copy)]
+struct LiveEntry {
+    ptr: usize,
+    stack: usize,
+    objects: f64,
+    bytes: f64
+}
+
+const EMPTY_STACK: StackEntry = StackEntry {
+    hash: 0,
+    depth: 0,
+    frames: [0; MAX_FRAMES],
+    alloc_objects: 0.0,
+    alloc_bytes: 0.0,
+    live_objects: 0.0,
+    live_bytes: 0.0
+};
+const EMPTY_LIVE: LiveEntry = LiveEntry { ptr: 0, stack: 0, objects: 0.0, bytes: 0.0 };
+// a freed live slot, probing continues past it
+const TOMBSTONE: usize = 1;
+
+struct Tables {
+    stacks: [StackEntry; STACK_SLOTS],
+    live: [LiveEntry; LIVE_SLOTS]
+}
+
+pub struct HeapProfiler {
+    running: AtomicBool,
+    interval: AtomicUsize,
+    dropped: AtomicUsize,
+    lock: AtomicBool,
+    tables: UnsafeCell<Tables>,
+    // live samples per home slot, only written under the lock
+    live_filter: [AtomicU16; LIVE_SLOTS]
+}
+
+unsafe impl Sync for HeapProfiler {}
+
+pub static HEAP_PROFILER: HeapProfiler = HeapProfiler::new();
+
+std::thread_local! {
+    // bytes left until the next sample, negative means not drawn yet
+    static COUNTDOWN: Cell<i64> = const { Cell::new(-1) };
+    static RNG: Cell<u64> = const { Cell::new(0) };
+    // set while this thread is inside the profiler, its own allocations are never sampled
+    static IN_PROFILER: Cell<bool> = const { Cell::new(false) };
+}
+
+unsafe extern "C" {
+    fn _Unwind_Backtrace(trace: extern "C" fn(*mut c_void, *mut c_void) -> i32, arg: *mut c_void) -> i32;
+    fn _Unwind_GetIP(ctx: *mut c_void) -> usize;
+}
+
+struct StackBuf {
+    frames: [usize; MAX_FRAMES],
+    depth: usize,
+    skip: usize
+}
+
+extern "C" fn unwind_frame(ctx: *mut c_void, arg: *mut c_void) -> i32 {
+    let buf = unsafe { &mut *(arg as *mut StackBuf) };
+    if buf.skip > 0 {
+        buf.skip -= 1;
+        return 0;
+    }
+    let ip = unsafe { _Unwind_GetIP(ctx) };
+    if ip == 0 || buf.depth == MAX_FRAMES {
+        // anything but _URC_NO_REASON stops the walk
+        return 5;
+    }
+    buf.frames[buf.depth] = ip;
+    buf.depth += 1;
+    0
+}
+
+#[inline(never)]
+fn capture_stack() -> StackBuf {
+    let mut buf = StackBuf { frames: [0; MAX_FRAMES], depth: 0, skip: 1 };
+    unsafe { _Unwind_Backtrace(unwind_frame, &mut buf as *mut StackBuf as *mut c_void) };
+    buf
+}
+
+// runs f with the reentrancy flag set, None if this thread is already inside the profiler
+fn guarded<R>(f: impl FnOnce() -> R) -> Option<R> {
+    let entered = IN_PROFILER.try_with(|flag| !flag.replace(true)).unwrap_or(false);
+    if !entered {
+        return None;
+    }
+    let result = f();
+    let _ = IN_PROFILER.try_with(|flag| flag.set(false));
+    Some(result)
+}
+
+fn next_random() -> f64 {
+    RNG.try_with(|rng| {
+        let mut x = rng.get();
+        if x == 0 {
+            // seed from the thread local's own address, distinct per thread
+            x = (rng as *const Cell<u64> as u64) ^ 0x9E37_79B9_7F4A_7C15;
+        }
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        rng.set(x);
+        // 53 random bits in (0, 1]
+        ((x >> 11) as f64 + 1.0) / (1u64 << 53) as f64
+    }).unwrap_or(0.5)
+}
+
+fn draw_countdown(mean: usize) -> i64 {
+    (-(next_random().ln()) * mean as f64) as i64
+}
+
+// the live table slot a pointer's probing starts at
+fn home_slot(ptr: usize) -> usize {
+    (ptr.wrapping_mul(0x9E37_79B9_7F4A_7C15u64 as usize) >> 20) & (LIVE_SLOTS - 1)
+}
+
+fn hash_frames(frames: &[usize]) -> u64 {
+    // fnv-1a, 0 marks an empty slot so it is never handed out
+    let mut h = 0xcbf2_9ce4_8422_2325u64;
+    for &f in frames {
+        h ^= f as u64;
+        h = h.wrapping_mul(0x0100_0000_01b3);
+    }
+    h.max(1)
+}
+
+impl HeapProfiler {
+    pub const fn new() -> Self {
+        Self {
+            running: AtomicBool::new(false),
+            interval: AtomicUsize::new(512 * 1024),
+            dropped: AtomicUsize::new(0),
+            lock: AtomicBool::new(false),
+            tables: UnsafeCell::new(Tables { stacks: [EMPTY_STACK; STACK_SLOTS], live: [EMPTY_LIVE; LIVE_SLOTS] }),
+            live_filter: [const { AtomicU16::new(0) }; LIVE_SLOTS]
+        }
+    }
+
+    // starts sampling roughly every mean_interval_bytes allocated bytes
+    pub fn start(&self, mean_interval_bytes: usize) {
+        self.interval.store(mean_interval_bytes.max(1), Ordering::Relaxed);
+        self.running.store(true, Ordering::Release);
+    }
+
+    // recorded samples stay around for export, frees after this point are no longer tracked
+    pub fn stop(&self) {
+        self.running.store(false, Ordering::Release);
+    }
+
+    #[inline]
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    pub fn reset(&self) {
+        self.with_tables(|tables| {
+            tables.stacks.fill(EMPTY_STACK);
+            tables.live.fill(EMPTY_LIVE);
+            for count in &self.live_filter {
+                count.store(0, Ordering::Relaxed);
+            }
+        });
+        self.dropped.store(0, Ordering::Relaxed);
+    }
+
+    // samples that found their stack or live table full
+    pub fn dropped_samples(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn on_alloc(&self, ptr: *mut u8, size: usize) {
+        if ptr.is_null() || size == 0 {
+            return;
+        }
+        let mean = self.interval.load(Ordering::Relaxed);
+        let sampled = COUNTDOWN.try_with(|countdown| {
+            let mut left = countdown.get();
+            if left < 0 {
+                left = draw_countdown(mean);
+            }
+            left -= size as i64;
+            if left > 0 {
+                countdown.set(left);
+                return false;
+            }
+            countdown.set(draw_countdown(mean));
+            true
+        }).unwrap_or(false);
+        if !sampled {
+            return;
+        }
+        let _ = guarded(|| {
+            let stack = capture_stack();
+            let probability = 1.0 - (-(size as f64) / mean as f64).exp();
+            let objects = 1.0 / probability;
+            let bytes = size as f64 / probability;
+            self.with_tables(|tables| {
+                if tables.record(ptr as usize, &stack.frames[..stack.depth], objects, bytes) {
+                    self.live_filter[home_slot(ptr as usize)].fetch_add(1, Ordering::Relaxed);
+                } else {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            });
+        });
+    }
+
+    pub(crate) fn on_dealloc(&self, ptr: *mut u8) {
+        // the sample was recorded before the block was handed out, so whoever frees it sees the count
+        let slot = home_slot(ptr as usize);
+        if self.live_filter[slot].load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        let _ = guarded(|| self.with_tables(|tables| {
+            if tables.release(ptr as usize) {
+                self.live_filter[slot].fetch_sub(1, Ordering::Relaxed);
+            }
+        }));
+    }
+
+    // aggregated per stack, stacks without any weight for the kind are left out
+    pub fn profile(&self, kind: ProfileKind) -> Vec<ProfileSample> {
+        // nothing may allocate while the tables are locked, copy them out a batch at a time
+        const BATCH: usize = 16;
+        let mut samples = Vec::new();
+        for first in (0..STACK_SLOTS).step_by(BATCH) {
+            let mut batch = [EMPTY_STACK; BATCH];
+            if guarded(|| self.with_tables(|tables| batch.copy_from_slice(&tables.stacks[first..first + BATCH]))).is_none() {
+                break;
+            }
+            for s in batch.iter().filter(|s| s.hash != 0) {
+                let (objects, bytes) = match kind {
+                    ProfileKind::Live => (s.live_objects, s.live_bytes),
+                    ProfileKind::Allocations => (s.alloc_objects, s.alloc_bytes),
+                };
+                if bytes >= 0.5 {
+                    samples.push(ProfileSample { frames: s.frames[..s.depth].to_vec(), objects: objects.round() as u64, bytes: bytes.round() as u64 });
+                }
+            }
+        }
+        samples
+    }
+
+    // one "outer;..;inner bytes" line per stack, the format flamegraph.pl and inferno read.
+    // resolve maps a return address to a frame name, unresolved frames print as hex.
+    pub fn write_folded(&self, kind: ProfileKind, resolve: impl Fn(usize) -> Option<String>, out: &mut impl fmt::Write) -> fmt::Result {
+        for sample in self.profile(kind) {
+            for (i, &ip) in sample.frames.iter().rev().enumerate() {
+                if i > 0 {
+                    out.write_char(';')?;
+                }
+                match resolve(ip) {
+                    Some(name) => out.write_str(&name)?,
+                    None => write!(out, "{ip:#x}")?,
+                }
+            }
+            writeln!(out, " {}", sample.bytes)?;
+        }
+        Ok(())
+    }
+
+    pub fn folded(&self, kind: ProfileKind) -> String {
+        let mut out = String::new();
+        let _ = self.write_folded(kind, |_| None, &mut out);
+        out
+    }
+
+    // uncompressed pprof protobuf (profile.proto), go tool pprof and friends read it as is
+    // and symbolise the addresses against the binaries named in the mappings.
+    pub fn pprof(&self, kind: ProfileKind) -> Vec<u8> {
+        let samples = self.profile(kind);
+        let (objects_name, bytes_name) = match kind {
+            ProfileKind::Live => ("inuse_objects", "inuse_space"),
+            ProfileKind::Allocations => ("alloc_objects", "alloc_space"),
+        };
+        let mut strings = StringTable::default();
+        let mut profile = Vec::new();
+
+        for (name, unit) in [(objects_name, "count"), (bytes_name, "bytes")] {
+            let mut value_type = Vec::new();
+            put_varint_field(&mut value_type, 1, strings.index(name));
+            put_varint_field(&mut value_type, 2, strings.index(unit));
+            put_bytes_field(&mut profile, 1, &value_type);
+        }
+
+        // every distinct address becomes one location, ids start at 1
+        let mut addresses: Vec<usize> = samples.iter().flat_map(|s| s.frames.iter().copied()).collect();
+        addresses.sort_unstable();
+        addresses.dedup();
+        let location_id = |ip: usize| addresses.binary_search(&ip).map(|i| i as u64 + 1).unwrap_or(0);
+
+        for sample in &samples {
+            let mut ids = Vec::new();
+            for &ip in &sample.frames {
+                put_varint(&mut ids, location_id(ip));
+            }
+            let mut values = Vec::new();
+            put_varint(&mut values, sample.objects);
+            put_varint(&mut values, sample.bytes);
+            let mut message = Vec::new();
+            put_bytes_field(&mut message, 1, &ids);
+            put_bytes_field(&mut message, 2, &values);
+            put_bytes_field(&mut profile, 2, &message);
+        }
+
+        let mappings = read_mappings();
+        for (i, mapping) in mappings.iter().enumerate() {
+            let mut message = Vec::new();
+            put_varint_field(&mut message, 1, i as u64 + 1);
+            put_varint_field(&mut message, 2, mapping.start as u64);
+            put_varint_field(&mut message, 3, mapping.end as u64);
+            put_varint_field(&mut message, 4, mapping.offset as u64);
+            put_varint_field(&mut message, 5, strings.index(&mapping.path));
+            put_bytes_field(&mut profile, 3, &message);
+        }
+
+        for (i, &ip) in addresses.iter().enumerate() {
+            // return addresses point past the call, step back into it for the symboliser
+            let address = ip.saturating_sub(1);
+            let mapping = mappings.iter().position(|m| address >= m.start && address < m.end).map(|m| m as u64 + 1).unwrap_or(0);
+            let mut message = Vec::new();
+            put_varint_field(&mut message, 1, i as u64 + 1);
+            put_varint_field(&mut message, 2, mapping);
+            put_varint_field(&mut message, 3, address as u64);
+            put_bytes_field(&mut profile, 4, &message);
+        }
+
+        for s in &strings.strings {
+            put_bytes_field(&mut profile, 6, s.as_bytes());
+        }
+        profile
+    }
+
+    fn with_tables<R>(&self, f: impl FnOnce(&mut Tables) -> R) -> R {
+        while self.lock.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            core::hint::spin_loop();
+        }
+        let result = f(unsafe { &mut *self.tables.get() });
+        self.lock.store(false, Ordering::Release);
+        result
+    }
+}
+
+impl Default for HeapProfiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tables {
+    fn record(&mut self, ptr: usize, frames: &[usize], objects: f64, bytes: f64) -> bool {
+        let hash = hash_frames(frames);
+        let mut stack = None;
+        for probe in 0..STACK_SLOTS {
+            let idx = (hash as usize + probe) & (STACK_SLOTS - 1);
+            let entry = &mut self.stacks[idx];
+            if entry.hash == 0 {
+                entry.hash = hash;
+                entry.depth = frames.len();
+                entry.frames[..frames.len()].copy_from_slice(frames);
+                stack = Some(idx);
+                break;
+            }
+            if entry.hash == hash && entry.frames[..entry.depth] == *frames {
+                stack = Some(idx);
+                break;
+            }
+        }
+        let stack = match stack {
+            Some(stack) => stack,
+            None => return false,
+        };
+        let entry = &mut self.stacks[stack];
+        entry.alloc_objects += objects;
+        entry.alloc_bytes += bytes;
+
+        let h = home_slot(ptr);
+        for probe in 0..LIVE_SLOTS {
+            let slot = &mut self.live[(h + probe) & (LIVE_SLOTS - 1)];
+            if slot.ptr == 0 || slot.ptr == TOMBSTONE {
+                *slot = LiveEntry { ptr, stack, objects, bytes };
+                entry.live_objects += objects;
+                entry.live_bytes += bytes;
+                return true;
+            }
+        }
+        false
+    }
+
+    // false if ptr wasn't a live sample
+    fn release(&mut self, ptr: usize) -> bool {
+        let h = home_slot(ptr);
+        for probe in 0..LIVE_SLOTS {
+            let slot = &mut self.live[(h + probe) & (LIVE_SLOTS - 1)];
+            if slot.ptr == 0 {
+                return false;
+            }
+            if slot.ptr == ptr {
+                let stack = &mut self.stacks[slot.stack];
+                stack.live_objects -= slot.objects;
+                stack.live_bytes -= slot.bytes;
+                slot.ptr = TOMBSTONE;
+                return true;
+            }
+        }
+        false
+    }
+}
+
+#[derive(Default)]
+struct StringTable {
+    strings: Vec<String>
+}
+
+impl StringTable {
+    fn index(&mut self, s: &str) -> u64 {
+        if self.strings.is_empty() {
+            // pprof requires "" at index 0
+            self.strings.push(String::new());
+        }
+        match self.strings.iter().position(|existing| existing == s) {
+            Some(i) => i as u64,
+            None => {
+                self.strings.push(s.to_string());
+                self.strings.len() as u64 - 1
+            }
+        }
+    }
+}
+
+struct Mapping {
+    start: usize,
+    end: usize,
+    offset: usize,
+    path: String
+}
+
+// executable mappings of the current process, from /proc/self/maps
+fn read_mappings() -> Vec<Mapping> {
+    let maps = std::fs::read_to_string("/proc/self/maps").unwrap_or_default();
+    maps.lines().filter_map(|line| {
+        let mut fields = line.split_whitespace();
+        let (range, perms, offset) = (fields.next()?, fields.next()?, fields.next()?);
+        let path = fields.nth(2).unwrap_or("");
+        if !perms.contains('x') {
+            return None;
+        }
+        let (start, end) = range.split_once('-')?;
+        Some(Mapping {
+            start: usize::from_str_radix(start, 16).ok()?,
+            end: usize::from_str_radix(end, 16).ok()?,
+            offset: usize::from_str_radix(offset, 16).ok()?,
+            path: path.to_string()
+        })
+    }).collect()
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn put_varint_field(out: &mut Vec<u8>, field: u64, value: u64) {
+    put_varint(out, field << 3);
+    put_varint(out, value);
+}
+
+fn put_bytes_field(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    put_varint(out, (field << 3) | 2);
+    put_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
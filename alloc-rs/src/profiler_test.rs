@@ -0,0 +1,48 @@
+use crate::profiler::{HeapProfiler, ProfileKind};
+
+#[test]
+pub fn heap_profiler_test() {
+    static PROFILER: HeapProfiler = HeapProfiler::new();
+    static mut BLOCKS: [u64; 4] = [0; 4];
+
+    #[inline(never)]
+    fn sampled_alloc(ptr: *mut u8) {
+        PROFILER.on_alloc(ptr, 64);
+    }
+
+    // an interval of one byte samples every allocation with weight ~1
+    PROFILER.start(1);
+    let blocks = &raw mut BLOCKS as *mut u64;
+    for i in 0..4 {
+        sampled_alloc(unsafe { blocks.add(i) } as *mut u8);
+    }
+    PROFILER.on_dealloc(blocks as *mut u8);
+    PROFILER.on_dealloc(unsafe { blocks.add(1) } as *mut u8);
+    PROFILER.stop();
+
+    let live = PROFILER.profile(ProfileKind::Live);
+    let allocs = PROFILER.profile(ProfileKind::Allocations);
+    assert!(!live.is_empty() && live.iter().all(|s| !s.frames.is_empty()), "samples carry a stack");
+    assert_eq!(live.iter().map(|s| s.bytes).sum::<u64>(), 2 * 64);
+    assert_eq!(allocs.iter().map(|s| (s.objects, s.bytes)).fold((0, 0), |a, s| (a.0 + s.0, a.1 + s.1)), (4, 4 * 64));
+    assert_eq!(PROFILER.dropped_samples(), 0);
+
+    let folded = PROFILER.folded(ProfileKind::Live);
+    assert!(folded.lines().all(|line| line.starts_with("0x")), "{folded}");
+    assert_eq!(folded.lines().map(|line| line.rsplit(' ').next().unwrap().parse::<u64>().unwrap()).sum::<u64>(), 128);
+    let named = {
+        let mut out = String::new();
+        PROFILER.write_folded(ProfileKind::Allocations, |_| Some("f".to_string()), &mut out).unwrap();
+        out
+    };
+    assert!(named.starts_with("f;f"), "{named}");
+
+    let pprof = PROFILER.pprof(ProfileKind::Live);
+    // first field is the sample_type value type, length delimited
+    assert_eq!(pprof[0], (1 << 3) | 2);
+    let contains = |needle: &[u8]| pprof.windows(needle.len()).any(|w| w == needle);
+    assert!(contains(b"inuse_space") && contains(b"inuse_objects"));
+
+    PROFILER.reset();
+    assert!(PROFILER.profile(ProfileKind::Allocations).is_empty());
+}
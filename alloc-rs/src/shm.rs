@@ -0,0 +1,476 @@
+use core::ffi::{c_char, c_void};
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use std::path::Path;
+
+// slab allocator in a shared memory object (memfd or a /dev/shm file) that several processes
+// map at the same time, e.g. capture hands filled packet buffers to analysis without a copy.
+// the object maps at a different address in every process, so everything in it is an offset
+// from its start: free list links, the page table, the buffers handed around (ShmBuf).
+// layout: page 0 holds the header, a mailbox and the page table, the rest are SHM_PAGE sized
+// pages that the bump hands out one at a time to a size class, like composite does for slab.
+// no locks: a process can die anywhere, and a lock it held would wedge everyone else. the free
+// lists are treiber stacks whose head carries a tag against aba, the bump is a fetch_add.
+// ownership: every block starts with the pid that owns it (0 free). free, send and recv move
+// it with a cas, and reclaim_dead() frees the blocks of processes that are gone. a process
+// dying between popping a block and stamping its pid leaks that one block until rebuild(),
+// which re-derives the free lists from the owner words while nobody else has the object mapped.
+// pids get reused, a long dead owner whose pid came back keeps its blocks.
+// the mailbox takes any number of senders and receivers: both sides claim a position with a
+// cas on tail or head, and every slot carries the position it was written for, so a receiver
+// can tell a published slot from one still waiting for its sender. a sender dying between the
+// claim and the write stalls the mailbox at that slot until rebuild(). the offsets in it come
+// out of shared memory like everything else, recv checks them before it stamps anything.
+
+pub const SHM_PAGE: usize = 64 * 1024;
+pub const SHM_MAX_CLASSES: usize = 8;
+pub const SHM_MAILBOX_SLOTS: usize = 1024;
+const BLOCK_HEADER: usize = 16;
+const SHM_MAGIC: u64 = 0x5348_4d53_4c41_4231;
+const SHM_VERSION: u32 = 2;
+// owner of a block that sits in the mailbox
+const OWNER_QUEUED: u32 = u32::MAX;
+
+const PROT_READ: i32 = 1;
+const PROT_WRITE: i32 = 2;
+const MAP_SHARED: i32 = 1;
+const MAP_FAILED: *mut c_void = !0usize as *mut c_void;
+const MFD_CLOEXEC: u32 = 1;
+const EPERM: i32 = 1;
+
+unsafe extern "C" {
+    fn mmap(addr: *mut c_void, len: usize, prot: i32, flags: i32, fd: i32, offset: i64) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> i32;
+    fn memfd_create(name: *const c_char, flags: u32) -> i32;
+    fn getpid() -> i32;
+    fn kill(pid: i32, sig: i32) -> i32;
+}
+
+#[repr(C)]
+struct ShmHeader {
+    magic: u64,
+    version: u32,
+    page_count: u32,
+    class_count: u32,
+    classes: [u32; SHM_MAX_CLASSES],
+    next_page: AtomicU32,
+    // (tag << 32) | block offset, 0 offset for empty
+    free: [AtomicU64; SHM_MAX_CLASSES],
+    // ring of (position << 32) | block offset
+    mailbox_head: AtomicU32,
+    mailbox_tail: AtomicU32,
+    mailbox: [AtomicU64; SHM_MAILBOX_SLOTS]
+}
+
+// then one AtomicU32 per page: 0 not handed out yet, class + 1 once it is
+const PAGE_TABLE: usize = core::mem::size_of::<ShmHeader>().next_multiple_of(8);
+// page 0 is the header, so this many pages fit in the table
+pub const SHM_MAX_PAGES: usize = (SHM_PAGE - PAGE_TABLE) / 4;
+
+// the start of a block, header included
+#[repr(C)]
+struct BlockHeader {
+    owner: AtomicU32,
+    // next free block's offset
+    link: AtomicU32,
+    // set when the page is carved, before anyone can reach the block
+    class: AtomicU32,
+    _pad: u32
+}
+
+// a buffer in the shared object, the same in every process that maps it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShmBuf {
+    offset: u32
+}
+
+impl ShmBuf {
+    pub const fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    // for offsets that came over some other channel, checked again on use
+    pub const fn from_offset(offset: u32) -> Self {
+        Self { offset }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ShmStats {
+    pub pages_used: usize,
+    pub page_count: usize,
+    pub free_blocks: usize,
+    // owned by a process or queued in the mailbox
+    pub owned_blocks: usize,
+    pub queued_blocks: usize
+}
+
+pub struct SharedHeap {
+    base: *mut u8,
+    len: usize,
+    file: File
+}
+
+// everything shared goes through atomics
+unsafe impl Send for SharedHeap {}
+unsafe impl Sync for SharedHeap {}
+
+impl SharedHeap {
+    // a new anonymous object, other processes get it through fd() (fork, SCM_RIGHTS)
+    pub fn create_memfd(name: &str, size: usize, classes: &[usize]) -> io::Result<Self> {
+        let name = CString::new(name).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "nul in memfd name"))?;
+        let fd = unsafe { memfd_create(name.as_ptr(), MFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Self::format(unsafe { File::from_raw_fd(fd) }, size, classes)
+    }
+
+    // a named object, e.g. under /dev/shm, that other processes open()
+    pub fn create(path: impl AsRef<Path>, size: usize, classes: &[usize]) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)?;
+        Self::format(file, size, classes)
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::attach(OpenOptions::new().read(true).write(true).open(path)?)
+    }
+
+    // maps an object created elsewhere. the fd is duplicated, the caller keeps its own
+    pub fn from_fd(fd: RawFd) -> io::Result<Self> {
+        let file = unsafe { std::mem::ManuallyDrop::new(File::from_raw_fd(fd)) };
+        Self::attach(file.try_clone()?)
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+
+    // a block of at least size bytes, owned by this process. None when no class fits or the
+    // object is full
+    pub fn alloc(&self, size: usize) -> Option<ShmBuf> {
+        let header = self.header();
+        let class = header.classes[..header.class_count as usize].iter().position(|&c| c as usize >= size)?;
+        loop {
+            if let Some(block) = self.pop(class) {
+                // a free block has owner 0 and nobody else can reach it once popped
+                self.block(block).owner.store(pid(), Ordering::Release);
+                return Some(ShmBuf { offset: block + BLOCK_HEADER as u32 });
+            }
+            if !self.refill(class) {
+                return None;
+            }
+        }
+    }
+
+    // false, and nothing freed, unless this process owns buf
+    pub fn free(&self, buf: ShmBuf) -> bool {
+        let Some(block) = self.block_of(buf) else {
+            return false;
+        };
+        if self.block(block).owner.compare_exchange(pid(), 0, Ordering::AcqRel, Ordering::Acquire).is_err() {
+            return false;
+        }
+        self.push(block);
+        true
+    }
+
+    // hands buf to whoever calls recv next, false if it isn't ours or the mailbox is full
+    pub fn send(&self, buf: ShmBuf) -> bool {
+        let Some(block) = self.block_of(buf) else {
+            return false;
+        };
+        // queued first, so only the owner gets to send it
+        let owner = &self.block(block).owner;
+        if owner.compare_exchange(pid(), OWNER_QUEUED, Ordering::AcqRel, Ordering::Acquire).is_err() {
+            return false;
+        }
+        let header = self.header();
+        let mut tail = header.mailbox_tail.load(Ordering::Acquire);
+        loop {
+            if tail.wrapping_sub(header.mailbox_head.load(Ordering::Acquire)) as usize >= SHM_MAILBOX_SLOTS {
+                owner.store(pid(), Ordering::Release);
+                return false;
+            }
+            match header.mailbox_tail.compare_exchange_weak(tail, tail.wrapping_add(1), Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => break,
+                Err(actual) => tail = actual,
+            }
+        }
+        // the receiver of the slot's last position moved head past it before we could claim it
+        header.mailbox[tail as usize % SHM_MAILBOX_SLOTS].store(((tail as u64) << 32) | block as u64, Ordering::Release);
+        true
+    }
+
+    // the oldest buffer sent, now owned by this process. None also while the oldest sender is
+    // still between its claim and its write. entries that aren't a queued block are dropped
+    pub fn recv(&self) -> Option<ShmBuf> {
+        let header = self.header();
+        let mut head = header.mailbox_head.load(Ordering::Acquire);
+        loop {
+            if head == header.mailbox_tail.load(Ordering::Acquire) {
+                return None;
+            }
+            let entry = header.mailbox[head as usize % SHM_MAILBOX_SLOTS].load(Ordering::Acquire);
+            if (entry >> 32) as u32 != head {
+                return None;
+            }
+            if let Err(actual) = header.mailbox_head.compare_exchange_weak(head, head.wrapping_add(1), Ordering::AcqRel, Ordering::Acquire) {
+                head = actual;
+                continue;
+            }
+            head = head.wrapping_add(1);
+            let buf = ShmBuf { offset: (entry as u32).wrapping_add(BLOCK_HEADER as u32) };
+            if let Some(block) = self.block_of(buf)
+                && self.block(block).owner.compare_exchange(OWNER_QUEUED, pid(), Ordering::AcqRel, Ordering::Acquire).is_ok() {
+                return Some(buf);
+            }
+        }
+    }
+
+    // where buf lives in this process' mapping, null for an offset that isn't a block
+    pub fn ptr(&self, buf: ShmBuf) -> *mut u8 {
+        match self.block_of(buf) {
+            Some(_) => unsafe { self.base.add(buf.offset as usize) },
+            None => core::ptr::null_mut(),
+        }
+    }
+
+    pub fn capacity(&self, buf: ShmBuf) -> usize {
+        self.block_of(buf).map_or(0, |block| self.header().classes[self.block(block).class.load(Ordering::Relaxed) as usize] as usize)
+    }
+
+    /// # Safety
+    /// buf must be owned by this process and nothing else may use its bytes meanwhile
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn bytes_mut(&self, buf: ShmBuf) -> &mut [u8] {
+        let p = self.ptr(buf);
+        assert!(!p.is_null());
+        unsafe { core::slice::from_raw_parts_mut(p, self.capacity(buf)) }
+    }
+
+    // the pid stamped on buf, None for a free block or one in the mailbox
+    pub fn owner(&self, buf: ShmBuf) -> Option<u32> {
+        let owner = self.block(self.block_of(buf)?).owner.load(Ordering::Acquire);
+        (owner != 0 && owner != OWNER_QUEUED).then_some(owner)
+    }
+
+    // frees every block whose owner has exited, returns how many. safe to run at any time
+    pub fn reclaim_dead(&self) -> usize {
+        let mut reclaimed = 0;
+        self.for_each_block(|block, owner| {
+            if owner != 0 && owner != OWNER_QUEUED && owner != pid() && !alive(owner)
+                && self.block(block).owner.compare_exchange(owner, 0, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+                self.push(block);
+                reclaimed += 1;
+            }
+        });
+        reclaimed
+    }
+
+    /// rebuilds the free lists from the owner words, picking up blocks lost by a process that
+    /// died mid alloc and freeing those of dead owners. the mailbox is emptied too, its blocks
+    /// are freed by their queued owner word
+    /// # Safety
+    /// no other process may use the object while it runs
+    pub unsafe fn rebuild(&self) {
+        let header = self.header();
+        for head in &header.free {
+            head.store(0, Ordering::Relaxed);
+        }
+        header.mailbox_head.store(header.mailbox_tail.load(Ordering::Relaxed), Ordering::Relaxed);
+        self.for_each_block(|block, owner| {
+            if owner == 0 || owner == OWNER_QUEUED || (owner != pid() && !alive(owner)) {
+                self.block(block).owner.store(0, Ordering::Relaxed);
+                self.push(block);
+            }
+        });
+    }
+
+    pub fn stats(&self) -> ShmStats {
+        let header = self.header();
+        let mut stats = ShmStats {
+            pages_used: (header.next_page.load(Ordering::Acquire) as usize).min(header.page_count as usize) - 1,
+            page_count: header.page_count as usize - 1,
+            ..Default::default()
+        };
+        self.for_each_block(|_, owner| match owner {
+            0 => stats.free_blocks += 1,
+            OWNER_QUEUED => stats.queued_blocks += 1,
+            _ => stats.owned_blocks += 1,
+        });
+        stats
+    }
+
+    fn format(file: File, size: usize, classes: &[usize]) -> io::Result<Self> {
+        let pages = size.div_ceil(SHM_PAGE);
+        let bad_class = |&c: &usize| c == 0 || c % 16 != 0 || c > SHM_PAGE - BLOCK_HEADER;
+        if !(2..=SHM_MAX_PAGES).contains(&pages) || classes.is_empty() || classes.len() > SHM_MAX_CLASSES
+            || classes.iter().any(bad_class) || !classes.is_sorted() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "bad shared heap shape"));
+        }
+        file.set_len((pages * SHM_PAGE) as u64)?;
+        let heap = Self::map(file)?;
+        // the object is fresh and zero filled, nobody else has it yet
+        let header = heap.base as *mut ShmHeader;
+        unsafe {
+            (*header).version = SHM_VERSION;
+            (*header).page_count = pages as u32;
+            (*header).class_count = classes.len() as u32;
+            for (i, &c) in classes.iter().enumerate() {
+                (*header).classes[i] = c as u32;
+            }
+            (*header).next_page = AtomicU32::new(1);
+        }
+        heap.header_magic().store(SHM_MAGIC, Ordering::Release);
+        Ok(heap)
+    }
+
+    fn attach(file: File) -> io::Result<Self> {
+        let heap = Self::map(file)?;
+        let header = heap.header();
+        if heap.len < SHM_PAGE || heap.header_magic().load(Ordering::Acquire) != SHM_MAGIC || header.version != SHM_VERSION
+            || header.page_count as usize * SHM_PAGE != heap.len {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a shared heap"));
+        }
+        Ok(heap)
+    }
+
+    fn map(file: File) -> io::Result<Self> {
+        let len = file.metadata()?.len() as usize;
+        if len < SHM_PAGE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "shared heap too small"));
+        }
+        let base = unsafe { mmap(core::ptr::null_mut(), len, PROT_READ | PROT_WRITE, MAP_SHARED, file.as_raw_fd(), 0) };
+        if base == MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { base: base as *mut u8, len, file })
+    }
+
+    fn header(&self) -> &ShmHeader {
+        unsafe { &*(self.base as *const ShmHeader) }
+    }
+
+    // the magic is published last, read it as an atomic
+    fn header_magic(&self) -> &AtomicU64 {
+        unsafe { &*(self.base as *const AtomicU64) }
+    }
+
+    fn page_class(&self, page: usize) -> &AtomicU32 {
+        unsafe { &*(self.base.add(PAGE_TABLE + page * 4) as *const AtomicU32) }
+    }
+
+    fn block(&self, block: u32) -> &BlockHeader {
+        unsafe { &*(self.base.add(block as usize) as *const BlockHeader) }
+    }
+
+    // the block header of buf, if buf is the start of a block in a page that has been handed out
+    fn block_of(&self, buf: ShmBuf) -> Option<u32> {
+        let offset = buf.offset as usize;
+        let page = offset / SHM_PAGE;
+        if page == 0 || page >= self.header().page_count as usize {
+            return None;
+        }
+        let class = (self.page_class(page).load(Ordering::Acquire) as usize).checked_sub(1)?;
+        let stride = self.header().classes[class] as usize + BLOCK_HEADER;
+        let in_page = (offset % SHM_PAGE).checked_sub(BLOCK_HEADER)?;
+        (in_page % stride == 0 && in_page + stride <= SHM_PAGE).then_some((offset - BLOCK_HEADER) as u32)
+    }
+
+    fn pop(&self, class: usize) -> Option<u32> {
+        let head = &self.header().free[class];
+        let mut current = head.load(Ordering::Acquire);
+        loop {
+            let block = current as u32;
+            if block == 0 {
+                return None;
+            }
+            // the link may be stale if block was popped meanwhile, then the tag check fails
+            let next = self.block(block).link.load(Ordering::Acquire);
+            let new = (((current >> 32) + 1) << 32) | next as u64;
+            match head.compare_exchange_weak(current, new, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return Some(block),
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    fn push(&self, block: u32) {
+        let class = self.block(block).class.load(Ordering::Relaxed) as usize;
+        self.push_chain(class, block, block);
+    }
+
+    // first..=last already linked through their link words
+    fn push_chain(&self, class: usize, first: u32, last: u32) {
+        let head = &self.header().free[class];
+        let mut current = head.load(Ordering::Acquire);
+        loop {
+            self.block(last).link.store(current as u32, Ordering::Release);
+            let new = (((current >> 32) + 1) << 32) | first as u64;
+            match head.compare_exchange_weak(current, new, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return,
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    // takes a fresh page for class and puts its blocks on the free list, false when none is left
+    fn refill(&self, class: usize) -> bool {
+        let header = self.header();
+        let page = header.next_page.fetch_add(1, Ordering::AcqRel) as usize;
+        if page >= header.page_count as usize {
+            // keeps the counter from wrapping on a full object
+            header.next_page.store(header.page_count, Ordering::Release);
+            return false;
+        }
+        let stride = header.classes[class] as usize + BLOCK_HEADER;
+        let count = SHM_PAGE / stride;
+        let first = (page * SHM_PAGE) as u32;
+        for i in 0..count {
+            let block = self.block(first + (i * stride) as u32);
+            block.owner.store(0, Ordering::Relaxed);
+            block.link.store(first + ((i + 1) * stride) as u32, Ordering::Relaxed);
+            block.class.store(class as u32, Ordering::Relaxed);
+        }
+        // blocks are set up before the page shows up in the table, a walker never sees them half done
+        self.page_class(page).store(class as u32 + 1, Ordering::Release);
+        self.push_chain(class, first, first + ((count - 1) * stride) as u32);
+        true
+    }
+
+    // f(block, owner) for every block of every page handed out so far
+    fn for_each_block(&self, mut f: impl FnMut(u32, u32)) {
+        let header = self.header();
+        let used = (header.next_page.load(Ordering::Acquire) as usize).min(header.page_count as usize);
+        for page in 1..used {
+            let Some(class) = (self.page_class(page).load(Ordering::Acquire) as usize).checked_sub(1) else {
+                continue;
+            };
+            let stride = header.classes[class] as usize + BLOCK_HEADER;
+            for i in 0..SHM_PAGE / stride {
+                let block = (page * SHM_PAGE + i * stride) as u32;
+                f(block, self.block(block).owner.load(Ordering::Acquire));
+            }
+        }
+    }
+}
+
+impl Drop for SharedHeap {
+    fn drop(&mut self) {
+        unsafe { munmap(self.base as *mut c_void, self.len) };
+    }
+}
+
+// asked every time rather than cached, a forked child keeps using the parent's SharedHeap
+fn pid() -> u32 {
+    unsafe { getpid() as u32 }
+}
+
+// kill with signal 0 only checks, EPERM means it exists but belongs to someone else
+fn alive(pid: u32) -> bool {
+    unsafe { kill(pid as i32, 0) == 0 || io::Error::last_os_error().raw_os_error() == Some(EPERM) }
+}
@@ -0,0 +1,96 @@
+use crate::shm::{SharedHeap, SHM_PAGE};
+
+unsafe extern "C" {
+    fn fork() -> i32;
+    fn _exit(status: i32) -> !;
+    fn waitpid(pid: i32, status: *mut i32, options: i32) -> i32;
+}
+
+#[test]
+pub fn shared_heap_test() {
+    let capture = SharedHeap::create_memfd("alloc-rs-shm-test", 4 * SHM_PAGE, &[256, 2048]).unwrap();
+    // a second mapping of the same object, like the analysis process would have
+    let analysis = SharedHeap::from_fd(capture.fd()).unwrap();
+    assert!(SharedHeap::create_memfd("bad", 4 * SHM_PAGE, &[100]).is_err());
+
+    let buf = capture.alloc(1500).unwrap();
+    assert_eq!(capture.capacity(buf), 2048);
+    assert!(capture.alloc(4096).is_none());
+    let packet = unsafe { capture.bytes_mut(buf) };
+    packet[..4].copy_from_slice(&[0x45, 0, 0x05, 0xdc]);
+    assert_ne!(capture.ptr(buf), analysis.ptr(buf));
+
+    // handed over without a copy, the offset means the same in both mappings
+    assert!(analysis.recv().is_none());
+    assert!(capture.send(buf));
+    assert!(!capture.send(buf));
+    assert_eq!(capture.owner(buf), None);
+    let got = analysis.recv().unwrap();
+    assert_eq!(got, buf);
+    assert_eq!(&unsafe { analysis.bytes_mut(got) }[..4], &[0x45, 0, 0x05, 0xdc]);
+    assert!(analysis.free(got));
+    assert!(!analysis.free(got));
+    assert!(analysis.ptr(crate::shm::ShmBuf::from_offset(got.offset() + 8)).is_null());
+
+    // freed blocks come back lifo, from either side
+    let small = analysis.alloc(64).unwrap();
+    assert_eq!(analysis.alloc(1500).unwrap(), buf);
+    let stats = capture.stats();
+    assert_eq!((stats.pages_used, stats.page_count, stats.owned_blocks), (2, 3, 2));
+
+    // a process that dies holding buffers
+    let child = unsafe { fork() };
+    if child == 0 {
+        let held = (0..3).all(|_| capture.alloc(200).is_some());
+        unsafe { _exit(if held { 0 } else { 1 }) };
+    }
+    let mut status = 0;
+    assert_eq!(unsafe { waitpid(child, &mut status, 0) }, child);
+    assert_eq!(status, 0);
+    assert_eq!(capture.stats().owned_blocks, 5);
+    assert_eq!(capture.reclaim_dead(), 3);
+    assert_eq!(capture.reclaim_dead(), 0);
+    assert_eq!(capture.stats().owned_blocks, 2);
+    assert_eq!(capture.owner(small), Some(std::process::id()));
+
+    // a sender gone while its buffer sits in the mailbox, rebuild frees it
+    let queued = capture.alloc(64).unwrap();
+    assert!(capture.send(queued));
+    assert_eq!(capture.stats().queued_blocks, 1);
+    unsafe { capture.rebuild() };
+    let stats = capture.stats();
+    assert_eq!((stats.queued_blocks, stats.owned_blocks), (0, 2));
+    assert!(analysis.recv().is_none());
+
+    // several senders and receivers on one mailbox, every buffer arrives exactly once
+    let mut sent: Vec<_> = (0..64).map(|_| capture.alloc(64).unwrap()).collect();
+    let received = std::sync::Mutex::new(Vec::new());
+    std::thread::scope(|s| {
+        for part in sent.chunks(16) {
+            s.spawn(|| part.iter().for_each(|&buf| assert!(capture.send(buf))));
+        }
+        for _ in 0..4 {
+            s.spawn(|| {
+                let mut got = Vec::new();
+                while got.len() < 16 {
+                    got.extend(analysis.recv());
+                }
+                received.lock().unwrap().extend(got);
+            });
+        }
+    });
+    let mut received = received.into_inner().unwrap();
+    assert!(analysis.recv().is_none());
+    received.sort_by_key(|buf| buf.offset());
+    sent.sort_by_key(|buf| buf.offset());
+    assert_eq!(received, sent);
+    assert!(received.iter().all(|&buf| analysis.free(buf)));
+
+    // the third page, then nothing
+    let mut n = 0;
+    while capture.alloc(2048).is_some() {
+        n += 1;
+    }
+    assert_eq!(n, SHM_PAGE / (2048 + 16) - 1 + SHM_PAGE / (2048 + 16));
+    assert!(capture.alloc(256).is_some());
+}
@@ -1,122 +1,457 @@
 use core::cell::UnsafeCell;
-use core::sync::atomic::{AtomicUsize, Ordering,AtomicPtr};
+use crate::sync::{AtomicBool, Ordering};
 use core::ptr::NonNull;
-use std::io::Cursor;
-use std::vec;
 
 use crate::bump::align_up;
+use crate::epoch::{DEFER_NODE_SIZE, Guard};
 
 // sinlgly-linked free struct node
 #[repr(C)]
-struct FreeNode { 
+struct FreeNode {
     next : usize // the raw pointer to the next node
 }
 
+pub const SLAB_PAGE_SIZE: usize = 4096;
+// keeps the per page free bitmap of for_each_live() on the stack
+pub const MAX_PAGE_SIZE: usize = 64 * 1024;
+const MAX_PAGE_BLOCKS: usize = MAX_PAGE_SIZE / core::mem::size_of::<usize>();
+const PAGE_MAGIC: usize = 0x51AB_9A6E;
+
+const LIST_FULL: usize = 0;
+const LIST_PARTIAL: usize = 1;
+const LIST_EMPTY: usize = 2;
+
+// header at the start of every slab page, the blocks follow right after it.
+// pages are aligned to page_size so a block finds its page by masking the address.
+#[repr(C)]
+struct PageHeader {
+    magic: usize,
+    prev: usize,
+    next: usize,
+    free: usize,
+    in_use: usize,
+    capacity: usize,
+    list: usize,
+    // blocks below this address were never handed out, 0 for a page that wasn't zeroed when
+    // it was added. lifo pops go down the page, so it only ever moves down
+    fresh_below: usize
+}
+
+// heads of the full, partial and empty page lists, doubly linked through the page headers
+struct PageLists {
+    heads: [usize; 3],
+    counts: [usize; 3]
+}
+
 
 pub type StrippedLayout = (usize, usize);
 
-pub struct Slab { 
+pub struct Slab {
     pub block_size: usize,
-    pub head: AtomicUsize,
-    pub region_start : usize,
-    pub region_end : usize,
-    pub blocks : UnsafeCell<Vec<StrippedLayout>>
+    pub block_align: usize,
+    pub page_size: usize,
+    pub reserve_pages: usize,
+    lock: AtomicBool,
+    lists: UnsafeCell<PageLists>
 }
 
-// fixed size lock free slab
-// caller has to provide and gurantee the memory region
-// free blocks are linked in atomic lifo stack of head
-// alloc pops from the stack and dealloc pushed to the stack again
-// exactly call once the init_region() before using this slab
+// fixed size slab made of per-page slabs
+// caller has to provide and gurantee the memory, either once through init_region() or page by
+// page through grow() while the slab is live
+// every page keeps its own lifo free list and in-use count, pages move between the full,
+// partial and empty lists as blocks come and go. alloc prefers partial pages so empty ones
+// stay empty and can be handed back with release_page() once there are more than reserve_pages.
+// the page lists sit behind a small spin lock, block and page transitions are a handful of
+// pointer writes so the critical section stays short.
 // the block is size is minimum core::mem::<usize>() or multiple of this
 // new_rounded() ensures this
+// power of two block sizes are naturally aligned, every block sits on a multiple of its own
+// size, so a 64 byte class also serves #[repr(align(64))] types. other sizes are word aligned.
+
+unsafe impl Sync for Slab {}
 
-impl Slab { 
+impl Slab {
 
-    pub const fn new_rounded(block_size : usize)-> Self  { 
+    loom_const_fn! {
+    pub const fn new_rounded(block_size : usize)-> Self  {
         let min_size = core::mem::size_of::<usize>();
         let size = if block_size > min_size {block_size} else {min_size};
         let align = core::mem::size_of::<usize>();
         let block_size = align_slab_up(size, align);
+        let block_align = if block_size.is_power_of_two() { block_size } else { align };
         Self {
             block_size,
-            head: AtomicUsize::new(0),
-            region_start: 0,
-            region_end:0,
-            blocks: UnsafeCell::new(Vec::new())
+            block_align,
+            page_size: SLAB_PAGE_SIZE,
+            // never give pages back unless the owner asks for it
+            reserve_pages: usize::MAX,
+            lock: AtomicBool::new(false),
+            lists: UnsafeCell::new(PageLists { heads: [0; 3], counts: [0; 3] })
+        }
+    }
+    }
+
+    // blocks aligned to at least align, the block size grows to a multiple of it
+    pub const fn with_align(mut self, align: usize) -> Self {
+        assert!(align.is_power_of_two(), "slab block alignment has to be a power of two");
+        assert!(align <= MAX_PAGE_SIZE / 2, "slab block alignment is limited to half a page");
+        self.block_size = align_slab_up(self.block_size, align);
+        if self.block_align < align {
+            self.block_align = align;
         }
+        self
+    }
+
+    pub const fn with_page_size(mut self, page_size: usize) -> Self {
+        assert!(page_size.is_power_of_two(), "slab page size has to be a power of two");
+        assert!(page_size <= MAX_PAGE_SIZE, "slab page size is limited to MAX_PAGE_SIZE");
+        self.page_size = page_size;
+        self
     }
 
-    // caller has to gurantee that region is valid and exclusively owned by the slab
-    // for its entire lifetime.
+    // how many completely empty pages stay in the slab before release_page() hands them out
+    pub const fn with_reserve(mut self, reserve_pages: usize) -> Self {
+        self.reserve_pages = reserve_pages;
+        self
+    }
+
+    /// # Safety
+    /// caller has to gurantee that region is valid and exclusively owned by the slab
+    /// for its entire lifetime.
     pub unsafe fn init_region(&mut self, start: usize, size: usize)  {
-        let end  = start.saturating_add(size);
-        let region_start  = align_up(start, core::mem::size_of::<usize>());
-        let region_end = end;
-        self.region_start = region_start;
-        self.region_end = region_end;
-
-        // building a lifo free list by walking the region of block_size steps
-        let mut cursor = region_start;
-        let mut head = 0 as usize;
-        while cursor.saturating_add(self.block_size) <= region_end {
-            let node = cursor as *mut FreeNode;
-             (*node).next = head; 
-            head = cursor;
+        unsafe { self.grow(start, size) };
+    }
+
+    /// carves every whole page of the region into blocks and adds them to the empty list.
+    /// safe to race with alloc/dealloc and other grow calls, returns false if no page fit.
+    ///
+    /// # Safety
+    /// same as init_region, the region has to be valid and exclusively owned by the slab for its lifetime.
+    pub unsafe fn grow(&self, start: usize, size: usize) -> bool {
+        let end = start.saturating_add(size);
+        let mut page = align_up(start, self.page_size);
+        let mut added = false;
+        while page.saturating_add(self.page_size) <= end {
+            added |= unsafe { self.add_page(page) };
+            page += self.page_size;
+        }
+        added
+    }
+
+    /// # Safety
+    /// page has to be page_size aligned, page_size bytes long and owned by the slab until released.
+    pub unsafe fn add_page(&self, page: usize) -> bool {
+        unsafe { self.add_page_inner(page, false) }
+    }
+
+    /// add_page for a page known to be all zero, alloc_zeroed then skips the memset for the
+    /// blocks of it that were never handed out.
+    ///
+    /// # Safety
+    /// same as add_page, and the page has to be zeroed.
+    pub unsafe fn add_fresh_page(&self, page: usize) -> bool {
+        unsafe { self.add_page_inner(page, true) }
+    }
+
+    unsafe fn add_page_inner(&self, page: usize, zeroed: bool) -> bool {
+        debug_assert!(page & (self.page_size - 1) == 0);
+        let page_end = page + self.page_size;
+
+        // building a lifo free list by walking the page of block_size steps
+        let mut cursor = self.first_block(page);
+        let mut free = 0usize;
+        let mut capacity = 0;
+        while cursor.saturating_add(self.block_size) <= page_end {
+            unsafe { (*(cursor as *mut FreeNode)).next = free };
+            free = cursor;
             cursor = cursor.saturating_add(self.block_size);
+            capacity += 1;
+        }
+        if capacity == 0 {
+            return false;
+        }
+        unsafe {
+            (page as *mut PageHeader).write(PageHeader {
+                magic: self.magic(),
+                prev: 0,
+                next: 0,
+                free,
+                in_use: 0,
+                capacity,
+                list: LIST_EMPTY,
+                fresh_below: if zeroed { page_end } else { 0 }
+            });
         }
-        self.head.store(head, Ordering::SeqCst);
+        self.with_lists(|lists| unsafe { lists.push(page, LIST_EMPTY) });
+        true
     }
 
+    /// a block of block_size bytes, None once every page is full.
+    ///
+    /// # Safety
+    /// the pages the slab was given have to still be valid and owned by it.
     pub unsafe fn alloc(&self) -> Option<NonNull<u8>> {
-        loop {
-            let head = self.head.load(Ordering::Acquire);
-            if head == 0 {
+        unsafe { self.pop_block() }.map(|(block, _)| block)
+    }
+
+    /// a zeroed block. blocks of a fresh page that were never handed out only get their free
+    /// list link cleared, the rest is memset outside the lock.
+    ///
+    /// # Safety
+    /// same as alloc.
+    pub unsafe fn alloc_zeroed(&self) -> Option<NonNull<u8>> {
+        let (block, fresh) = unsafe { self.pop_block() }?;
+        unsafe {
+            if fresh {
+                (block.as_ptr() as *mut FreeNode).write(FreeNode { next: 0 });
+            } else {
+                core::ptr::write_bytes(block.as_ptr(), 0, self.block_size);
+            }
+        }
+        Some(block)
+    }
+
+    // the block and whether it is still as zeroed as when its page was added
+    unsafe fn pop_block(&self) -> Option<(NonNull<u8>, bool)> {
+        self.with_lists(|lists| unsafe {
+            let page = if lists.heads[LIST_PARTIAL] != 0 {
+                lists.heads[LIST_PARTIAL]
+            } else if lists.heads[LIST_EMPTY] != 0 {
+                let page = lists.heads[LIST_EMPTY];
+                lists.move_to(page, LIST_PARTIAL);
+                page
+            } else {
                 return None;
+            };
+            let header = &mut *(page as *mut PageHeader);
+            let block = header.free;
+            header.free = (block as *const FreeNode).read().next;
+            header.in_use += 1;
+            if header.free == 0 {
+                lists.move_to(page, LIST_FULL);
             }
-            let next = unsafe { (head as *const FreeNode).read().next};
-            match self.head.compare_exchange(head, next, Ordering::AcqRel, Ordering::Acquire) { 
-                Ok(_) => return Some(unsafe { NonNull::new_unchecked(head as *mut u8) } ),
-                Err(_) => {
-                    core::hint::spin_loop();
-                }
+            let fresh = block < header.fresh_below;
+            if fresh {
+                header.fresh_below = block;
             }
-        }
+            Some((NonNull::new_unchecked(block as *mut u8), fresh))
+        })
     }
 
+    /// gives a block back to its page.
+    ///
+    /// # Safety
+    /// ptr has to be a block handed out by this slab's alloc or alloc_zeroed and not freed since,
+    /// nothing may use it afterwards.
     pub unsafe fn dealloc(&self, ptr: NonNull<u8>) {
         let p = ptr.as_ptr() as usize;
         debug_assert!(self.owns(p));
-        loop {
-            let head = self.head.load(Ordering::Acquire);
-            let node = unsafe { p as *mut FreeNode };
-            (*node).next = head;
-            match self.head.compare_exchange(head, p, Ordering::AcqRel, Ordering::Acquire) { 
-                Ok(_) => return,
-                Err(_) => {
-                    core::hint::spin_loop();
-                }
+        let page = self.page_of(p);
+        self.with_lists(|lists| unsafe {
+            let header = &mut *(page as *mut PageHeader);
+            (*(p as *mut FreeNode)).next = header.free;
+            header.free = p;
+            header.in_use -= 1;
+            if header.in_use == 0 {
+                lists.move_to(page, LIST_EMPTY);
+            } else if header.list == LIST_FULL {
+                lists.move_to(page, LIST_PARTIAL);
             }
+        })
+    }
+
+    /// frees the block once no epoch guard can still be reading it, instead of tagging the
+    /// pointers of lock-free structures whose nodes live in this slab.
+    ///
+    /// # Safety
+    /// same as dealloc, and ptr has to be unlinked from the structure already. the slab has to
+    /// outlive the collector's next collect() that frees it. blocks have to be at least
+    /// DEFER_NODE_SIZE bytes.
+    pub unsafe fn dealloc_deferred(&self, guard: &Guard<'_, '_>, ptr: NonNull<u8>) {
+        assert!(self.block_size >= DEFER_NODE_SIZE, "block too small to carry a deferred free");
+        unsafe fn free_to_slab(ptr: NonNull<u8>, slab: usize) {
+            unsafe { (*(slab as *const Slab)).dealloc(ptr) };
         }
+        unsafe { guard.defer_free(ptr, free_to_slab, self as *const Slab as usize) };
     }
 
+    // hands out one completely empty page once more than reserve_pages are sitting idle.
+    // the page is no longer owned by the slab afterwards and can go back to whoever backs it.
+    pub fn release_page(&self) -> Option<usize> {
+        self.with_lists(|lists| unsafe {
+            if lists.counts[LIST_EMPTY] <= self.reserve_pages {
+                return None;
+            }
+            let page = lists.heads[LIST_EMPTY];
+            lists.unlink(page);
+            (*(page as *mut PageHeader)).magic = 0;
+            Some(page)
+        })
+    }
+
+    /// forgets every page at once, live blocks included. the pages go back to whoever backs them.
+    ///
+    /// # Safety
+    /// no block handed out by the slab may be used or freed afterwards.
+    pub unsafe fn clear(&self) {
+        self.with_lists(|lists| {
+            for head in lists.heads.iter_mut() {
+                while *head != 0 {
+                    let header = unsafe { &mut *(*head as *mut PageHeader) };
+                    header.magic = 0;
+                    *head = header.next;
+                }
+            }
+            lists.counts = [0; 3];
+        })
+    }
+
+    // goes by the magic word at the start of p's page, so p has to point into memory that is
+    // safe to read and whose page start can't hold caller data that looks like a header.
+    // CompositeAllocator routes frees by its page owner table instead
     #[inline]
     pub fn owns(&self, p: usize) -> bool {
-        return p >= self.region_start && p <= self.region_end;
+        let page = self.page_of(p);
+        if page == 0 {
+            return false;
+        }
+        // the header word may belong to somebody else's memory, read it as a plain atomic word.
+        // always core's atomic, loom's type doesn't share the layout of a raw usize
+        let magic = unsafe { (*(page as *const core::sync::atomic::AtomicUsize)).load(core::sync::atomic::Ordering::Acquire) };
+        magic == self.magic() && p >= self.first_block(page)
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    pub fn debug_count_free(&self) -> usize {
+        self.with_lists(|lists| {
+            let mut count = 0;
+            for list in [LIST_PARTIAL, LIST_EMPTY] {
+                let mut page = lists.heads[list];
+                while page != 0 {
+                    let header = unsafe { &*(page as *const PageHeader) };
+                    count += header.capacity - header.in_use;
+                    page = header.next;
+                }
+            }
+            count
+        })
+    }
+
+    // blocks currently handed out
+    pub fn live_count(&self) -> usize {
+        self.with_lists(|lists| {
+            let mut count = 0;
+            for list in [LIST_FULL, LIST_PARTIAL] {
+                let mut page = lists.heads[list];
+                while page != 0 {
+                    let header = unsafe { &*(page as *const PageHeader) };
+                    count += header.in_use;
+                    page = header.next;
+                }
+            }
+            count
+        })
     }
 
-    pub fn block_size(&self) -> usize { 
-        return self.block_size;
+    // calls f with the address of every block currently handed out, page by page.
+    // f runs under the slab lock, it must not allocate from or free to this slab.
+    pub fn for_each_live(&self, mut f: impl FnMut(usize)) {
+        self.with_lists(|lists| {
+            for list in [LIST_FULL, LIST_PARTIAL] {
+                let mut page = lists.heads[list];
+                while page != 0 {
+                    let header = unsafe { &*(page as *const PageHeader) };
+                    let first = self.first_block(page);
+                    let mut free = [0u64; MAX_PAGE_BLOCKS / 64];
+                    let mut block = header.free;
+                    while block != 0 {
+                        let idx = (block - first) / self.block_size;
+                        free[idx / 64] |= 1 << (idx % 64);
+                        block = unsafe { (block as *const FreeNode).read().next };
+                    }
+                    for idx in 0..header.capacity {
+                        if free[idx / 64] & (1 << (idx % 64)) == 0 {
+                            f(first + idx * self.block_size);
+                        }
+                    }
+                    page = header.next;
+                }
+            }
+        })
     }
 
-    pub fn debug_count_free(&self) -> usize { 
-        let mut count = 0;
-        let mut head = self.head.load(Ordering::Acquire);
-        while head != 0 {
-            head = unsafe { (head as *const FreeNode).read().next};
-            count += 1;
+    // number of pages on the (full, partial, empty) lists
+    pub fn page_counts(&self) -> (usize, usize, usize) {
+        self.with_lists(|lists| (lists.counts[LIST_FULL], lists.counts[LIST_PARTIAL], lists.counts[LIST_EMPTY]))
+    }
+
+    #[inline]
+    fn page_of(&self, p: usize) -> usize {
+        p & !(self.page_size - 1)
+    }
+
+    #[inline]
+    fn first_block(&self, page: usize) -> usize {
+        align_up(page + core::mem::size_of::<PageHeader>(), self.block_align)
+    }
+
+    #[inline]
+    fn magic(&self) -> usize {
+        PAGE_MAGIC ^ self.block_size
+    }
+
+    fn with_lists<R>(&self, f: impl FnOnce(&mut PageLists) -> R) -> R {
+        while self.lock.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            crate::sync::spin_loop();
+        }
+        // f may panic (for_each_live runs caller code), the lock goes either way
+        let _unlock = ListsLock(&self.lock);
+        f(unsafe { &mut *self.lists.get() })
+    }
+}
+
+struct ListsLock<'s>(&'s AtomicBool);
+
+impl Drop for ListsLock<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+impl PageLists {
+    unsafe fn push(&mut self, page: usize, list: usize) {
+        let header = unsafe { &mut *(page as *mut PageHeader) };
+        header.list = list;
+        header.prev = 0;
+        header.next = self.heads[list];
+        if header.next != 0 {
+            unsafe { (*(header.next as *mut PageHeader)).prev = page };
+        }
+        self.heads[list] = page;
+        self.counts[list] += 1;
+    }
+
+    unsafe fn unlink(&mut self, page: usize) {
+        let header = unsafe { &mut *(page as *mut PageHeader) };
+        if header.prev != 0 {
+            unsafe { (*(header.prev as *mut PageHeader)).next = header.next };
+        } else {
+            self.heads[header.list] = header.next;
+        }
+        if header.next != 0 {
+            unsafe { (*(header.next as *mut PageHeader)).prev = header.prev };
+        }
+        self.counts[header.list] -= 1;
+    }
+
+    unsafe fn move_to(&mut self, page: usize, list: usize) {
+        unsafe {
+            self.unlink(page);
+            self.push(page, list);
         }
-        return count;
     }
 }
 
@@ -127,4 +462,4 @@ pub const fn align_slab_up(addr: usize, align: usize) ->  usize {
     } else {
         (addr + align - 1) & !(align - 1)
     }
-}
\ No newline at end of file
+}
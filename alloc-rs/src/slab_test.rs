@@ -22,4 +22,118 @@ pub fn simple_slab_test(){
     unsafe { slab_allocator.dealloc(b);}
     let d = unsafe { slab_allocator.alloc().expect("should alloc d")};
     assert!(d.as_ptr() as usize == ub , "address should be same");
-}
\ No newline at end of file
+}
+#[cfg(test)]
+#[repr(C, align(4096))]
+struct Pages<const N: usize>([u8; N]);
+
+#[test]
+pub fn slab_grow_test() {
+    static mut FIRST: Pages<4096> = Pages([0u8; 4096]);
+    static mut EXTRA: Pages<4096> = Pages([0u8; 4096]);
+    let first_addr = unsafe { &raw mut FIRST as *mut u8 as usize};
+    let extra_addr = unsafe { &raw mut EXTRA as *mut u8 as usize};
+
+    let mut slab = Slab::new_rounded(64);
+    unsafe { slab.init_region(first_addr, 4096) };
+    let initial = slab.debug_count_free();
+    for _ in 0..initial {
+        unsafe { slab.alloc().expect("initial region should have room") };
+    }
+    assert!(unsafe { slab.alloc() }.is_none(), "initial region should be exhausted");
+    assert!(!slab.owns(extra_addr + 512));
+
+    assert!(unsafe { slab.grow(extra_addr, 4096) });
+    let grown = slab.debug_count_free();
+    assert_eq!(grown, initial);
+    let p = unsafe { slab.alloc().expect("grown region should alloc") };
+    let up = p.as_ptr() as usize;
+    assert!(up >= extra_addr && up + 64 <= extra_addr + 4096);
+    assert!(slab.owns(up), "blocks of grown regions are owned by the slab");
+    unsafe { slab.dealloc(p) };
+    assert_eq!(slab.debug_count_free(), grown);
+
+    assert!(!unsafe { slab.grow(extra_addr + 1, 4096) }, "region without a whole page is rejected");
+}
+
+#[test]
+pub fn slab_page_lists_test() {
+    static mut REGION: Pages<{ 4 * 4096 }> = Pages([0u8; 4 * 4096]);
+    let region_addr = unsafe { &raw mut REGION as *mut u8 as usize};
+
+    let mut slab = Slab::new_rounded(512).with_reserve(1);
+    unsafe { slab.init_region(region_addr, 4 * 4096) };
+    assert_eq!(slab.page_counts(), (0, 0, 4));
+    let per_page = slab.debug_count_free() / 4;
+
+    // filling one page exactly moves it to the full list, one more block opens a partial page
+    let mut blocks = [None; 64];
+    for b in blocks.iter_mut().take(per_page + 1) {
+        *b = Some(unsafe { slab.alloc().expect("should alloc") });
+    }
+    assert_eq!(slab.page_counts(), (1, 1, 2));
+
+    unsafe { slab.dealloc(blocks[0].take().unwrap()) };
+    assert_eq!(slab.page_counts(), (0, 2, 2), "a full page with a free block is partial again");
+
+    for b in blocks.iter_mut() {
+        if let Some(p) = b.take() {
+            unsafe { slab.dealloc(p) };
+        }
+    }
+    assert_eq!(slab.page_counts(), (0, 0, 4));
+
+    // everything above the reserve goes back to the owner, and the page is not ours anymore
+    let mut released = 0;
+    while let Some(page) = slab.release_page() {
+        assert_eq!(page % 4096, 0);
+        assert!(!slab.owns(page + 1024));
+        released += 1;
+    }
+    assert_eq!(released, 3);
+    assert_eq!(slab.page_counts(), (0, 0, 1));
+    assert!(unsafe { slab.alloc() }.is_some(), "the reserve page still serves allocations");
+
+    // a callback that unwinds under the lock doesn't leave it taken. resume_unwind skips the
+    // panic hook, whose backtrace would not fit the test heap
+    let walk = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        slab.for_each_live(|_| std::panic::resume_unwind(Box::new(())))
+    }));
+    assert!(walk.is_err());
+    assert!(unsafe { slab.alloc() }.is_some());
+}
+
+#[test]
+pub fn slab_alloc_zeroed_test() {
+    static mut PAGE: Pages<4096> = Pages([0u8; 4096]);
+    let page_addr = &raw mut PAGE as *mut u8 as usize;
+
+    let slab = Slab::new_rounded(64);
+    unsafe { slab.add_fresh_page(page_addr) };
+    unsafe {
+        let first = slab.alloc_zeroed().unwrap().as_ptr();
+        // the next block down was never handed out, a marker past its link word shows whether
+        // alloc_zeroed memsets it
+        let second = first.sub(64);
+        second.add(8).write(0xee);
+        assert_eq!(slab.alloc_zeroed().unwrap().as_ptr(), second);
+        assert_eq!((second as *const usize).read(), 0, "the free list link is cleared");
+        assert_eq!(second.add(8).read(), 0xee, "fresh blocks skip the memset");
+
+        // a recycled block is dirty and gets cleared in full
+        first.write_bytes(0xff, 64);
+        slab.dealloc(core::ptr::NonNull::new_unchecked(first));
+        let again = slab.alloc_zeroed().unwrap().as_ptr();
+        assert_eq!(again, first);
+        assert!((0..64).all(|i| again.add(i).read() == 0));
+    }
+
+    // pages of unknown content are never trusted
+    static mut DIRTY: Pages<4096> = Pages([0xffu8; 4096]);
+    let dirty = Slab::new_rounded(64);
+    unsafe {
+        dirty.add_page(&raw mut DIRTY as *mut u8 as usize);
+        let p = dirty.alloc_zeroed().unwrap().as_ptr();
+        assert!((0..64).all(|i| p.add(i).read() == 0));
+    }
+}
@@ -0,0 +1,184 @@
+use core::fmt;
+
+use crate::composite::{CompositeAllocator, SLAB_CLASSES};
+use crate::sync::Ordering;
+
+// heap snapshots for leak checks, e.g. assert that processing N packets leaves the heap
+// exactly as it found it.
+// a snapshot lists every live slab block per size class, the live bytes of the bump and mmap
+// tiers and the state of the page pool. the bump tier is counted by what is live in it rather
+// than by how far it got, thread chunks are carved whole and freed bump memory isn't reused,
+// so neither would say whether a block was let go. the lists go into a buffer the caller hands in, so taking a snapshot allocates
+// nothing: it never shows up in the next snapshot and never eats into the heap, which matters
+// for the bump tier since that one doesn't take memory back.
+
+#[derive(Debug, Clone, Default)]
+pub struct ClassSnapshot<'b> {
+    pub block_size: usize,
+    // sorted block addresses
+    pub live: &'b [usize]
+}
+
+#[derive(Debug, Clone)]
+pub struct HeapSnapshot<'b> {
+    pub classes: [ClassSnapshot<'b>; SLAB_CLASSES],
+    pub class_count: usize,
+    // bytes of bump tier blocks not freed yet
+    pub bump_live: usize,
+    // how far the bump got and what is left behind it
+    pub bump_used: usize,
+    pub bump_free: usize,
+    pub mmap_bytes: usize,
+    pub mmap_mappings: usize,
+    pub free_pages: usize,
+    // the buffer ran out before every live block was listed
+    pub truncated: bool
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ClassDiff {
+    pub block_size: usize,
+    // live in the later snapshot but not in the earlier one
+    pub allocated: Vec<usize>,
+    // live in the earlier snapshot but gone in the later one
+    pub freed: Vec<usize>
+}
+
+#[derive(Debug, Clone)]
+pub struct HeapDiff {
+    pub classes: [ClassDiff; SLAB_CLASSES],
+    pub class_count: usize,
+    // live bump bytes gained in between
+    pub bump_growth: usize,
+    // mapped bytes and mappings gained in between
+    pub mmap_growth: usize,
+    pub mmap_new_mappings: usize
+}
+
+impl CompositeAllocator {
+    // buf takes one word per live slab block, the classes' lists are slices of it. blocks past
+    // its end are left out and the snapshot says it was truncated
+    pub fn snapshot<'b>(&self, buf: &'b mut [usize]) -> HeapSnapshot<'b> {
+        self.ensure_init();
+        let mut snapshot = HeapSnapshot {
+            classes: Default::default(),
+            class_count: self.config.class_count,
+            bump_live: self.bump_live.load(Ordering::Relaxed),
+            bump_used: self.bump_allocator.used_bytes(),
+            bump_free: self.bump_allocator.free_bytes(),
+            mmap_bytes: 0,
+            mmap_mappings: 0,
+            free_pages: self.free_pages(),
+            truncated: false
+        };
+        #[cfg(all(feature = "std", target_os = "linux", not(loom)))]
+        {
+            snapshot.mmap_bytes = self.mmap.mapped_bytes();
+            snapshot.mmap_mappings = self.mmap.mappings();
+        }
+        let mut rest = buf;
+        for (class, slab) in snapshot.classes.iter_mut().zip(&self.slabs[..self.config.class_count]) {
+            class.block_size = slab.block_size;
+            let mut len = 0;
+            slab.for_each_live(|block| {
+                if len < rest.len() {
+                    rest[len] = block;
+                    len += 1;
+                } else {
+                    snapshot.truncated = true;
+                }
+            });
+            let (live, tail) = core::mem::take(&mut rest).split_at_mut(len);
+            live.sort_unstable();
+            class.live = live;
+            rest = tail;
+        }
+        snapshot
+    }
+}
+
+impl HeapSnapshot<'_> {
+    pub fn live_blocks(&self) -> usize {
+        self.classes[..self.class_count].iter().map(|class| class.live.len()).sum()
+    }
+
+    pub fn live_bytes(&self) -> usize {
+        self.classes[..self.class_count].iter().map(|class| class.live.len() * class.block_size).sum()
+    }
+
+    // what happened between self and a later snapshot of the same allocator
+    pub fn diff(&self, later: &HeapSnapshot<'_>) -> HeapDiff {
+        let mut diff = HeapDiff {
+            classes: Default::default(),
+            class_count: self.class_count.min(later.class_count),
+            bump_growth: later.bump_live.saturating_sub(self.bump_live),
+            mmap_growth: later.mmap_bytes.saturating_sub(self.mmap_bytes),
+            mmap_new_mappings: later.mmap_mappings.saturating_sub(self.mmap_mappings)
+        };
+        for (i, class) in diff.classes[..diff.class_count].iter_mut().enumerate() {
+            let (before, after) = (self.classes[i].live, later.classes[i].live);
+            class.block_size = later.classes[i].block_size;
+            // both sides are sorted, one merge pass splits them
+            let (mut b, mut a) = (0, 0);
+            while b < before.len() || a < after.len() {
+                if a == after.len() || (b < before.len() && before[b] < after[a]) {
+                    class.freed.push(before[b]);
+                    b += 1;
+                } else if b == before.len() || after[a] < before[b] {
+                    class.allocated.push(after[a]);
+                    a += 1;
+                } else {
+                    a += 1;
+                    b += 1;
+                }
+            }
+        }
+        diff
+    }
+}
+
+impl HeapDiff {
+    // nothing allocated and not freed in between
+    pub fn is_clean(&self) -> bool {
+        self.bump_growth == 0 && self.mmap_growth == 0 && self.mmap_new_mappings == 0
+            && self.classes[..self.class_count].iter().all(|class| class.allocated.is_empty())
+    }
+
+    pub fn leaked_blocks(&self) -> usize {
+        self.classes[..self.class_count].iter().map(|class| class.allocated.len()).sum()
+    }
+
+    pub fn leaked_bytes(&self) -> usize {
+        self.bump_growth + self.mmap_growth + self.classes[..self.class_count].iter().map(|class| class.allocated.len() * class.block_size).sum::<usize>()
+    }
+}
+
+impl fmt::Display for HeapSnapshot<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "slab tier: {} live blocks, {} bytes{}", self.live_blocks(), self.live_bytes(),
+            if self.truncated { " (truncated)" } else { "" })?;
+        for class in &self.classes[..self.class_count] {
+            writeln!(f, "  class {:>5}: {:>6} live", class.block_size, class.live.len())?;
+        }
+        writeln!(f, "bump tier: {} live, {} used, {} free", self.bump_live, self.bump_used, self.bump_free)?;
+        writeln!(f, "mmap tier: {} mappings, {} bytes", self.mmap_mappings, self.mmap_bytes)?;
+        write!(f, "page pool: {} free pages", self.free_pages)
+    }
+}
+
+impl fmt::Display for HeapDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} blocks / {} bytes allocated and not freed", self.leaked_blocks(), self.leaked_bytes())?;
+        for class in &self.classes[..self.class_count] {
+            if class.allocated.is_empty() && class.freed.is_empty() {
+                continue;
+            }
+            writeln!(f, "  class {:>5}: +{} -{}", class.block_size, class.allocated.len(), class.freed.len())?;
+            for block in &class.allocated {
+                writeln!(f, "    leaked {block:#x}")?;
+            }
+        }
+        writeln!(f, "bump tier: +{} bytes", self.bump_growth)?;
+        write!(f, "mmap tier: +{} mappings, +{} bytes", self.mmap_new_mappings, self.mmap_growth)
+    }
+}
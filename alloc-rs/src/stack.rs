@@ -0,0 +1,214 @@
+use core::alloc::Layout;
+use core::cell::Cell;
+use core::ptr::NonNull;
+
+use crate::bump::align_up;
+
+// double ended stack allocator, the two direction sibling of BumpAllocator.
+// long lived data (per connection state) stacks up from the bottom of the region, scratch
+// data (per packet decode buffers) stacks down from the top, the region is full when the two
+// meet in the middle. each end is a stack of its own: free() pops the newest allocation of an
+// end, a Marker taken earlier rewinds an end past everything allocated since in one step.
+// every allocation carries a two word header right in front of it with the end position and
+// the newest allocation from before it, so free() restores the end exactly, padding included.
+// a marker also carries its end's generation. the generation moves whenever free, rewind or
+// reset takes the end back past the furthest marker taken in it, so a marker whose allocations
+// were given back (and maybe handed out again) can't rewind onto them.
+// single threaded, the ends are plain Cells.
+
+const WORD: usize = core::mem::size_of::<usize>();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum End {
+    Bottom,
+    Top
+}
+
+#[repr(C)]
+struct AllocHeader {
+    // end position before this allocation
+    prev_pos: usize,
+    // newest allocation on the same end before this one, 0 if none
+    prev_last: usize
+}
+
+const HEADER: usize = core::mem::size_of::<AllocHeader>();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Marker {
+    end: End,
+    pos: usize,
+    last: usize,
+    generation: usize
+}
+
+pub struct StackAllocator {
+    heap_start: usize,
+    heap_end: usize,
+    bottom: Cell<usize>,
+    top: Cell<usize>,
+    last_bottom: Cell<usize>,
+    last_top: Cell<usize>,
+    generation_bottom: Cell<usize>,
+    generation_top: Cell<usize>,
+    // furthest position a marker of the current generation was taken at
+    marked_bottom: Cell<usize>,
+    marked_top: Cell<usize>
+}
+
+impl StackAllocator {
+    /// both ends grow into heap_size bytes at heap_start.
+    ///
+    /// # Safety
+    /// the region has to be valid for reads and writes, and nothing else may use it while
+    /// allocations from this stack are live.
+    pub unsafe fn from_raw_parts(heap_start: usize, heap_size: usize) -> Self {
+        let heap_end = heap_start + heap_size;
+        Self {
+            heap_start,
+            heap_end,
+            bottom: Cell::new(heap_start),
+            top: Cell::new(heap_end),
+            last_bottom: Cell::new(0),
+            last_top: Cell::new(0),
+            generation_bottom: Cell::new(0),
+            generation_top: Cell::new(0),
+            marked_bottom: Cell::new(heap_start),
+            marked_top: Cell::new(heap_end)
+        }
+    }
+
+    // None once the allocation would cross the other end
+    pub fn alloc(&self, end: End, layout: Layout) -> Option<NonNull<u8>> {
+        let align = layout.align().max(WORD);
+        let size = layout.size();
+        match end {
+            End::Bottom => {
+                let pos = self.bottom.get();
+                let obj = align_up(pos.checked_add(HEADER)?, align);
+                let new_bottom = obj.checked_add(size)?;
+                if new_bottom > self.top.get() {
+                    return None;
+                }
+                unsafe { ((obj - HEADER) as *mut AllocHeader).write(AllocHeader { prev_pos: pos, prev_last: self.last_bottom.get() }) };
+                self.bottom.set(new_bottom);
+                self.last_bottom.set(obj);
+                NonNull::new(obj as *mut u8)
+            },
+            End::Top => {
+                let pos = self.top.get();
+                let obj = pos.checked_sub(size)? & !(align - 1);
+                let new_top = obj.checked_sub(HEADER)?;
+                if new_top < self.bottom.get() {
+                    return None;
+                }
+                unsafe { (new_top as *mut AllocHeader).write(AllocHeader { prev_pos: pos, prev_last: self.last_top.get() }) };
+                self.top.set(new_top);
+                self.last_top.set(obj);
+                NonNull::new(obj as *mut u8)
+            },
+        }
+    }
+
+    pub fn alloc_bottom(&self, layout: Layout) -> Option<NonNull<u8>> {
+        self.alloc(End::Bottom, layout)
+    }
+
+    pub fn alloc_top(&self, layout: Layout) -> Option<NonNull<u8>> {
+        self.alloc(End::Top, layout)
+    }
+
+    /// pops the newest allocation of the end, false (and nothing freed) if ptr isn't it.
+    ///
+    /// # Safety
+    /// the memory behind ptr must not be used afterwards.
+    pub unsafe fn free(&self, end: End, ptr: NonNull<u8>) -> bool {
+        let obj = ptr.as_ptr() as usize;
+        let last = self.end_cells(end).1;
+        if obj == 0 || last.get() != obj {
+            return false;
+        }
+        let header = unsafe { ((obj - HEADER) as *const AllocHeader).read() };
+        self.retreat(end, header.prev_pos);
+        last.set(header.prev_last);
+        true
+    }
+
+    pub fn marker(&self, end: End) -> Marker {
+        let (pos, last) = self.end_cells(end);
+        let (generation, marked) = self.end_marks(end);
+        marked.set(match end {
+            End::Bottom => marked.get().max(pos.get()),
+            End::Top => marked.get().min(pos.get()),
+        });
+        Marker { end, pos: pos.get(), last: last.get(), generation: generation.get() }
+    }
+
+    /// frees everything the marker's end handed out since the marker was taken. false if the
+    /// end was already freed or rewound past the marker, or past one taken after it.
+    ///
+    /// # Safety
+    /// none of the memory allocated after the marker may be used afterwards.
+    pub unsafe fn rewind(&self, marker: Marker) -> bool {
+        let (pos, last) = self.end_cells(marker.end);
+        let valid = marker.generation == self.end_marks(marker.end).0.get() && match marker.end {
+            End::Bottom => marker.pos >= self.heap_start && marker.pos <= pos.get(),
+            End::Top => marker.pos <= self.heap_end && marker.pos >= pos.get(),
+        };
+        if valid {
+            self.retreat(marker.end, marker.pos);
+            last.set(marker.last);
+        }
+        valid
+    }
+
+    /// # Safety
+    /// nothing allocated from either end may be used afterwards.
+    pub unsafe fn reset(&self) {
+        self.retreat(End::Bottom, self.heap_start);
+        self.retreat(End::Top, self.heap_end);
+        self.last_bottom.set(0);
+        self.last_top.set(0);
+    }
+
+    // bytes taken by one end, headers and padding included
+    pub fn used_bytes(&self, end: End) -> usize {
+        match end {
+            End::Bottom => self.bottom.get() - self.heap_start,
+            End::Top => self.heap_end - self.top.get(),
+        }
+    }
+
+    // the gap between the two ends
+    pub fn free_bytes(&self) -> usize {
+        self.top.get() - self.bottom.get()
+    }
+
+    fn end_cells(&self, end: End) -> (&Cell<usize>, &Cell<usize>) {
+        match end {
+            End::Bottom => (&self.bottom, &self.last_bottom),
+            End::Top => (&self.top, &self.last_top),
+        }
+    }
+
+    fn end_marks(&self, end: End) -> (&Cell<usize>, &Cell<usize>) {
+        match end {
+            End::Bottom => (&self.generation_bottom, &self.marked_bottom),
+            End::Top => (&self.generation_top, &self.marked_top),
+        }
+    }
+
+    // moves the end back to to, the markers it passes go stale
+    fn retreat(&self, end: End, to: usize) {
+        let (generation, marked) = self.end_marks(end);
+        let passed = match end {
+            End::Bottom => to < marked.get(),
+            End::Top => to > marked.get(),
+        };
+        if passed {
+            generation.set(generation.get() + 1);
+            marked.set(to);
+        }
+        self.end_cells(end).0.set(to);
+    }
+}
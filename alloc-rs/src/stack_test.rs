@@ -0,0 +1,68 @@
+use core::alloc::Layout;
+
+use crate::stack::{End, StackAllocator};
+
+#[test]
+pub fn double_ended_stack_test() {
+    static mut HEAP: [u64; 64] = [0u64; 64];
+    let heap_addr = &raw mut HEAP as *mut u8 as usize;
+    let stack = unsafe { StackAllocator::from_raw_parts(heap_addr, 512) };
+    let word = Layout::new::<u64>();
+
+    // connection state from the bottom, packet scratch from the top
+    let conn = stack.alloc_bottom(word).unwrap();
+    let conn_used = stack.used_bytes(End::Bottom);
+    let scratch = stack.alloc_top(Layout::from_size_align(64, 64).unwrap()).unwrap();
+    assert_eq!(scratch.as_ptr() as usize % 64, 0);
+    assert!((conn.as_ptr() as usize) < scratch.as_ptr() as usize);
+
+    // lifo: only the newest allocation of an end can be freed
+    let a = stack.alloc_bottom(word).unwrap();
+    let b = stack.alloc_bottom(word).unwrap();
+    unsafe {
+        assert!(!stack.free(End::Bottom, a));
+        assert!(stack.free(End::Bottom, b));
+        assert!(stack.free(End::Bottom, a));
+        assert!(!stack.free(End::Top, a));
+    }
+    assert_eq!(stack.used_bytes(End::Bottom), conn_used);
+
+    // a marker rewinds everything since it was taken, the older scratch buffer stays
+    let top_used = stack.used_bytes(End::Top);
+    let marker = stack.marker(End::Top);
+    for _ in 0..4 {
+        stack.alloc_top(Layout::new::<[u8; 24]>()).unwrap();
+    }
+    unsafe { assert!(stack.rewind(marker)) };
+    assert_eq!(stack.used_bytes(End::Top), top_used);
+
+    // a marker whose allocations were given back is stale, even once the end has grown past
+    // its position again
+    let outer = stack.marker(End::Top);
+    stack.alloc_top(word).unwrap();
+    let inner = stack.marker(End::Top);
+    stack.alloc_top(word).unwrap();
+    unsafe { assert!(stack.rewind(inner)) };
+    unsafe { assert!(stack.rewind(outer)) };
+    let fresh = stack.marker(End::Top);
+    for _ in 0..3 {
+        stack.alloc_top(word).unwrap();
+    }
+    unsafe { assert!(!stack.rewind(inner)) };
+    unsafe { assert!(stack.rewind(fresh)) };
+    assert_eq!(stack.used_bytes(End::Top), top_used);
+    unsafe { assert!(stack.free(End::Top, scratch)) };
+    assert_eq!(stack.used_bytes(End::Top), 0);
+
+    // the ends meet: everything left fits exactly once
+    let free = stack.free_bytes();
+    let rest = stack.alloc_top(Layout::from_size_align(free - 16, 8).unwrap()).unwrap();
+    assert_eq!(stack.free_bytes(), 0);
+    assert!(stack.alloc_bottom(Layout::from_size_align(1, 1).unwrap()).is_none());
+    assert!(stack.alloc_top(Layout::from_size_align(1, 1).unwrap()).is_none());
+    unsafe { assert!(stack.free(End::Top, rest)) };
+    assert!(stack.alloc_bottom(Layout::from_size_align(free - 16, 8).unwrap()).is_some());
+
+    unsafe { stack.reset() };
+    assert_eq!(stack.free_bytes(), 512);
+}
@@ -0,0 +1,30 @@
+// atomics used by the lock-free parts of the slab, bump and composite allocators.
+// a normal build gets core's atomics, building with RUSTFLAGS="--cfg loom" swaps in loom's so
+// the loom tests can model check every interleaving:
+//     RUSTFLAGS="--cfg loom" cargo test --release loom_test
+// loom atomics can't be created in a const context, so the constructors that build them are
+// declared through loom_const_fn! and lose their const under loom. the global allocator statics
+// are left out of loom builds, loom itself allocates through the system allocator.
+
+#[cfg(not(loom))]
+pub(crate) use core::sync::atomic::{fence, AtomicBool, AtomicUsize, Ordering};
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::{fence, AtomicBool, AtomicUsize, Ordering};
+
+// busy wait hint, under loom it yields so the model lets the other thread make progress
+#[inline]
+pub(crate) fn spin_loop() {
+    #[cfg(not(loom))]
+    core::hint::spin_loop();
+    #[cfg(loom)]
+    loom::thread::yield_now();
+}
+
+macro_rules! loom_const_fn {
+    ($(#[$attr:meta])* $vis:vis const fn $($rest:tt)*) => {
+        #[cfg(not(loom))]
+        $(#[$attr])* $vis const fn $($rest)*
+        #[cfg(loom)]
+        $(#[$attr])* $vis fn $($rest)*
+    };
+}
@@ -0,0 +1,259 @@
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+// per subsystem accounting, every allocation is charged to one tag ("arp", "rx-buffers", ..)
+// each tag tracks live bytes and the peak, and can carry a hard budget that makes allocations
+// fail instead of letting one component starve the shared heap.
+// the table is a fixed array so it works without alloc, slot 0 is the untagged bucket.
+// register claims a slot with a cas and publishes the name before the slot turns ready, then
+// looks for another register of the same name that got a different slot meanwhile. the lower
+// slot wins, the other one is given up again. a given up slot can be claimed and renamed while
+// somebody still compares against it, so the name sits in atomics and the state carries a
+// generation that the reader checks again afterwards.
+
+pub const MAX_TAGS: usize = 16;
+const UNLIMITED: usize = usize::MAX;
+
+// the low two bits of a slot state, the rest counts how often the slot was given up
+const SLOT_FREE: usize = 0;
+const SLOT_CLAIMED: usize = 1;
+const SLOT_NAMED: usize = 2;
+const SLOT_READY: usize = 3;
+const SLOT_KIND: usize = 3;
+const SLOT_GENERATION: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Tag(u8);
+
+impl Tag {
+    pub const UNTAGGED: Tag = Tag(0);
+
+    pub const fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    // out of range indices end up in the untagged bucket
+    pub const fn from_index(index: usize) -> Tag {
+        if index < MAX_TAGS { Tag(index as u8) } else { Tag::UNTAGGED }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TagStats {
+    pub tag: Tag,
+    pub name: &'static str,
+    pub live_bytes: usize,
+    pub peak_bytes: usize,
+    pub budget: Option<usize>,
+    pub allocs: usize,
+    pub failures: usize
+}
+
+struct TagSlot {
+    state: AtomicUsize,
+    name_ptr: AtomicPtr<u8>,
+    name_len: AtomicUsize,
+    live: AtomicUsize,
+    peak: AtomicUsize,
+    budget: AtomicUsize,
+    allocs: AtomicUsize,
+    failures: AtomicUsize
+}
+
+impl TagSlot {
+    const fn new() -> Self {
+        Self {
+            state: AtomicUsize::new(SLOT_FREE),
+            name_ptr: AtomicPtr::new(core::ptr::null_mut()),
+            name_len: AtomicUsize::new(0),
+            live: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+            budget: AtomicUsize::new(UNLIMITED),
+            allocs: AtomicUsize::new(0),
+            failures: AtomicUsize::new(0)
+        }
+    }
+
+    // the name of a named or ready slot, None for any other state or if the slot was given up
+    // while reading
+    fn name(&self) -> Option<&'static str> {
+        let state = self.state.load(Ordering::SeqCst);
+        if state & SLOT_KIND < SLOT_NAMED {
+            return None;
+        }
+        let (ptr, len) = (self.name_ptr.load(Ordering::SeqCst), self.name_len.load(Ordering::SeqCst));
+        if self.state.load(Ordering::SeqCst) != state {
+            return None;
+        }
+        // both halves come from the same &'static str, the state didn't move in between
+        Some(unsafe { core::str::from_utf8_unchecked(core::slice::from_raw_parts(ptr, len)) })
+    }
+
+    fn kind(&self) -> usize {
+        self.state.load(Ordering::Acquire) & SLOT_KIND
+    }
+}
+
+pub struct TagTable {
+    slots: [TagSlot; MAX_TAGS]
+}
+
+impl TagTable {
+    pub const fn new() -> Self {
+        let mut slots = [const { TagSlot::new() }; MAX_TAGS];
+        slots[0].state = AtomicUsize::new(SLOT_READY);
+        slots[0].name_ptr = AtomicPtr::new("untagged".as_ptr() as *mut u8);
+        slots[0].name_len = AtomicUsize::new("untagged".len());
+        Self { slots }
+    }
+
+    // registering a name twice hands back the existing tag and leaves its budget alone.
+    // returns None once all MAX_TAGS slots are taken.
+    pub fn register(&self, name: &'static str, budget: Option<usize>) -> Option<Tag> {
+        'retry: loop {
+            if let Some(tag) = self.find(name) {
+                return Some(tag);
+            }
+            for (i, slot) in self.slots.iter().enumerate().skip(1) {
+                let state = slot.state.load(Ordering::SeqCst);
+                if state & SLOT_KIND != SLOT_FREE
+                    || slot.state.compare_exchange(state, state | SLOT_CLAIMED, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+                    continue;
+                }
+                slot.name_ptr.store(name.as_ptr() as *mut u8, Ordering::SeqCst);
+                slot.name_len.store(name.len(), Ordering::SeqCst);
+                slot.state.store(state | SLOT_NAMED, Ordering::SeqCst);
+                // a racing register of the same name sees this slot or we see its, or both
+                let rival = self.slots.iter().enumerate().skip(1).find(|&(j, other)| j != i && other.name() == Some(name));
+                if let Some((j, _)) = rival.filter(|&(j, _)| j < i) {
+                    slot.state.store((state & !SLOT_KIND) + SLOT_GENERATION, Ordering::SeqCst);
+                    while self.slots[j].kind() == SLOT_NAMED {
+                        core::hint::spin_loop();
+                    }
+                    continue 'retry;
+                }
+                slot.budget.store(budget.unwrap_or(UNLIMITED), Ordering::Relaxed);
+                slot.state.store(state | SLOT_READY, Ordering::SeqCst);
+                return Some(Tag(i as u8));
+            }
+            return None;
+        }
+    }
+
+    pub fn find(&self, name: &str) -> Option<Tag> {
+        self.slots.iter().position(|slot| slot.kind() == SLOT_READY && slot.name() == Some(name))
+            .map(|i| Tag(i as u8))
+    }
+
+    pub fn set_budget(&self, tag: Tag, budget: Option<usize>) {
+        if let Some(slot) = self.slot(tag) {
+            slot.budget.store(budget.unwrap_or(UNLIMITED), Ordering::Relaxed);
+        }
+    }
+
+    // reserves bytes against the tag's budget, false means the allocation has to fail
+    pub fn charge(&self, tag: Tag, bytes: usize) -> bool {
+        let slot = match self.slot(tag) {
+            Some(slot) => slot,
+            None => return false,
+        };
+        let budget = slot.budget.load(Ordering::Relaxed);
+        let mut live = slot.live.load(Ordering::Relaxed);
+        loop {
+            let new_live = match live.checked_add(bytes) {
+                Some(new_live) if new_live <= budget => new_live,
+                _ => {
+                    slot.failures.fetch_add(1, Ordering::Relaxed);
+                    return false;
+                }
+            };
+            match slot.live.compare_exchange_weak(live, new_live, Ordering::AcqRel, Ordering::Relaxed) {
+                Ok(_) => {
+                    slot.peak.fetch_max(new_live, Ordering::Relaxed);
+                    slot.allocs.fetch_add(1, Ordering::Relaxed);
+                    return true;
+                },
+                Err(current) => {
+                    live = current;
+                    core::hint::spin_loop();
+                }
+            }
+        }
+    }
+
+    pub fn uncharge(&self, tag: Tag, bytes: usize) {
+        if let Some(slot) = self.slot(tag) {
+            slot.live.fetch_sub(bytes, Ordering::AcqRel);
+        }
+    }
+
+    pub fn stats(&self, tag: Tag) -> Option<TagStats> {
+        let slot = self.slot(tag)?;
+        let budget = slot.budget.load(Ordering::Relaxed);
+        Some(TagStats {
+            tag,
+            name: slot.name().unwrap_or(""),
+            live_bytes: slot.live.load(Ordering::Relaxed),
+            peak_bytes: slot.peak.load(Ordering::Relaxed),
+            budget: if budget == UNLIMITED { None } else { Some(budget) },
+            allocs: slot.allocs.load(Ordering::Relaxed),
+            failures: slot.failures.load(Ordering::Relaxed)
+        })
+    }
+
+    // every registered tag, the untagged bucket first
+    pub fn iter(&self) -> impl Iterator<Item = TagStats> + '_ {
+        (0..MAX_TAGS).filter_map(|i| self.stats(Tag(i as u8)))
+    }
+
+    fn slot(&self, tag: Tag) -> Option<&TagSlot> {
+        let slot = self.slots.get(tag.index())?;
+        if slot.kind() == SLOT_READY { Some(slot) } else { None }
+    }
+}
+
+impl Default for TagTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// scoped thread local tag, every allocation the thread makes through an accounting
+// CompositeAllocator is charged to it until the scope is dropped. scopes nest.
+#[cfg(feature = "std")]
+std::thread_local! {
+    static CURRENT_TAG: core::cell::Cell<u8> = const { core::cell::Cell::new(0) };
+}
+
+#[cfg(feature = "std")]
+pub struct TagScope {
+    prev: u8,
+    // the scope restores this thread's tag, it must not move to another thread
+    _not_send: core::marker::PhantomData<*const ()>
+}
+
+#[cfg(feature = "std")]
+impl TagScope {
+    pub fn enter(tag: Tag) -> Self {
+        let prev = CURRENT_TAG.try_with(|current| current.replace(tag.0)).unwrap_or(0);
+        Self { prev, _not_send: core::marker::PhantomData }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Drop for TagScope {
+    fn drop(&mut self) {
+        let _ = CURRENT_TAG.try_with(|current| current.set(self.prev));
+    }
+}
+
+// the calling thread's tag, untagged outside of any scope or without std
+pub fn current_tag() -> Tag {
+    #[cfg(feature = "std")]
+    {
+        Tag(CURRENT_TAG.try_with(|current| current.get()).unwrap_or(0))
+    }
+    #[cfg(not(feature = "std"))]
+    {
+        Tag::UNTAGGED
+    }
+}
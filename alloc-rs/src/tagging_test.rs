@@ -0,0 +1,81 @@
+use core::alloc::{GlobalAlloc, Layout};
+
+use crate::composite::CompositeAllocator;
+use crate::tagging::{self, Tag, TagScope, TagTable};
+
+#[test]
+pub fn tag_table_budget_test() {
+    let table = TagTable::new();
+    let arp = table.register("arp", Some(100)).expect("should register arp");
+    assert_eq!(table.register("arp", None), Some(arp), "same name hands back the same tag");
+    assert_eq!(table.find("arp"), Some(arp));
+
+    assert!(table.charge(arp, 60));
+    assert!(!table.charge(arp, 60), "over budget");
+    table.uncharge(arp, 60);
+    assert!(table.charge(arp, 90));
+    let stats = table.stats(arp).unwrap();
+    assert_eq!((stats.live_bytes, stats.peak_bytes, stats.allocs, stats.failures), (90, 90, 2, 1));
+
+    table.set_budget(arp, None);
+    assert!(table.charge(arp, 1000));
+    assert_eq!(table.iter().map(|s| s.name).collect::<Vec<_>>(), ["untagged", "arp"]);
+
+    // threads racing to register the same names all end up with one tag per name
+    for _ in 0..50 {
+        let table = TagTable::new();
+        let table = &table;
+        let tags: Vec<_> = std::thread::scope(|s| {
+            let racers: Vec<_> = (0..4).map(|i| s.spawn(move || {
+                let names = if i % 2 == 0 { ["rx", "tx"] } else { ["tx", "rx"] };
+                names.map(|name| table.register(name, None).unwrap())
+            })).collect();
+            racers.into_iter().map(|racer| racer.join().unwrap()).collect()
+        });
+        let rx = table.find("rx").unwrap();
+        let tx = table.find("tx").unwrap();
+        assert!(tags.iter().enumerate().all(|(i, &tag)| tag == if i % 2 == 0 { [rx, tx] } else { [tx, rx] }));
+        assert_eq!(table.iter().count(), 3);
+    }
+}
+
+#[test]
+pub fn composite_tagged_accounting_test() {
+    static mut HEAP: [u8; 64 * 1024] = [0u8; 64 * 1024];
+    static ALLOC: CompositeAllocator = unsafe { CompositeAllocator::builder().heap(&raw mut HEAP) }
+        .slab_region_bytes(16 * 1024)
+        .accounting(true)
+        .build();
+
+    let rx = ALLOC.register_tag("rx-buffers", Some(1024)).unwrap();
+    let config = ALLOC.register_tag("config", None).unwrap();
+
+    let buf = Layout::from_size_align(512, 16).unwrap();
+    let a = ALLOC.alloc_tagged(buf, rx);
+    let b = ALLOC.alloc_tagged(buf, rx);
+    assert!(!a.is_null() && !b.is_null());
+    assert_eq!(a as usize % 16, 0);
+    assert!(ALLOC.alloc_tagged(buf, rx).is_null(), "rx-buffers is at its budget");
+    assert_eq!(ALLOC.tag_stats(rx).unwrap().failures, 1);
+
+    // the scoped tag applies to the regular GlobalAlloc path
+    let small = Layout::new::<u64>();
+    let c = {
+        let _scope = TagScope::enter(config);
+        assert_eq!(tagging::current_tag(), config);
+        unsafe { ALLOC.alloc(small) }
+    };
+    assert_eq!(tagging::current_tag(), Tag::UNTAGGED);
+    assert_eq!(ALLOC.tag_stats(config).unwrap().live_bytes, 8);
+
+    // dealloc finds the tag in the header, whatever scope it runs in
+    unsafe {
+        ALLOC.dealloc(a, buf);
+        ALLOC.dealloc(c, small);
+    }
+    let rx_stats = ALLOC.tag_stats(rx).unwrap();
+    assert_eq!((rx_stats.live_bytes, rx_stats.peak_bytes), (512, 1024));
+    assert_eq!(ALLOC.tag_stats(config).unwrap().live_bytes, 0);
+    unsafe { ALLOC.dealloc(b, buf) };
+    assert_eq!(ALLOC.tag_stats(rx).unwrap().live_bytes, 0);
+}
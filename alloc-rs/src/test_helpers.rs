@@ -0,0 +1,12 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+// bumps its counter when dropped, for the tests that check destructors run exactly once.
+// the counter is a static so the value is 'static and Send, like the arenas ask of the values
+// whose destructors they run later. give every test its own counter, tests run in parallel
+pub struct CountsDrops(pub &'static AtomicUsize);
+
+impl Drop for CountsDrops {
+    fn drop(&mut self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,62 @@
+#![cfg(not(loom))]
+
+use alloc_rs::composite::GLOBAL_ALLOC;
+
+// stand-in for per packet work: a small decode buffer and a boxed header, all dropped at the end
+fn process_packet(seq: u32) -> u32 {
+    let frame: Vec<u8> = (0..42u8).map(|b| b ^ seq as u8).collect();
+    let header = Box::new([frame[12], frame[13], frame[20], frame[21]]);
+    header.iter().map(|&b| b as u32).sum::<u32>() + frame.len() as u32
+}
+
+// both checks share one test so nothing else allocates on the heap between the snapshots
+#[test]
+fn heap_returns_to_same_state_after_packets() {
+    // warm up once so lazily initialised runtime state is not counted
+    process_packet(0);
+
+    // the snapshots list into buffers of their own, taking one allocates nothing
+    let (mut before_buf, mut after_buf) = ([0usize; 2048], [0usize; 2048]);
+    let before = GLOBAL_ALLOC.snapshot(&mut before_buf);
+    assert!(!before.truncated);
+    let mut checksum = 0u32;
+    for seq in 0..1000 {
+        checksum = checksum.wrapping_add(process_packet(seq));
+    }
+    let after = GLOBAL_ALLOC.snapshot(&mut after_buf);
+    let diff = before.diff(&after);
+    assert!(diff.is_clean(), "processing packets leaked:\n{diff}");
+    assert!(checksum > 0);
+
+    let before = GLOBAL_ALLOC.snapshot(&mut before_buf);
+    let leaked = Box::leak(Box::new(7u64)) as *mut u64 as usize;
+    let after = GLOBAL_ALLOC.snapshot(&mut after_buf);
+    let diff = before.diff(&after);
+    assert!(!diff.is_clean());
+    assert_eq!(diff.leaked_blocks(), 1);
+    assert!(diff.classes.iter().any(|class| class.allocated.contains(&leaked)));
+
+    // past the largest class, small enough to come out of the thread's bump chunk
+    let before = GLOBAL_ALLOC.snapshot(&mut before_buf);
+    let leaked = Box::leak(vec![0u8; 200].into_boxed_slice());
+    let after = GLOBAL_ALLOC.snapshot(&mut after_buf);
+    let diff = before.diff(&after);
+    assert!(!diff.is_clean());
+    assert_eq!((diff.leaked_blocks(), diff.bump_growth), (0, leaked.len()));
+
+    // freed bump memory stops counting even though it isn't reused
+    let before = GLOBAL_ALLOC.snapshot(&mut before_buf);
+    drop(vec![0u8; 300]);
+    let after = GLOBAL_ALLOC.snapshot(&mut after_buf);
+    assert!(before.diff(&after).is_clean());
+
+    // a mapping of its own
+    let before = GLOBAL_ALLOC.snapshot(&mut before_buf);
+    let leaked = Box::leak(vec![0u8; 1 << 20].into_boxed_slice());
+    let after = GLOBAL_ALLOC.snapshot(&mut after_buf);
+    let diff = before.diff(&after);
+    assert!(!diff.is_clean());
+    assert_eq!(diff.mmap_new_mappings, 1);
+    assert!(diff.mmap_growth >= leaked.len());
+    assert_eq!(diff.bump_growth, 0);
+}
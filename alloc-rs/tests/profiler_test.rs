@@ -0,0 +1,29 @@
+#![cfg(all(not(loom), target_os = "linux"))]
+
+use alloc_rs::profiler::{HEAP_PROFILER, ProfileKind};
+
+#[inline(never)]
+fn packet() -> Box<[u8; 48]> {
+    Box::new(core::hint::black_box([7u8; 48]))
+}
+
+// goes through GLOBAL_ALLOC's hooks, its own binary so no other test allocates meanwhile
+#[test]
+fn profiler_sees_global_allocations_and_frees() {
+    // an interval of one byte samples every allocation with weight ~1
+    HEAP_PROFILER.start(1);
+    let mut kept = Vec::with_capacity(8);
+    for _ in 0..8 {
+        kept.push(packet());
+        drop(packet());
+    }
+    HEAP_PROFILER.stop();
+
+    let objects = |kind| HEAP_PROFILER.profile(kind).iter().map(|s| s.objects).sum::<u64>();
+    let (live, allocated) = (objects(ProfileKind::Live), objects(ProfileKind::Allocations));
+    // the 8 kept boxes and the vec are live, the 8 dropped boxes were released by dealloc
+    assert!(live >= 9, "live {live}");
+    assert!(allocated >= 17 && live + 8 <= allocated, "live {live} allocated {allocated}");
+    assert_eq!(kept.iter().map(|b| b[0] as usize).sum::<usize>(), 56);
+    assert_eq!(HEAP_PROFILER.dropped_samples(), 0);
+}